@@ -1,97 +1,162 @@
 use wgpu_prefix_sum_demo::block_blelloch_scan::BlockBlellochGpuContext;
-use wgpu_prefix_sum_demo::cpu_prefix_scan::cpu_prefix_sum;
+use wgpu_prefix_sum_demo::chunked_scan::ChunkedScan;
+use wgpu_prefix_sum_demo::cpu_prefix_scan::CpuPrefixScan;
+use wgpu_prefix_sum_demo::decoupled_lookback_scan::DecoupledLookbackGpuContext;
 use wgpu_prefix_sum_demo::global_blelloch_scan::GlobalBlellochGpuContext;
+use wgpu_prefix_sum_demo::gpu_options::GpuOptions;
 use wgpu_prefix_sum_demo::hillis_steele_scan::HillisSteeleGpuContext;
+use wgpu_prefix_sum_demo::prefix_scan::{PrefixScan, ScanConfig, SubgroupSizes};
+use wgpu_prefix_sum_demo::reduce_then_scan::ReduceThenScanGpuContext;
 use wgpu_prefix_sum_demo::subgroup_scan::SubgroupScanGpuContext;
 
-use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use criterion::{BatchSize, BenchmarkId, Criterion, criterion_group, criterion_main};
 
-fn bench_prefix_scan(c: &mut Criterion) {
-    let mut group = c.benchmark_group("Prefix scan comparison");
-
-    let sizes: Vec<usize> = (1..=29).map(|p| 1usize << p).collect();
-
-    for &n in &sizes {
-        let data = vec![1u32; n];
-
-        let hillis = pollster::block_on(HillisSteeleGpuContext::new(n)).unwrap();
-        hillis.upload_data(&data);
+/// Element counts of every benchmark, from 2 to 2^29.
+fn sizes() -> Vec<usize> {
+    (1..=29).map(|p| 1usize << p).collect()
+}
 
-        let global = pollster::block_on(GlobalBlellochGpuContext::new(n)).unwrap();
-        global.upload_data(&data);
-        
-        let blocked = pollster::block_on(BlockBlellochGpuContext::new(n)).unwrap();
-        blocked.upload_data(&data);
-        
-        let subgroup = pollster::block_on(SubgroupScanGpuContext::new(n)).unwrap();
-        subgroup.upload_data(&data);
+/// The device all the benchmarks run on, with subgroup operations if the adapter has them, and
+/// the default config with the subgroup sizes of the adapter.
+fn create_device() -> (wgpu::Device, wgpu::Queue, ScanConfig) {
+    pollster::block_on(async {
+        let adapter = GpuOptions::default().request_adapter().await.unwrap();
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: Some("bench device"),
+                required_features: wgpu::Features::SUBGROUP & adapter.features(),
+                required_limits: adapter.limits(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let mut config = ScanConfig::default();
+        if device.features().contains(wgpu::Features::SUBGROUP) {
+            config = config.with_subgroup_sizes(SubgroupSizes::of_adapter(&adapter.get_info()));
+        }
+        (device, queue, config)
+    })
+}
 
-        group.bench_with_input(BenchmarkId::new("CPU Sequential", n), &n, |b, &_n| {
-            b.iter(|| {
-                let v = cpu_prefix_sum(&data);
-                std::hint::black_box(v);
-            });
-        });
+/// Benchmark a context created with `config` for the largest size, on the lengths up to it.
+/// The context is dropped before the next group creates its own.
+fn bench_context<C: PrefixScan>(
+    c: &mut Criterion,
+    name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    config: &ScanConfig,
+) {
+    let sizes = sizes();
+    let capacity = *sizes.last().unwrap();
+    let ctx = match C::from_device(device, queue, capacity, config) {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            eprintln!("Skipping {name}: {e}");
+            return;
+        }
+    };
+    let data = vec![1u32; capacity];
 
-        group.bench_with_input(BenchmarkId::new("GPU Hillis-Steele", n), &n, |b, &_n| {
+    let mut group = c.benchmark_group(name);
+    for &n in &sizes {
+        ctx.upload_data(&data[..n]).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, &_n| {
             b.iter_batched(
                 || {
-                    hillis.run_prefix_scan();
+                    // Finish the previous scan, which the CPU context only runs when flushed
+                    ctx.wait_idle().unwrap();
+                    ctx.run_prefix_scan();
                 },
                 |_| {
-                    hillis.wait_idle().unwrap();
+                    ctx.wait_idle().unwrap();
                 },
                 BatchSize::PerIteration,
             )
         });
+    }
+    group.finish();
+}
 
-        group.bench_with_input(BenchmarkId::new("GPU Global Blelloch", n), &n, |b, &_n| {
-            b.iter_batched(
-                || {
-                    let mut enc = global.get_command_encoder();
-                    global.encode_up_sweep(&mut enc);
-                    global.encode_set_last_zero(&mut enc);
-                    global.encode_down_sweep(&mut enc);
-                    global.submit(enc);
-                },
-                |_| {
-                    global.wait_idle().unwrap();
-                },
-                BatchSize::PerIteration,
-            )
-        });
-    
-        group.bench_with_input(BenchmarkId::new("GPU Blocked Blelloch", n), &n, |b, &_n| {
-            b.iter_batched(
-                || {
-                    let mut enc = blocked.get_command_encoder();
-                    blocked.encode_scan(&mut enc);
-                    blocked.submit(enc);
-                },
-                |_| {
-                    blocked.wait_idle().unwrap();
-                },
-                BatchSize::PerIteration,
-            )
-        });
-        
-        group.bench_with_input(BenchmarkId::new("GPU Subgroup", n), &n, |b, &_n| {
+/// Benchmark streaming the input through the device in chunks of 2^22 elements, including the
+/// uploads and readbacks the other benchmarks leave out.
+fn bench_chunked(c: &mut Criterion, device: &wgpu::Device, queue: &wgpu::Queue) {
+    let chunked =
+        ChunkedScan::<u32>::from_device(device, queue, 1 << 22, &ScanConfig::default()).unwrap();
+    let mut group = c.benchmark_group("GPU Chunked (2^22 per chunk)");
+    for n in sizes() {
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, &n| {
             b.iter_batched(
-                || {
-                    let mut enc = subgroup.get_command_encoder();
-                    subgroup.encode_scan(&mut enc);
-                    subgroup.submit(enc);
-                },
-                |_| {
-                    subgroup.wait_idle().unwrap();
+                || vec![1u32; n],
+                |mut data| {
+                    chunked.scan_in_place(&mut data).unwrap();
+                    data
                 },
                 BatchSize::PerIteration,
             )
         });
     }
-
     group.finish();
 }
 
+fn bench_prefix_scan(c: &mut Criterion) {
+    let (device, queue, config) = create_device();
+    let (device, queue) = (&device, &queue);
+    let elems_per_thread = config.clone().with_elems_per_thread(4);
+    let fused_carry = config.clone().with_fused_carry(true);
+    let bank_conflict_free = config.clone().with_bank_conflict_free(true);
+
+    bench_context::<CpuPrefixScan>(c, "CPU Sequential", device, queue, &config);
+    bench_context::<HillisSteeleGpuContext>(c, "GPU Hillis-Steele", device, queue, &config);
+    bench_context::<GlobalBlellochGpuContext>(c, "GPU Global Blelloch", device, queue, &config);
+    bench_context::<BlockBlellochGpuContext>(c, "GPU Blocked Blelloch", device, queue, &config);
+    bench_context::<BlockBlellochGpuContext>(
+        c,
+        "GPU Blocked Blelloch (4 per thread)",
+        device,
+        queue,
+        &elems_per_thread,
+    );
+    bench_context::<BlockBlellochGpuContext>(
+        c,
+        "GPU Blocked Blelloch (fused carry)",
+        device,
+        queue,
+        &fused_carry,
+    );
+    bench_context::<BlockBlellochGpuContext>(
+        c,
+        "GPU Blocked Blelloch (conflict-free)",
+        device,
+        queue,
+        &bank_conflict_free,
+    );
+    bench_context::<ReduceThenScanGpuContext>(c, "GPU Reduce-then-scan", device, queue, &config);
+    bench_context::<DecoupledLookbackGpuContext>(
+        c,
+        "GPU Decoupled Look-back",
+        device,
+        queue,
+        &config,
+    );
+    // Adapters without subgroup support run the emulated subgroup operations
+    bench_context::<SubgroupScanGpuContext>(c, "GPU Subgroup", device, queue, &config);
+    bench_context::<SubgroupScanGpuContext>(
+        c,
+        "GPU Subgroup (4 per thread)",
+        device,
+        queue,
+        &elems_per_thread,
+    );
+    bench_context::<SubgroupScanGpuContext>(
+        c,
+        "GPU Subgroup (fused carry)",
+        device,
+        queue,
+        &fused_carry,
+    );
+    bench_chunked(c, device, queue);
+}
+
 criterion_group!(benches, bench_prefix_scan);
 criterion_main!(benches);
@@ -1,9 +1,34 @@
-const WG_SIZE: u32 = 64u;
+// `Elem`, `identity()` and `combine(a, b)` of the scan operator, and the `Chunk` the elements are
+// loaded and stored in, are declared by the prelude which is prepended on the host.
+// Invocations per workgroup, a power of two
+override WG_SIZE: u32 = 64u;
+const MAX_ELEMS_PER_THREAD: u32 = 16u;
+// Scan all levels inclusively instead of exclusively
+override INCLUSIVE: bool = false;
+// Consecutive elements each invocation scans in registers, a multiple of `CHUNK_LEN`
+override ELEMS_PER_THREAD: u32 = 1u;
+// Pad `local_data` so the power-of-two strides of the sweeps hit different banks
+override CONFLICT_FREE: bool = false;
+// Shared memory is assumed to have 2^LOG_NUM_BANKS banks
+const LOG_NUM_BANKS: u32 = 5u;
 
-@group(0) @binding(0) var<storage, read_write> global_data: array<u32>;
-@group(0) @binding(1) var<storage, read_write> block_sum: array<u32>;
+struct Level {
+    len: u32,
+};
 
-var<workgroup> local_data: array<u32, 64u>;
+// Padded to a whole number of chunks
+@group(0) @binding(0) var<storage, read_write> global_data: array<Chunk>;
+@group(0) @binding(1) var<storage, read_write> block_sum: array<Elem>;
+// Number of elements on this level, the buffers may be longer
+@group(0) @binding(2) var<uniform> level: Level;
+// Input of the out-of-place scan, only read by the first level
+@group(0) @binding(3) var<storage, read> input_data: array<Chunk>;
+
+// Totals of the elements of each invocation, accessed through `bank_idx`. The padding adds one
+// element per `2^LOG_NUM_BANKS` elements.
+var<workgroup> local_data: array<Elem, WG_SIZE + (WG_SIZE >> LOG_NUM_BANKS)>;
+// Elements of this invocation
+var<private> values: array<Elem, MAX_ELEMS_PER_THREAD>;
 
 fn linearize_workgroup_id(wid: vec3<u32>, num_wg: vec3<u32>) -> u32 {
     // linear = x + y*X + z*(X*Y)
@@ -11,26 +36,119 @@ fn linearize_workgroup_id(wid: vec3<u32>, num_wg: vec3<u32>) -> u32 {
 }
 
 /**
- * Get local and global index.
+ * Get local index and the global index of the first element of the invocation.
  */
 fn get_indices(lid: vec3<u32>, wid: vec3<u32>, num_wg: vec3<u32>) -> array<u32, 2> {
     let local_idx = lid.x;
     let wg_linear = linearize_workgroup_id(wid, num_wg);
-    let block_base = wg_linear * WG_SIZE;
-    let global_idx = block_base + local_idx;
+    let block_base = wg_linear * WG_SIZE * ELEMS_PER_THREAD;
+    let global_idx = block_base + local_idx * ELEMS_PER_THREAD;
     return array<u32, 2>(local_idx, global_idx);
 }
 
 /**
- * Load data from the storage to the workgroup variable.
+ * Index of element `i` in `local_data`. With `CONFLICT_FREE`, one padding element is skipped
+ * every `2^LOG_NUM_BANKS` elements, so elements a multiple of the bank count apart are spread
+ * over different banks.
  */
-fn copy_global_data_to_local(n: u32, local_idx: u32, global_idx: u32) {
-    var global_val = 0u;
-    if (global_idx < n) {
-        global_val = global_data[global_idx];
+fn bank_idx(i: u32) -> u32 {
+    if (CONFLICT_FREE) {
+        return i + (i >> LOG_NUM_BANKS);
+    }
+    return i;
+}
+
+/**
+ * Load the elements of the data scanned in place into `values`, the identity past the end.
+ */
+fn load_global_data(n: u32, global_idx: u32) {
+    for (var c = 0u; c < ELEMS_PER_THREAD / CHUNK_LEN; c = c + 1u) {
+        let base = global_idx + c * CHUNK_LEN;
+        var chunk: Chunk;
+        if (base < n) {
+            chunk = global_data[base / CHUNK_LEN];
+        }
+        for (var i = 0u; i < CHUNK_LEN; i = i + 1u) {
+            var v = identity();
+            if (base + i < n) {
+                v = chunk_get(chunk, i);
+            }
+            values[c * CHUNK_LEN + i] = v;
+        }
+    }
+}
+
+/**
+ * Load the elements of the input of the out-of-place scan into `values`, the identity past the
+ * end.
+ */
+fn load_input_data(n: u32, global_idx: u32) {
+    for (var c = 0u; c < ELEMS_PER_THREAD / CHUNK_LEN; c = c + 1u) {
+        let base = global_idx + c * CHUNK_LEN;
+        var chunk: Chunk;
+        if (base < n) {
+            chunk = input_data[base / CHUNK_LEN];
+        }
+        for (var i = 0u; i < CHUNK_LEN; i = i + 1u) {
+            var v = identity();
+            if (base + i < n) {
+                v = chunk_get(chunk, i);
+            }
+            values[c * CHUNK_LEN + i] = v;
+        }
+    }
+}
+
+/**
+ * Write the scanned `values` out to the global storage.
+ */
+fn store_global_data(n: u32, global_idx: u32) {
+    for (var c = 0u; c < ELEMS_PER_THREAD / CHUNK_LEN; c = c + 1u) {
+        let base = global_idx + c * CHUNK_LEN;
+        if (base >= n) {
+            break;
+        }
+        // The elements of the chunk past the end are written back unchanged
+        var chunk: Chunk;
+        if (base + CHUNK_LEN > n) {
+            chunk = global_data[base / CHUNK_LEN];
+        }
+        for (var i = 0u; i < CHUNK_LEN; i = i + 1u) {
+            if (base + i < n) {
+                chunk_set(&chunk, i, values[c * CHUNK_LEN + i]);
+            }
+        }
+        global_data[base / CHUNK_LEN] = chunk;
+    }
+}
+
+/**
+ * Store the total of the `values` of the invocation for the workgroup scan.
+ */
+fn reduce_values(local_idx: u32) {
+    var total = identity();
+    for (var i = 0u; i < ELEMS_PER_THREAD; i = i + 1u) {
+        total = combine(total, values[i]);
+    }
+    local_data[bank_idx(local_idx)] = total;
+}
+
+/**
+ * Scan the `values` of the invocation serially, starting from the combination of the elements
+ * before them in the block.
+ */
+fn scan_values(prefix: Elem) {
+    var running = prefix;
+    for (var i = 0u; i < ELEMS_PER_THREAD; i = i + 1u) {
+        let v = values[i];
+        if (INCLUSIVE) {
+            running = combine(running, v);
+            values[i] = running;
+        } else {
+            values[i] = running;
+            running = combine(running, v);
+        }
     }
-    local_data[local_idx] = global_val;
-    workgroupBarrier();
 }
 
 /**
@@ -47,7 +165,8 @@ fn up_sweep(local_idx: u32) {
             // intra-warp branch divergence compared to a strided predicate.
             let target_idx = (local_idx + 1u) * step - 1u;
             // target_idx - (step >> 1u) -> index of the sum target (step/2 back)
-            local_data[target_idx] += local_data[target_idx - (step >> 1u)];
+            let target_slot = bank_idx(target_idx);
+            local_data[target_slot] = combine(local_data[bank_idx(target_idx - (step >> 1u))], local_data[target_slot]);
         }
         workgroupBarrier();
         step = step << 1u;
@@ -64,71 +183,216 @@ fn down_sweep(local_idx: u32) {
      if (local_idx < num_targets) {
          let target_idx = (local_idx + 1u) * step - 1u;
          let prev_idx = target_idx - (step >> 1u);
-         let prev_val = local_data[prev_idx];
-         local_data[prev_idx] = local_data[target_idx];
-         local_data[target_idx] += prev_val;
+         let prev_slot = bank_idx(prev_idx);
+         let target_slot = bank_idx(target_idx);
+         let prev_val = local_data[prev_slot];
+         local_data[prev_slot] = local_data[target_slot];
+         local_data[target_slot] = combine(local_data[target_slot], prev_val);
      }
      workgroupBarrier();
      step = step >> 1u;
     }
 }
 
-@compute @workgroup_size(WG_SIZE)
-fn block_scan_write_sum(
-    @builtin(local_invocation_id) lid: vec3<u32>,
-    @builtin(workgroup_id) wid: vec3<u32>,
-    @builtin(num_workgroups) num_wg: vec3<u32>
-) {
-    let n = arrayLength(&global_data);
-    let indices = get_indices(lid, wid, num_wg);
-    let local_idx = indices[0];
-    let global_idx = indices[1];
-    copy_global_data_to_local(n, local_idx, global_idx);
+/**
+ * Scan the block of `values` and write the block sum. The result is written to `global_data`.
+ */
+fn scan_write_sum(n: u32, local_idx: u32, global_idx: u32, wg_linear: u32) {
+    reduce_values(local_idx);
+    workgroupBarrier();
 
     up_sweep(local_idx);
 
     // write out the block sum here before overwriting with 0
-    let wg_linear = linearize_workgroup_id(wid, num_wg);
-    let n_blocks = arrayLength(&block_sum);
+    let block_len = WG_SIZE * ELEMS_PER_THREAD;
+    let n_blocks = (n + block_len - 1u) / block_len;
     if (local_idx == 0u) {
         if (wg_linear < n_blocks) {
-            block_sum[wg_linear] = local_data[WG_SIZE - 1u];
+            block_sum[wg_linear] = local_data[bank_idx(WG_SIZE - 1u)];
         }
-        local_data[WG_SIZE - 1u] = 0u;
+        local_data[bank_idx(WG_SIZE - 1u)] = identity();
+    }
+    workgroupBarrier();
+
+    down_sweep(local_idx);
+
+    scan_values(local_data[bank_idx(local_idx)]);
+    store_global_data(n, global_idx);
+}
+
+/**
+ * Scan the block of `values` which fits into one block. The result is written to `global_data`.
+ */
+fn scan_no_sum(n: u32, local_idx: u32, global_idx: u32) {
+    reduce_values(local_idx);
+    workgroupBarrier();
+
+    up_sweep(local_idx);
+
+    if (local_idx == 0u) {
+        local_data[bank_idx(WG_SIZE - 1u)] = identity();
     }
     workgroupBarrier();
 
     down_sweep(local_idx);
 
-    // write out the local scan result to the global storage
-    if (global_idx < n) {
-        global_data[global_idx] = local_data[local_idx];
+    scan_values(local_data[bank_idx(local_idx)]);
+    store_global_data(n, global_idx);
+}
+
+/**
+ * Write the sum of the block of `values` to `block_sum` without scanning the block, which is
+ * scanned once the carries are known.
+ */
+fn reduce_write_sum(n: u32, local_idx: u32, wg_linear: u32) {
+    reduce_values(local_idx);
+    workgroupBarrier();
+
+    up_sweep(local_idx);
+
+    let block_len = WG_SIZE * ELEMS_PER_THREAD;
+    let n_blocks = (n + block_len - 1u) / block_len;
+    if (local_idx == 0u && wg_linear < n_blocks) {
+        block_sum[wg_linear] = local_data[bank_idx(WG_SIZE - 1u)];
     }
 }
 
+/**
+ * Carry of the blocks before `wg_linear` in the already scanned `block_sum`.
+ */
+fn block_carry(wg_linear: u32) -> Elem {
+    // The inclusive scan of the block sums already contains the current block,
+    // so the carry is the previous entry and the first block has none.
+    if (!INCLUSIVE) {
+        return block_sum[wg_linear];
+    }
+    if (wg_linear == 0u) {
+        return identity();
+    }
+    return block_sum[wg_linear - 1u];
+}
+
+/**
+ * Scan the block of `values`, starting from the carry of the blocks before it in the already
+ * scanned `block_sum`. The result is written to `global_data`.
+ */
+fn scan_with_carry(n: u32, local_idx: u32, global_idx: u32, wg_linear: u32) {
+    let carry = block_carry(wg_linear);
+
+    reduce_values(local_idx);
+    workgroupBarrier();
+
+    up_sweep(local_idx);
+
+    if (local_idx == 0u) {
+        local_data[bank_idx(WG_SIZE - 1u)] = identity();
+    }
+    workgroupBarrier();
+
+    down_sweep(local_idx);
+
+    scan_values(combine(carry, local_data[bank_idx(local_idx)]));
+    store_global_data(n, global_idx);
+}
+
+@compute @workgroup_size(WG_SIZE)
+fn block_scan_write_sum(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>
+) {
+    let n = level.len;
+    let indices = get_indices(lid, wid, num_wg);
+    let wg_linear = linearize_workgroup_id(wid, num_wg);
+    load_global_data(n, indices[1]);
+    scan_write_sum(n, indices[0], indices[1], wg_linear);
+}
+
+@compute @workgroup_size(WG_SIZE)
+fn block_scan_write_sum_from_input(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>
+) {
+    let n = level.len;
+    let indices = get_indices(lid, wid, num_wg);
+    let wg_linear = linearize_workgroup_id(wid, num_wg);
+    load_input_data(n, indices[1]);
+    scan_write_sum(n, indices[0], indices[1], wg_linear);
+}
+
 @compute @workgroup_size(WG_SIZE)
 fn block_scan_no_sum(
     @builtin(local_invocation_id) lid: vec3<u32>,
     @builtin(workgroup_id) wid: vec3<u32>,
     @builtin(num_workgroups) num_wg: vec3<u32>
 ) {
-     let n = arrayLength(&global_data);
-     let indices = get_indices(lid, wid, num_wg);
-     let local_idx = indices[0];
-     let global_idx = indices[1];
-     copy_global_data_to_local(n, local_idx, global_idx);
+    let n = level.len;
+    let indices = get_indices(lid, wid, num_wg);
+    load_global_data(n, indices[1]);
+    scan_no_sum(n, indices[0], indices[1]);
+}
+
+@compute @workgroup_size(WG_SIZE)
+fn block_scan_no_sum_from_input(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>
+) {
+    let n = level.len;
+    let indices = get_indices(lid, wid, num_wg);
+    load_input_data(n, indices[1]);
+    scan_no_sum(n, indices[0], indices[1]);
+}
 
-     up_sweep(local_idx);
+@compute @workgroup_size(WG_SIZE)
+fn block_reduce(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>
+) {
+    let n = level.len;
+    let indices = get_indices(lid, wid, num_wg);
+    let wg_linear = linearize_workgroup_id(wid, num_wg);
+    load_global_data(n, indices[1]);
+    reduce_write_sum(n, indices[0], wg_linear);
+}
 
-     if (local_idx == 0u) {
-         local_data[WG_SIZE - 1u] = 0u;
-     }
-     workgroupBarrier();
+@compute @workgroup_size(WG_SIZE)
+fn block_reduce_from_input(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>
+) {
+    let n = level.len;
+    let indices = get_indices(lid, wid, num_wg);
+    let wg_linear = linearize_workgroup_id(wid, num_wg);
+    load_input_data(n, indices[1]);
+    reduce_write_sum(n, indices[0], wg_linear);
+}
 
-     down_sweep(local_idx);
+@compute @workgroup_size(WG_SIZE)
+fn block_scan_carry(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>
+) {
+    let n = level.len;
+    let indices = get_indices(lid, wid, num_wg);
+    let wg_linear = linearize_workgroup_id(wid, num_wg);
+    load_global_data(n, indices[1]);
+    scan_with_carry(n, indices[0], indices[1], wg_linear);
+}
 
-     // write out the local scan result to the global storage
-     if (global_idx < n) {
-         global_data[global_idx] = local_data[local_idx];
-     }
-}
\ No newline at end of file
+@compute @workgroup_size(WG_SIZE)
+fn block_scan_carry_from_input(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>
+) {
+    let n = level.len;
+    let indices = get_indices(lid, wid, num_wg);
+    let wg_linear = linearize_workgroup_id(wid, num_wg);
+    load_input_data(n, indices[1]);
+    scan_with_carry(n, indices[0], indices[1], wg_linear);
+}
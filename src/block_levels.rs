@@ -0,0 +1,776 @@
+use crate::fused_carry::{FusedCarry, FusedCarryFirstLevel};
+use crate::prefix_scan::BufferRange;
+use crate::readback::{Readback, ReadbackBuffer, WorkDone};
+use crate::utils::{
+    BindGroupCache, LevelUniforms, StorageBinding, align_up, bind_ranges, check_buffer_ranges,
+    check_len, level_lens, read_buffer, split_dispatch_3d, wait_for_previous_submit,
+};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Levels of a blocked scan: every block of a level is scanned and its sum written to the level
+/// above it until a level fits into one block, then the carries are added back down.
+///
+/// Shared by the block scans whose shaders have the `block_scan_write_sum` and
+/// `block_scan_no_sum` entry points, their `_from_input` variants, and an `add_carry` entry point
+/// in the add-carry shader. The levels above the first one bind the buffers of the context. The
+/// first level binds the data being scanned, which is a range of the caller's buffer in a buffer
+/// scan, so its bind groups are created for each binding the first time it is scanned.
+pub struct BlockLevels {
+    device: wgpu::Device,
+    // The first level reads from the input in the out-of-place scan
+    pipeline_input_write_sum: wgpu::ComputePipeline,
+    pipeline_input_no_sum: wgpu::ComputePipeline,
+    pipeline_write_sum: wgpu::ComputePipeline,
+    pipeline_no_sum: wgpu::ComputePipeline,
+    pipeline_add_carry: wgpu::ComputePipeline,
+    // The levels above the first one, level `i` at index `i - 1`
+    bind_groups_write_sum: Vec<wgpu::BindGroup>,
+    bind_groups_no_sum: Vec<wgpu::BindGroup>,
+    bind_groups_add_carry: Vec<wgpu::BindGroup>,
+    fused_carry: Option<FusedCarry>,
+    first_levels: BindGroupCache<FirstLevel>,
+    data_buffers: Vec<wgpu::Buffer>,
+    level_uniforms: LevelUniforms,
+    block_len: usize,
+}
+
+/// Bind groups of the first level. The ones which also bind the level above it are `None` if the
+/// context has a single level.
+#[derive(Clone)]
+struct FirstLevel {
+    from_input: bool,
+    write_sum: Option<wgpu::BindGroup>,
+    no_sum: wgpu::BindGroup,
+    add_carry: Option<wgpu::BindGroup>,
+    fused_carry: Option<FusedCarryFirstLevel>,
+}
+
+impl BlockLevels {
+    /// Create the levels of a scan over up to `capacity` elements in blocks of `block_len`, with
+    /// the buffer of a level of `len` elements `buffer_size(len)` bytes long.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        block_scan_shader: &wgpu::ShaderModule,
+        add_carry_shader: &wgpu::ShaderModule,
+        constants: &[(&str, f64)],
+        capacity: usize,
+        block_len: usize,
+        buffer_size: impl Fn(usize) -> u64,
+        fused_carry: bool,
+    ) -> Self {
+        let create_pipeline = |label: &str, module: &wgpu::ShaderModule, entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: None,
+                module,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants,
+                    ..Default::default()
+                },
+                cache: Default::default(),
+            })
+        };
+        let pipeline_input_write_sum = create_pipeline(
+            "block_scan_write_sum_from_input pipeline",
+            block_scan_shader,
+            "block_scan_write_sum_from_input",
+        );
+        let pipeline_input_no_sum = create_pipeline(
+            "block_scan_no_sum_from_input pipeline",
+            block_scan_shader,
+            "block_scan_no_sum_from_input",
+        );
+        let pipeline_write_sum = create_pipeline(
+            "block_scan_write_sum pipeline",
+            block_scan_shader,
+            "block_scan_write_sum",
+        );
+        let pipeline_no_sum = create_pipeline(
+            "block_scan_no_sum pipeline",
+            block_scan_shader,
+            "block_scan_no_sum",
+        );
+        let pipeline_add_carry =
+            create_pipeline("add_carry pipeline", add_carry_shader, "add_carry");
+
+        // Build the buffers and bind groups of every level the longest scan needs, shorter
+        // scans use the first levels
+        let capacity_lens = level_lens(capacity, block_len);
+        let level_uniforms = LevelUniforms::new(device, queue, capacity_lens.len());
+        let data_buffers: Vec<wgpu::Buffer> = capacity_lens
+            .iter()
+            .map(|&level_len| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("block-sum"),
+                    size: buffer_size(level_len),
+                    usage: wgpu::BufferUsages::STORAGE
+                        | wgpu::BufferUsages::COPY_SRC
+                        | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            })
+            .collect();
+
+        // bind group: (prev_level -> this_level)
+        let bind_groups_write_sum = (2..data_buffers.len())
+            .map(|i| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("block-scan bind group"),
+                    layout: &pipeline_write_sum.get_bind_group_layout(0),
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: data_buffers[i - 1].as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: data_buffers[i].as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: level_uniforms.binding(i - 1),
+                        },
+                    ],
+                })
+            })
+            .collect();
+
+        // Any level can be the last one, depending on the length
+        let bind_groups_no_sum = (1..data_buffers.len())
+            .map(|i| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("block-scan bind group"),
+                    layout: &pipeline_no_sum.get_bind_group_layout(0),
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: data_buffers[i].as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: level_uniforms.binding(i),
+                        },
+                    ],
+                })
+            })
+            .collect();
+
+        // Build Add-carry bind groups, the carry of level i is added to level i - 1
+        let bind_groups_add_carry = (2..data_buffers.len())
+            .map(|i| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("add-carry bind group"),
+                    layout: &pipeline_add_carry.get_bind_group_layout(0),
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: data_buffers[i - 1].as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: data_buffers[i].as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: level_uniforms.binding(i - 1),
+                        },
+                    ],
+                })
+            })
+            .collect();
+
+        let fused_carry = fused_carry.then(|| {
+            FusedCarry::new(
+                device,
+                block_scan_shader,
+                constants,
+                &data_buffers,
+                &level_uniforms,
+            )
+        });
+
+        Self {
+            device: device.clone(),
+            pipeline_input_write_sum,
+            pipeline_input_no_sum,
+            pipeline_write_sum,
+            pipeline_no_sum,
+            pipeline_add_carry,
+            bind_groups_write_sum,
+            bind_groups_no_sum,
+            bind_groups_add_carry,
+            fused_carry,
+            first_levels: BindGroupCache::new(),
+            data_buffers,
+            level_uniforms,
+            block_len,
+        }
+    }
+
+    /// Buffer of the first level, which the context scans its own data in.
+    pub fn data_buffer(&self) -> &wgpu::Buffer {
+        &self.data_buffers[0]
+    }
+
+    /// Bind groups of the first level which scans `data`, after reading it from `input` if it is
+    /// set.
+    fn first_level(&self, data: &StorageBinding, input: Option<&StorageBinding>) -> FirstLevel {
+        self.first_levels.get_or_create(data, input, || {
+            let (pipeline_write_sum, pipeline_no_sum) = if input.is_some() {
+                (&self.pipeline_input_write_sum, &self.pipeline_input_no_sum)
+            } else {
+                (&self.pipeline_write_sum, &self.pipeline_no_sum)
+            };
+            let input_entry = input.map(|input| wgpu::BindGroupEntry {
+                binding: 3,
+                resource: input.resource(),
+            });
+            let sums = self.data_buffers.get(1);
+            let write_sum = sums.map(|sums| {
+                let entries: Vec<wgpu::BindGroupEntry> = [
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: data.resource(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: sums.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: self.level_uniforms.binding(0),
+                    },
+                ]
+                .into_iter()
+                .chain(input_entry.clone())
+                .collect();
+                self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("block-scan bind group"),
+                    layout: &pipeline_write_sum.get_bind_group_layout(0),
+                    entries: &entries,
+                })
+            });
+            let entries: Vec<wgpu::BindGroupEntry> = [
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: data.resource(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.level_uniforms.binding(0),
+                },
+            ]
+            .into_iter()
+            .chain(input_entry)
+            .collect();
+            let no_sum = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("block-scan bind group"),
+                layout: &pipeline_no_sum.get_bind_group_layout(0),
+                entries: &entries,
+            });
+            let add_carry = sums.map(|sums| {
+                self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("add-carry bind group"),
+                    layout: &self.pipeline_add_carry.get_bind_group_layout(0),
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: data.resource(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: sums.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: self.level_uniforms.binding(0),
+                        },
+                    ],
+                })
+            });
+            let fused_carry = self.fused_carry.as_ref().and_then(|fused_carry| {
+                fused_carry.first_level(
+                    &self.device,
+                    data,
+                    input,
+                    &self.data_buffers,
+                    &self.level_uniforms,
+                )
+            });
+            FirstLevel {
+                from_input: input.is_some(),
+                write_sum,
+                no_sum,
+                add_carry,
+                fused_carry,
+            }
+        })
+    }
+
+    /// Record a scan of the first `len` elements of `data` into `encoder`, reading them from
+    /// `input` first if it is set.
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        len: usize,
+        data: &StorageBinding,
+        input: Option<&StorageBinding>,
+    ) {
+        if len == 0 {
+            return;
+        }
+        let first_level = self.first_level(data, input);
+        let max_dim = self.device.limits().max_compute_workgroups_per_dimension;
+        let lens = level_lens(len, self.block_len);
+        let last_idx = lens.len() - 1;
+
+        // Each scan writes its own lengths, so scans of different lengths can share an encoder
+        self.level_uniforms.encode_write(encoder, &lens);
+
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+
+        let fused_carry = self
+            .fused_carry
+            .as_ref()
+            .zip(first_level.fused_carry.as_ref());
+        if let Some((fused_carry, fused_first_level)) = fused_carry {
+            // Only reduce the levels, the scan of the data adds the carries
+            fused_carry.encode_reduce(&mut pass, fused_first_level, &lens, self.block_len, max_dim);
+        } else {
+            // apply the scan for block sums recursively until the size of the block sums array becomes smaller than one block size
+            for (i, &level_len) in lens[..last_idx].iter().enumerate() {
+                let (pipeline, bind_group) = match (i, &first_level.write_sum) {
+                    (0, Some(bind_group)) if first_level.from_input => {
+                        (&self.pipeline_input_write_sum, bind_group)
+                    }
+                    (0, Some(bind_group)) => (&self.pipeline_write_sum, bind_group),
+                    _ => (&self.pipeline_write_sum, &self.bind_groups_write_sum[i - 1]),
+                };
+                let workgroups_needed = level_len.div_ceil(self.block_len).max(1) as u32;
+                pass.set_pipeline(pipeline);
+                pass.set_bind_group(0, bind_group, &[]);
+                let [x, y, z] = split_dispatch_3d(workgroups_needed, max_dim);
+                pass.dispatch_workgroups(x, y, z);
+            }
+        }
+
+        // The last sums also requires scan but no need to write the new block sums since it is already fitting in one block
+        let (pipeline, bind_group) = match last_idx {
+            0 if first_level.from_input => (&self.pipeline_input_no_sum, &first_level.no_sum),
+            0 => (&self.pipeline_no_sum, &first_level.no_sum),
+            _ => (
+                &self.pipeline_no_sum,
+                &self.bind_groups_no_sum[last_idx - 1],
+            ),
+        };
+        let workgroups_needed = lens[last_idx].div_ceil(self.block_len).max(1) as u32;
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        let [x, y, z] = split_dispatch_3d(workgroups_needed, max_dim);
+        pass.dispatch_workgroups(x, y, z);
+
+        if let Some((fused_carry, fused_first_level)) = fused_carry {
+            fused_carry.encode_scan(&mut pass, fused_first_level, &lens, self.block_len, max_dim);
+            return;
+        }
+
+        // add carry to the previous data
+        pass.set_pipeline(&self.pipeline_add_carry);
+        for level in (1..=last_idx).rev() {
+            let bind_group = match (level, &first_level.add_carry) {
+                (1, Some(bind_group)) => bind_group,
+                _ => &self.bind_groups_add_carry[level - 2],
+            };
+            let workgroups_needed = lens[level - 1].div_ceil(self.block_len).max(1) as u32;
+
+            pass.set_bind_group(0, bind_group, &[]);
+            let [x, y, z] = split_dispatch_3d(workgroups_needed, max_dim);
+            pass.dispatch_workgroups(x, y, z);
+        }
+    }
+}
+
+/// The state of a context built on [`BlockLevels`]: the levels, the input of the out-of-place
+/// scan, the readback buffer and the length of the next scan. The block scan contexts wrap it
+/// and get their methods from [`impl_block_context!`].
+pub struct BlockScan<T> {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    levels: BlockLevels,
+    // The first level reads from `input` in the out-of-place scan
+    input: Option<wgpu::Buffer>,
+    readback: ReadbackBuffer,
+    // Elements loaded and stored at a time, which a buffer range must consist of to be bound
+    chunk_len: usize,
+    capacity: usize,
+    len: AtomicUsize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: bytemuck::Pod> BlockScan<T> {
+    /// Create the buffers of a scan over up to `capacity` elements, which are loaded in chunks of
+    /// `chunk_len` elements and scanned in blocks of `block_len`, and the levels running the
+    /// shaders with `constants`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        block_scan_shader: &wgpu::ShaderModule,
+        add_carry_shader: &wgpu::ShaderModule,
+        constants: &[(&str, f64)],
+        capacity: usize,
+        chunk_len: usize,
+        block_len: usize,
+        out_of_place: bool,
+        fused_carry: bool,
+    ) -> Self {
+        // Whole chunks are loaded, so the buffers are padded to them
+        let padded_size = |len: usize| (align_up(len.max(1), chunk_len) * size_of::<T>()) as u64;
+
+        let input = out_of_place.then(|| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("input"),
+                size: padded_size(capacity),
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        });
+
+        let levels = BlockLevels::new(
+            device,
+            queue,
+            block_scan_shader,
+            add_carry_shader,
+            constants,
+            capacity,
+            block_len,
+            padded_size,
+            fused_carry,
+        );
+
+        let readback = ReadbackBuffer::new(device, padded_size(capacity));
+
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            levels,
+            input,
+            readback,
+            chunk_len,
+            capacity,
+            len: AtomicUsize::new(capacity),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn set_len(&self, len: usize) -> anyhow::Result<()> {
+        check_len(self.capacity, len)?;
+        self.len.store(len, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn input_buffer(&self) -> &wgpu::Buffer {
+        self.input.as_ref().unwrap_or(self.levels.data_buffer())
+    }
+
+    pub fn output_buffer(&self) -> &wgpu::Buffer {
+        self.levels.data_buffer()
+    }
+
+    pub fn upload_data(&self, input: &[T]) -> anyhow::Result<()> {
+        self.set_len(input.len())?;
+        self.queue
+            .write_buffer(self.input_buffer(), 0, bytemuck::cast_slice(input));
+        Ok(())
+    }
+
+    pub fn encode_scan(&self, encoder: &mut wgpu::CommandEncoder) {
+        let input = self.input.as_ref().map(StorageBinding::entire);
+        self.levels.encode(
+            encoder,
+            self.len(),
+            &StorageBinding::entire(self.levels.data_buffer()),
+            input.as_ref(),
+        );
+    }
+
+    pub fn encode_scan_buffer(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        input: BufferRange<'_>,
+        output: Option<BufferRange<'_>>,
+    ) -> anyhow::Result<()> {
+        let output = check_buffer_ranges::<T>(input, output)?;
+        self.set_len(input.len)?;
+        if input.len == 0 {
+            return Ok(());
+        }
+        if let Some(bindings) =
+            bind_ranges::<T>(&self.device, encoder, input, output, self.chunk_len)
+        {
+            self.levels
+                .encode(encoder, input.len, &bindings.data, bindings.input.as_ref());
+            return Ok(());
+        }
+        let byte_len = (input.len * size_of::<T>()) as u64;
+        encoder.copy_buffer_to_buffer(input.buffer, input.offset, self.input_buffer(), 0, byte_len);
+        self.encode_scan(encoder);
+        encoder.copy_buffer_to_buffer(
+            self.output_buffer(),
+            0,
+            output.buffer,
+            output.offset,
+            byte_len,
+        );
+        Ok(())
+    }
+
+    pub fn read_computed_data(&self) -> anyhow::Result<Vec<T>> {
+        read_buffer(
+            &self.device,
+            &self.queue,
+            self.output_buffer(),
+            &self.readback,
+            self.len(),
+        )
+    }
+
+    pub fn read_computed_data_async(&self) -> Readback<T> {
+        Readback::new(
+            &self.device,
+            &self.queue,
+            self.output_buffer(),
+            &self.readback,
+            self.len(),
+        )
+    }
+
+    pub fn wait_for_previous_submit(&self) -> anyhow::Result<()> {
+        wait_for_previous_submit(&self.device, &self.queue)
+    }
+
+    pub fn wait_for_previous_submit_async(&self) -> WorkDone {
+        WorkDone::new(&self.device, &self.queue)
+    }
+
+    pub fn wait_idle(&self) -> anyhow::Result<()> {
+        self.device.poll(wgpu::PollType::wait_indefinitely())?;
+        Ok(())
+    }
+
+    pub fn get_command_encoder(&self) -> wgpu::CommandEncoder {
+        self.device.create_command_encoder(&Default::default())
+    }
+
+    pub fn submit(&self, encoder: wgpu::CommandEncoder) {
+        self.queue.submit([encoder.finish()]);
+    }
+}
+
+/// Implement the public surface of a block scan context wrapping a [`BlockScan`], which is
+/// created by `$create(device, queue, capacity, config)`, and which uses the
+/// `$optional_features` when the device has them.
+macro_rules! impl_block_context {
+    ($context:ident, $optional_features:expr, $create:expr) => {
+        impl<T: bytemuck::Pod> $context<T> {
+            /// Queue the context submits its scans to.
+            pub fn queue(&self) -> &wgpu::Queue {
+                self.0.queue()
+            }
+
+            /// Maximum number of elements the context can scan.
+            pub fn capacity(&self) -> usize {
+                self.0.capacity()
+            }
+
+            /// Number of elements the next scan covers.
+            pub fn len(&self) -> usize {
+                self.0.len()
+            }
+
+            pub fn is_empty(&self) -> bool {
+                self.0.len() == 0
+            }
+
+            /// Set the number of elements the following scans cover, without touching the data.
+            pub fn set_len(&self, len: usize) -> anyhow::Result<()> {
+                self.0.set_len(len)
+            }
+
+            /// Buffer the input is uploaded to, which is the output buffer unless the scan is out
+            /// of place.
+            pub fn input_buffer(&self) -> &wgpu::Buffer {
+                self.0.input_buffer()
+            }
+
+            /// Buffer holding the result.
+            pub fn output_buffer(&self) -> &wgpu::Buffer {
+                self.0.output_buffer()
+            }
+
+            /// Upload the input data to the context and set the length to `input.len()`.
+            pub fn upload_data(&self, input: &[T]) -> anyhow::Result<()> {
+                self.0.upload_data(input)
+            }
+
+            /// Record the scan into the caller's command encoder. Nothing is submitted.
+            pub fn encode_scan(&self, encoder: &mut wgpu::CommandEncoder) {
+                self.0.encode_scan(encoder)
+            }
+
+            /// Record a scan of the elements in `input` into the caller's command encoder,
+            /// writing the result to `output`, or back over the input if it is `None`. See
+            /// [`PrefixScan::encode_scan_buffer`]($crate::prefix_scan::PrefixScan::encode_scan_buffer).
+            pub fn encode_scan_buffer(
+                &self,
+                encoder: &mut wgpu::CommandEncoder,
+                input: $crate::prefix_scan::BufferRange<'_>,
+                output: Option<$crate::prefix_scan::BufferRange<'_>>,
+            ) -> anyhow::Result<()> {
+                self.0.encode_scan_buffer(encoder, input, output)
+            }
+
+            /// Record the scan into a new command encoder and submit it.
+            pub fn run_prefix_scan(&self) {
+                let mut encoder = self.0.get_command_encoder();
+                self.0.encode_scan(&mut encoder);
+                self.0.submit(encoder);
+            }
+
+            /// Read the first [`len`](Self::len) elements of the scan result back to the CPU.
+            pub fn read_computed_data(&self) -> anyhow::Result<Vec<T>> {
+                self.0.read_computed_data()
+            }
+
+            /// Read the result back without blocking the thread, see
+            /// [`Readback`]($crate::readback::Readback).
+            pub fn read_computed_data_async(&self) -> $crate::readback::Readback<T> {
+                self.0.read_computed_data_async()
+            }
+
+            /// Block until the work submitted so far has been completed.
+            pub fn wait_for_previous_submit(&self) -> anyhow::Result<()> {
+                self.0.wait_for_previous_submit()
+            }
+
+            /// Wait for the work submitted so far without blocking the thread, see
+            /// [`WorkDone`]($crate::readback::WorkDone).
+            pub fn wait_for_previous_submit_async(&self) -> $crate::readback::WorkDone {
+                self.0.wait_for_previous_submit_async()
+            }
+
+            /// Block until the device becomes idle.
+            pub fn wait_idle(&self) -> anyhow::Result<()> {
+                self.0.wait_idle()
+            }
+
+            pub fn get_command_encoder(&self) -> wgpu::CommandEncoder {
+                self.0.get_command_encoder()
+            }
+
+            pub fn submit(&self, encoder: wgpu::CommandEncoder) {
+                self.0.submit(encoder)
+            }
+        }
+
+        impl<T: bytemuck::Pod> $crate::prefix_scan::ScanBuffers for $context<T> {
+            fn input_buffer(&self) -> &wgpu::Buffer {
+                self.0.input_buffer()
+            }
+
+            fn output_buffer(&self) -> &wgpu::Buffer {
+                self.0.output_buffer()
+            }
+        }
+
+        impl<T: $crate::scan_element::ScanElement> $crate::prefix_scan::PrefixScan<T>
+            for $context<T>
+        {
+            fn optional_features() -> wgpu::Features {
+                $optional_features
+            }
+
+            fn from_device(
+                device: &wgpu::Device,
+                queue: &wgpu::Queue,
+                capacity: usize,
+                config: &$crate::prefix_scan::ScanConfig,
+            ) -> anyhow::Result<Self> {
+                $create(device, queue, capacity, config).map(Self)
+            }
+
+            fn capacity(&self) -> usize {
+                $context::capacity(self)
+            }
+
+            fn len(&self) -> usize {
+                $context::len(self)
+            }
+
+            fn set_len(&self, len: usize) -> anyhow::Result<()> {
+                $context::set_len(self, len)
+            }
+
+            fn upload_data(&self, input: &[T]) -> anyhow::Result<()> {
+                $context::upload_data(self, input)
+            }
+
+            fn encode_scan(&self, encoder: &mut wgpu::CommandEncoder) {
+                $context::encode_scan(self, encoder)
+            }
+
+            fn encode_scan_buffer(
+                &self,
+                encoder: &mut wgpu::CommandEncoder,
+                input: $crate::prefix_scan::BufferRange<'_>,
+                output: Option<$crate::prefix_scan::BufferRange<'_>>,
+            ) -> anyhow::Result<()> {
+                $context::encode_scan_buffer(self, encoder, input, output)
+            }
+
+            fn run_prefix_scan(&self) {
+                $context::run_prefix_scan(self)
+            }
+
+            fn read_computed_data(&self) -> anyhow::Result<Vec<T>> {
+                $context::read_computed_data(self)
+            }
+
+            fn read_computed_data_async(&self) -> $crate::readback::Readback<T> {
+                $context::read_computed_data_async(self)
+            }
+
+            fn wait_for_previous_submit(&self) -> anyhow::Result<()> {
+                $context::wait_for_previous_submit(self)
+            }
+
+            fn wait_for_previous_submit_async(&self) -> $crate::readback::WorkDone {
+                $context::wait_for_previous_submit_async(self)
+            }
+
+            fn wait_idle(&self) -> anyhow::Result<()> {
+                $context::wait_idle(self)
+            }
+        }
+    };
+}
+
+pub(crate) use impl_block_context;
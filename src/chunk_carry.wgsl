@@ -0,0 +1,42 @@
+// `Elem`, `identity()` and `combine(a, b)` of the scan operator are declared by the prelude
+// which is prepended on the host.
+override INCLUSIVE: bool = true;
+// Invocations per workgroup, a power of two
+override WG_SIZE: u32 = 64u;
+
+struct Chunk {
+    len: u32,
+};
+
+// Scan result of the current chunk
+@group(0) @binding(0) var<storage, read_write> data: array<Elem>;
+// Combination of all the elements of the previous chunks
+@group(0) @binding(1) var<storage, read_write> carry: array<Elem>;
+// Number of elements in the current chunk, the buffer may be longer
+@group(0) @binding(2) var<uniform> chunk: Chunk;
+// Last input element of the current chunk, which the exclusive scan result does not cover
+@group(0) @binding(3) var<storage, read> last_input: array<Elem>;
+
+@compute @workgroup_size(WG_SIZE)
+fn add_carry(
+  @builtin(global_invocation_id) gid: vec3<u32>,
+  @builtin(num_workgroups) nwg: vec3<u32>,
+) {
+    let width = nwg.x * WG_SIZE;
+    let plane = width * nwg.y;
+    let i = gid.x + gid.y * width + gid.z * plane;
+    if (i >= chunk.len) { return; }
+
+    data[i] = combine(carry[0], data[i]);
+}
+
+// Runs after `add_carry`, so the last element already includes the previous chunks
+@compute @workgroup_size(1)
+fn update_carry() {
+    let tail = data[chunk.len - 1u];
+    if (INCLUSIVE) {
+        carry[0] = tail;
+    } else {
+        carry[0] = combine(tail, last_input[0]);
+    }
+}
@@ -0,0 +1,11 @@
+// The elements of an invocation are loaded and stored one at a time.
+alias Chunk = Elem;
+const CHUNK_LEN: u32 = 1u;
+
+fn chunk_get(c: Chunk, i: u32) -> Elem {
+    return c;
+}
+
+fn chunk_set(c: ptr<function, Chunk>, i: u32, v: Elem) {
+    *c = v;
+}
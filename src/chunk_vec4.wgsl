@@ -0,0 +1,11 @@
+// The elements of an invocation are loaded and stored four at a time.
+alias Chunk = vec4<Elem>;
+const CHUNK_LEN: u32 = 4u;
+
+fn chunk_get(c: Chunk, i: u32) -> Elem {
+    return c[i];
+}
+
+fn chunk_set(c: ptr<function, Chunk>, i: u32, v: Elem) {
+    (*c)[i] = v;
+}
@@ -0,0 +1,329 @@
+use crate::block_blelloch_scan::BlockBlellochGpuContext;
+use crate::prefix_scan::{PrefixScan, ScanBuffers, ScanConfig, ScanKind};
+use crate::readback::Readback;
+use crate::scan_element::ScanElement;
+use crate::scan_op::{check_op, wgsl_prelude};
+use crate::utils::{
+    BindGroupCache, StorageBinding, check_device, check_workgroup_size, init_wgpu, shader_source,
+    split_dispatch_3d,
+};
+use std::marker::PhantomData;
+
+const DEFAULT_WG_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ChunkUniform {
+    len: u32,
+    _pad: [u32; 3],
+}
+
+/// Scan of inputs which do not fit into the device memory, or into a single storage binding.
+///
+/// The input is streamed through the device in chunks of up to `chunk_len` elements. Each chunk
+/// is uploaded to and scanned by an inner `S` context, then the combination of all the previous
+/// chunks, which stays on the GPU, is carried into the result in the context's output buffer.
+/// The result is read back through the context while the next chunk is already being scanned, so
+/// the device memory stays bounded by the chunk length no matter how long the input is.
+pub struct ChunkedScan<
+    T: ScanElement = u32,
+    S: PrefixScan<T> + ScanBuffers = BlockBlellochGpuContext<T>,
+> {
+    device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    scan: S,
+    kind: ScanKind,
+    wg_size: u32,
+    add_carry_pipeline: wgpu::ComputePipeline,
+    update_carry_pipeline: wgpu::ComputePipeline,
+    // Bind groups of the output buffers of the context, which may depend on the length
+    carry_bind_groups: BindGroupCache<CarryBindGroups>,
+    carry: wgpu::Buffer,
+    last_input: wgpu::Buffer,
+    uniform: wgpu::Buffer,
+    chunk_len: usize,
+    _marker: PhantomData<T>,
+}
+
+#[derive(Clone)]
+struct CarryBindGroups {
+    add_carry: wgpu::BindGroup,
+    update_carry: wgpu::BindGroup,
+}
+
+impl<T: ScanElement, S: PrefixScan<T> + ScanBuffers> ChunkedScan<T, S> {
+    /// Create a chunked scan on its own device, created on the adapter picked according to
+    /// `config.gpu`.
+    pub async fn with_config(chunk_len: usize, config: ScanConfig) -> anyhow::Result<Self> {
+        let (device, queue, config) =
+            init_wgpu(config, S::required_features(), S::optional_features()).await?;
+        Self::from_device(&device, &queue, chunk_len, &config)
+    }
+
+    /// Create a chunked scan on a caller-owned device, which streams the input through the
+    /// device `chunk_len` elements at a time.
+    ///
+    /// Returns an error if a chunk does not fit into the limits of the device, see
+    /// [`max_chunk_len`](Self::max_chunk_len).
+    pub fn from_device(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        chunk_len: usize,
+        config: &ScanConfig,
+    ) -> anyhow::Result<Self> {
+        let wg_size = config.workgroup_size.unwrap_or(DEFAULT_WG_SIZE);
+        check_workgroup_size(device, wg_size, 0)?;
+        // Storage buffers cannot be empty
+        let chunk_len = chunk_len.max(1);
+        check_device(
+            device,
+            S::required_features(),
+            (chunk_len * size_of::<T>()) as u64,
+            chunk_len.div_ceil(wg_size as usize) as u32,
+        )?;
+        check_op::<T>(config.op)?;
+        let scan = S::from_device(device, queue, chunk_len, config)?;
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("chunk carry shader"),
+            source: shader_source(
+                &wgsl_prelude::<T>(config.op),
+                include_str!("chunk_carry.wgsl"),
+            ),
+        });
+        let constants = [
+            ("INCLUSIVE", config.kind.inclusive_constant()),
+            ("WG_SIZE", wg_size as f64),
+        ];
+        let create_pipeline = |label: &str, entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: None,
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &constants,
+                    ..Default::default()
+                },
+                cache: Default::default(),
+            })
+        };
+        let add_carry_pipeline = create_pipeline("add_carry pipeline", "add_carry");
+        let update_carry_pipeline = create_pipeline("update_carry pipeline", "update_carry");
+
+        let carry = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("carry"),
+            size: size_of::<T>() as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let last_input = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("last input"),
+            size: size_of::<T>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // The contents are written for every chunk
+        let uniform = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("chunk uniform"),
+            size: size_of::<ChunkUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Ok(Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            scan,
+            kind: config.kind,
+            wg_size,
+            add_carry_pipeline,
+            update_carry_pipeline,
+            carry_bind_groups: BindGroupCache::new(),
+            carry,
+            last_input,
+            uniform,
+            chunk_len,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Largest chunk length a single storage binding of `device` can hold. The inner scan may
+    /// need a smaller one, e.g. the global Blelloch scan pads the chunk to a power of two.
+    pub fn max_chunk_len(device: &wgpu::Device) -> usize {
+        let limits = device.limits();
+        let max_bytes = limits
+            .max_buffer_size
+            .min(limits.max_storage_buffer_binding_size as u64);
+        max_bytes as usize / size_of::<T>()
+    }
+
+    pub fn chunk_len(&self) -> usize {
+        self.chunk_len
+    }
+
+    /// Scan the elements of `input`, passing the result to `sink` one chunk at a time, in order.
+    /// Only one chunk of the input and of the result is held in memory at a time.
+    pub fn scan_iter<I>(
+        &self,
+        input: I,
+        mut sink: impl FnMut(&[T]) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let mut input = input.into_iter();
+        let mut chunk = Vec::with_capacity(self.chunk_len);
+        let mut previous = None;
+        loop {
+            chunk.clear();
+            chunk.extend(input.by_ref().take(self.chunk_len));
+            if chunk.is_empty() {
+                break;
+            }
+            let (result, readback) = self.scan_chunk(&chunk, previous.take())?;
+            if let Some(result) = result {
+                sink(&result)?;
+            }
+            previous = Some(readback);
+        }
+        if let Some(readback) = previous {
+            sink(&readback.wait()?)?;
+        }
+        Ok(())
+    }
+
+    /// Scan `data` in place.
+    pub fn scan_in_place(&self, data: &mut [T]) -> anyhow::Result<()> {
+        // Start of the chunk whose result is being read back
+        let mut done = 0;
+        let mut previous = None;
+        for start in (0..data.len()).step_by(self.chunk_len) {
+            let end = (start + self.chunk_len).min(data.len());
+            let (result, readback) = self.scan_chunk(&data[start..end], previous.take())?;
+            if let Some(result) = result {
+                data[done..start].copy_from_slice(&result);
+                done = start;
+            }
+            previous = Some(readback);
+        }
+        if let Some(readback) = previous {
+            data[done..].copy_from_slice(&readback.wait()?);
+        }
+        Ok(())
+    }
+
+    /// Scan `input` into a new vector.
+    pub fn scan_slice(&self, input: &[T]) -> anyhow::Result<Vec<T>> {
+        let mut output = Vec::with_capacity(input.len());
+        let mut previous = None;
+        for chunk in input.chunks(self.chunk_len) {
+            let (result, readback) = self.scan_chunk(chunk, previous.take())?;
+            if let Some(result) = result {
+                output.extend_from_slice(&result);
+            }
+            previous = Some(readback);
+        }
+        if let Some(readback) = previous {
+            output.extend_from_slice(&readback.wait()?);
+        }
+        Ok(output)
+    }
+
+    /// Scan one chunk of at most `chunk_len` elements and carry the chunks before it into it,
+    /// starting over if there is no `previous` chunk. The result of the previous chunk is read
+    /// while the GPU scans this one, and returned with the readback of this chunk.
+    fn scan_chunk(
+        &self,
+        chunk: &[T],
+        previous: Option<Readback<T>>,
+    ) -> anyhow::Result<(Option<Vec<T>>, Readback<T>)> {
+        let len = chunk.len();
+        let elem_size = size_of::<T>() as u64;
+        self.scan.upload_data(chunk)?;
+        let uniform = ChunkUniform {
+            len: len as u32,
+            _pad: [0; 3],
+        };
+        self.queue
+            .write_buffer(&self.uniform, 0, bytemuck::bytes_of(&uniform));
+
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        if self.kind == ScanKind::Exclusive {
+            // The scan may overwrite the input, which the carry of the exclusive scan needs
+            let last_offset = (len as u64 - 1) * elem_size;
+            encoder.copy_buffer_to_buffer(
+                self.scan.input_buffer(),
+                last_offset,
+                &self.last_input,
+                0,
+                elem_size,
+            );
+        }
+        self.scan.encode_scan(&mut encoder);
+        {
+            let bind_groups = self.carry_bind_groups(self.scan.output_buffer());
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            if previous.is_some() {
+                let max_dim = self.device.limits().max_compute_workgroups_per_dimension;
+                let [x, y, z] = split_dispatch_3d((len as u32).div_ceil(self.wg_size), max_dim);
+                pass.set_pipeline(&self.add_carry_pipeline);
+                pass.set_bind_group(0, &bind_groups.add_carry, &[]);
+                pass.dispatch_workgroups(x, y, z);
+            }
+            pass.set_pipeline(&self.update_carry_pipeline);
+            pass.set_bind_group(0, &bind_groups.update_carry, &[]);
+            pass.dispatch_workgroups(1, 1, 1);
+        }
+        self.queue.submit([encoder.finish()]);
+
+        // The context has one readback buffer, which the previous chunk releases first
+        let result = previous.map(Readback::wait).transpose()?;
+        Ok((result, self.scan.read_computed_data_async()))
+    }
+
+    /// Bind groups carrying the previous chunks into the result in `output`.
+    fn carry_bind_groups(&self, output: &wgpu::Buffer) -> CarryBindGroups {
+        let output = StorageBinding::entire(output);
+        self.carry_bind_groups.get_or_create(&output, None, || {
+            let create_bind_group = |label: &str, pipeline: &wgpu::ComputePipeline, last_input| {
+                let entries: Vec<wgpu::BindGroupEntry> = [
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: output.resource(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: self.carry.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: self.uniform.as_entire_binding(),
+                    },
+                ]
+                .into_iter()
+                .chain(last_input)
+                .collect();
+                self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some(label),
+                    layout: &pipeline.get_bind_group_layout(0),
+                    entries: &entries,
+                })
+            };
+            CarryBindGroups {
+                add_carry: create_bind_group(
+                    "add_carry bind group",
+                    &self.add_carry_pipeline,
+                    None,
+                ),
+                update_carry: create_bind_group(
+                    "update_carry bind group",
+                    &self.update_carry_pipeline,
+                    Some(wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: self.last_input.as_entire_binding(),
+                    }),
+                ),
+            }
+        })
+    }
+}
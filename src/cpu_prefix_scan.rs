@@ -1,12 +1,203 @@
-pub fn cpu_prefix_sum(data: &[u32]) -> Vec<u32> {
+use crate::prefix_scan::{BufferRange, PrefixScan, ScanConfig, ScanKind};
+use crate::readback::{Readback, WorkDone};
+use crate::scan_element::ScanElement;
+use crate::scan_error::ScanError;
+use crate::scan_op::{ScanOp, check_op};
+use crate::utils::check_len;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+pub fn cpu_prefix_sum<T: ScanElement>(data: &[T]) -> Vec<T> {
+    // Addition applies to every element type
+    inclusive_scan(data, ScanOp::Add)
+}
+
+/// Sequential inclusive scan with any of the operators, the reference for the GPU scans.
+///
+/// Returns [`ScanError::UnsupportedOp`] if `op` cannot be applied to `T`.
+pub fn cpu_prefix_scan<T: ScanElement>(data: &[T], op: ScanOp) -> Result<Vec<T>, ScanError> {
+    check_op::<T>(op)?;
+    Ok(inclusive_scan(data, op))
+}
+
+/// Inclusive scan with an operator already checked against `T`.
+fn inclusive_scan<T: ScanElement>(data: &[T], op: ScanOp) -> Vec<T> {
     if data.is_empty() {
         return vec![];
     }
     let n = data.len();
-    let mut res = vec![0u32; n];
+    let mut res = vec![T::zeroed(); n];
     res[0] = data[0];
     for i in 1..n {
-        res[i] = res[i - 1] + data[i];
+        res[i] = T::combine(op, res[i - 1], data[i]);
     }
     res
 }
+
+/// Scan `data` into `output` of the same length, inclusively or exclusively, with an operator
+/// already checked against `T`.
+fn scan_into<T: ScanElement>(data: &[T], output: &mut [T], op: ScanOp, kind: ScanKind) {
+    let mut acc = T::identity(op);
+    for (out, &v) in output.iter_mut().zip(data) {
+        let next = T::combine(op, acc, v);
+        *out = match kind {
+            ScanKind::Inclusive => next,
+            ScanKind::Exclusive => acc,
+        };
+        acc = next;
+    }
+}
+
+/// Sequential inclusive segmented scan, which restarts at every element with a nonzero flag.
+/// The reference for the segmented GPU scans.
+///
+/// Returns [`ScanError::FlagsTooShort`] if there is no flag for every element of `data`, and
+/// [`ScanError::UnsupportedOp`] if `op` cannot be applied to `T`.
+pub fn cpu_segmented_prefix_scan<T: ScanElement>(
+    data: &[T],
+    flags: &[u32],
+    op: ScanOp,
+) -> Result<Vec<T>, ScanError> {
+    check_op::<T>(op)?;
+    if flags.len() < data.len() {
+        return Err(ScanError::FlagsTooShort {
+            len: data.len(),
+            flags_len: flags.len(),
+        });
+    }
+    let mut res = Vec::with_capacity(data.len());
+    for (i, &v) in data.iter().enumerate() {
+        if i == 0 || flags[i] != 0 {
+            res.push(v);
+        } else {
+            res.push(T::combine(op, res[i - 1], v));
+        }
+    }
+    Ok(res)
+}
+
+/// [`PrefixScan`] adapter for [`cpu_prefix_sum`], so the CPU baseline can be swapped in for
+/// any GPU context.
+///
+/// There is no GPU work to record, so encoding or running the scan only marks it as pending and
+/// the scan itself is executed by whichever waiting or reading method is called next, like the
+/// GPU contexts do their work between submit and wait.
+pub struct CpuPrefixScan<T: ScanElement = u32> {
+    input: Mutex<Vec<T>>,
+    output: Mutex<Vec<T>>,
+    pending: AtomicBool,
+    capacity: usize,
+    len: AtomicUsize,
+    kind: ScanKind,
+    op: ScanOp,
+}
+
+impl<T: ScanElement> CpuPrefixScan<T> {
+    fn create(capacity: usize, config: &ScanConfig) -> anyhow::Result<Self> {
+        check_op::<T>(config.op)?;
+        Ok(Self {
+            input: Mutex::new(vec![T::zeroed(); capacity]),
+            output: Mutex::new(vec![T::zeroed(); capacity]),
+            pending: AtomicBool::new(false),
+            capacity,
+            len: AtomicUsize::new(capacity),
+            kind: config.kind,
+            op: config.op,
+        })
+    }
+
+    fn flush(&self) {
+        if !self.pending.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        let len = self.len();
+        let input = self.input.lock().unwrap();
+        let mut output = self.output.lock().unwrap();
+        scan_into(&input[..len], &mut output[..len], self.op, self.kind);
+    }
+
+    /// The first [`len`](PrefixScan::len) elements of the result.
+    fn result(&self) -> Vec<T> {
+        self.output.lock().unwrap()[..self.len()].to_vec()
+    }
+}
+
+impl<T: ScanElement> PrefixScan<T> for CpuPrefixScan<T> {
+    async fn with_config(capacity: usize, config: ScanConfig) -> anyhow::Result<Self> {
+        Self::create(capacity, &config)
+    }
+
+    /// The device is not used, the scan runs on the CPU.
+    fn from_device(
+        _device: &wgpu::Device,
+        _queue: &wgpu::Queue,
+        capacity: usize,
+        config: &ScanConfig,
+    ) -> anyhow::Result<Self> {
+        Self::create(capacity, config)
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    fn set_len(&self, len: usize) -> anyhow::Result<()> {
+        check_len(self.capacity, len)?;
+        self.len.store(len, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn upload_data(&self, input: &[T]) -> anyhow::Result<()> {
+        self.set_len(input.len())?;
+        self.input.lock().unwrap()[..input.len()].copy_from_slice(input);
+        Ok(())
+    }
+
+    fn encode_scan(&self, _encoder: &mut wgpu::CommandEncoder) {
+        self.pending.store(true, Ordering::Release);
+    }
+
+    /// Always fails, the CPU scan cannot read GPU buffers.
+    fn encode_scan_buffer(
+        &self,
+        _encoder: &mut wgpu::CommandEncoder,
+        _input: BufferRange<'_>,
+        _output: Option<BufferRange<'_>>,
+    ) -> anyhow::Result<()> {
+        Err(ScanError::Unsupported("the CPU scan cannot read GPU buffers").into())
+    }
+
+    fn run_prefix_scan(&self) {
+        self.pending.store(true, Ordering::Release);
+    }
+
+    fn read_computed_data(&self) -> anyhow::Result<Vec<T>> {
+        self.flush();
+        Ok(self.result())
+    }
+
+    /// The CPU scan runs here, so the returned readback is already resolved.
+    fn read_computed_data_async(&self) -> Readback<T> {
+        self.flush();
+        Readback::done(self.result())
+    }
+
+    fn wait_for_previous_submit(&self) -> anyhow::Result<()> {
+        self.flush();
+        Ok(())
+    }
+
+    fn wait_for_previous_submit_async(&self) -> WorkDone {
+        self.flush();
+        WorkDone::done()
+    }
+
+    fn wait_idle(&self) -> anyhow::Result<()> {
+        self.flush();
+        Ok(())
+    }
+}
@@ -0,0 +1,342 @@
+use crate::prefix_scan::{BufferRange, PrefixScan, ScanBuffers, ScanConfig};
+use crate::readback::{Readback, ReadbackBuffer, WorkDone};
+use crate::scan_element::ScanElement;
+use crate::scan_op::{check_op, wgsl_prelude};
+use crate::utils::{
+    BindGroupCache, StorageBinding, UniformBuffer, can_bind_range, check_buffer_ranges,
+    check_device, check_len, check_workgroup_size, read_buffer, shader_source, split_dispatch_3d,
+    wait_for_previous_submit,
+};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const DEFAULT_WG_SIZE: u32 = 256;
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ScanUniform {
+    len: u32,
+    _pad: [u32; 3],
+}
+
+/// Single-pass chained scan with decoupled look-back.
+///
+/// Every workgroup scans one tile, publishes its aggregate, then walks back over the tiles before
+/// it until it finds an inclusive prefix, so the data is read and written once instead of going
+/// through a hierarchy of levels. A workgroup which waits too long for a predecessor reduces the
+/// predecessor's input itself, so the scan does not rely on the adapter scheduling the workgroups
+/// fairly. That needs the input intact, so the result is always written to a separate buffer.
+pub struct DecoupledLookbackGpuContext<T: ScanElement = u32> {
+    device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+    // Bind groups of the caller's ranges in a buffer scan
+    range_bind_groups: BindGroupCache<wgpu::BindGroup>,
+    input: wgpu::Buffer,
+    output: wgpu::Buffer,
+    tile_state: wgpu::Buffer,
+    uniform: UniformBuffer,
+    readback: ReadbackBuffer,
+    wg_size: u32,
+    capacity: usize,
+    len: AtomicUsize,
+    _marker: PhantomData<T>,
+}
+
+/// Bind group of a scan from `input` to `output`.
+fn create_bind_group(
+    device: &wgpu::Device,
+    pipeline: &wgpu::ComputePipeline,
+    input: wgpu::BindingResource<'_>,
+    output: wgpu::BindingResource<'_>,
+    tile_state: &wgpu::Buffer,
+    uniform: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("decoupled look-back bind group"),
+        layout: &pipeline.get_bind_group_layout(0),
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: input,
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: output,
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: tile_state.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: uniform.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+impl<T: ScanElement> DecoupledLookbackGpuContext<T> {
+    /// Record a scan of the first [`len`](PrefixScan::len) elements with `bind_group`.
+    fn encode_with(&self, encoder: &mut wgpu::CommandEncoder, bind_group: &wgpu::BindGroup) {
+        let len = self.len();
+        if len == 0 {
+            return;
+        }
+        // The tiles are handed out and looked back at through the tile state, which has to
+        // start out zeroed
+        encoder.clear_buffer(&self.tile_state, 0, None);
+        // Each scan writes its own length, so scans of different lengths can share an encoder
+        let uniform = ScanUniform {
+            len: len as u32,
+            _pad: [0; 3],
+        };
+        self.uniform
+            .encode_write(encoder, bytemuck::bytes_of(&uniform));
+
+        let max_dim = self.device.limits().max_compute_workgroups_per_dimension;
+        let [x, y, z] = split_dispatch_3d(len.div_ceil(self.wg_size as usize) as u32, max_dim);
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.dispatch_workgroups(x, y, z);
+    }
+
+    /// Buffer the input is uploaded to, which the scan does not modify.
+    pub fn input_buffer(&self) -> &wgpu::Buffer {
+        &self.input
+    }
+
+    /// Buffer holding the result.
+    pub fn output_buffer(&self) -> &wgpu::Buffer {
+        &self.output
+    }
+
+    pub fn get_command_encoder(&self) -> wgpu::CommandEncoder {
+        self.device.create_command_encoder(&Default::default())
+    }
+
+    pub fn submit(&self, encoder: wgpu::CommandEncoder) {
+        self.queue.submit([encoder.finish()]);
+    }
+}
+
+impl<T: ScanElement> ScanBuffers for DecoupledLookbackGpuContext<T> {
+    fn input_buffer(&self) -> &wgpu::Buffer {
+        DecoupledLookbackGpuContext::input_buffer(self)
+    }
+
+    fn output_buffer(&self) -> &wgpu::Buffer {
+        DecoupledLookbackGpuContext::output_buffer(self)
+    }
+}
+
+impl<T: ScanElement> PrefixScan<T> for DecoupledLookbackGpuContext<T> {
+    fn from_device(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        capacity: usize,
+        config: &ScanConfig,
+    ) -> anyhow::Result<Self> {
+        let wg_size = config.workgroup_size.unwrap_or(DEFAULT_WG_SIZE);
+        // The tile is reduced in one element per invocation
+        check_workgroup_size(device, wg_size, (wg_size as usize * size_of::<T>()) as u64)?;
+        let max_tiles = capacity.div_ceil(wg_size as usize).max(1);
+        check_device(
+            device,
+            wgpu::Features::empty(),
+            (capacity * size_of::<T>()) as u64,
+            max_tiles as u32,
+        )?;
+        check_op::<T>(config.op)?;
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("decoupled look-back shader"),
+            source: shader_source(
+                &wgsl_prelude::<T>(config.op),
+                include_str!("decoupled_lookback_scan.wgsl"),
+            ),
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("decoupled look-back pipeline"),
+            layout: None,
+            module: &shader,
+            entry_point: None,
+            compilation_options: wgpu::PipelineCompilationOptions {
+                constants: &[
+                    ("INCLUSIVE", config.kind.inclusive_constant()),
+                    ("WG_SIZE", wg_size as f64),
+                ],
+                ..Default::default()
+            },
+            cache: Default::default(),
+        });
+
+        // Zero-sized buffers cannot be bound, so keep at least one element
+        let byte_len = (capacity * size_of::<T>()).max(4) as u64;
+
+        let input = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("input"),
+            size: byte_len,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let output = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("output"),
+            size: byte_len,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        // The tile counter, then two words per tile. Cleared before every scan.
+        let tile_state = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("tile state"),
+            size: ((1 + 2 * max_tiles) * size_of::<u32>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // The contents are written by every recorded scan
+        let uniform = UniformBuffer::new(device, queue, "uniform", size_of::<ScanUniform>() as u64);
+        let readback = ReadbackBuffer::new(device, byte_len);
+
+        let bind_group = create_bind_group(
+            device,
+            &pipeline,
+            input.as_entire_binding(),
+            output.as_entire_binding(),
+            &tile_state,
+            uniform.buffer(),
+        );
+
+        let context = Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            pipeline,
+            bind_group,
+            range_bind_groups: BindGroupCache::new(),
+            input,
+            output,
+            tile_state,
+            uniform,
+            readback,
+            wg_size,
+            capacity,
+            len: AtomicUsize::new(capacity),
+            _marker: PhantomData,
+        };
+        context.set_len(capacity)?;
+        Ok(context)
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    fn set_len(&self, len: usize) -> anyhow::Result<()> {
+        check_len(self.capacity, len)?;
+        self.len.store(len, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn upload_data(&self, input: &[T]) -> anyhow::Result<()> {
+        self.set_len(input.len())?;
+        self.queue
+            .write_buffer(&self.input, 0, bytemuck::cast_slice(input));
+        Ok(())
+    }
+
+    fn encode_scan(&self, encoder: &mut wgpu::CommandEncoder) {
+        self.encode_with(encoder, &self.bind_group);
+    }
+
+    fn encode_scan_buffer(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        input: BufferRange<'_>,
+        output: Option<BufferRange<'_>>,
+    ) -> anyhow::Result<()> {
+        let output = check_buffer_ranges::<T>(input, output)?;
+        self.set_len(input.len)?;
+        if input.len == 0 {
+            return Ok(());
+        }
+        let byte_len = (input.len * size_of::<T>()) as u64;
+        if !can_bind_range(&self.device, output, 1) {
+            encoder.copy_buffer_to_buffer(input.buffer, input.offset, &self.input, 0, byte_len);
+            self.encode_scan(encoder);
+            encoder.copy_buffer_to_buffer(&self.output, 0, output.buffer, output.offset, byte_len);
+            return Ok(());
+        }
+        // The input is read while the output is written, so it is copied unless it is in another
+        // buffer
+        let input = if input.buffer != output.buffer && can_bind_range(&self.device, input, 1) {
+            StorageBinding::of_range::<T>(input)
+        } else {
+            encoder.copy_buffer_to_buffer(input.buffer, input.offset, &self.input, 0, byte_len);
+            StorageBinding::entire(&self.input)
+        };
+        let output = StorageBinding::of_range::<T>(output);
+        let bind_group = self
+            .range_bind_groups
+            .get_or_create(&output, Some(&input), || {
+                create_bind_group(
+                    &self.device,
+                    &self.pipeline,
+                    input.resource(),
+                    output.resource(),
+                    &self.tile_state,
+                    self.uniform.buffer(),
+                )
+            });
+        self.encode_with(encoder, &bind_group);
+        Ok(())
+    }
+
+    fn run_prefix_scan(&self) {
+        let mut encoder = self.get_command_encoder();
+        self.encode_scan(&mut encoder);
+        self.submit(encoder);
+    }
+
+    fn read_computed_data(&self) -> anyhow::Result<Vec<T>> {
+        read_buffer(
+            &self.device,
+            &self.queue,
+            &self.output,
+            &self.readback,
+            self.len(),
+        )
+    }
+
+    fn read_computed_data_async(&self) -> Readback<T> {
+        Readback::new(
+            &self.device,
+            &self.queue,
+            &self.output,
+            &self.readback,
+            self.len(),
+        )
+    }
+
+    fn wait_for_previous_submit(&self) -> anyhow::Result<()> {
+        wait_for_previous_submit(&self.device, &self.queue)
+    }
+
+    fn wait_for_previous_submit_async(&self) -> WorkDone {
+        WorkDone::new(&self.device, &self.queue)
+    }
+
+    fn wait_idle(&self) -> anyhow::Result<()> {
+        self.device.poll(wgpu::PollType::wait_indefinitely())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+// `Elem`, `identity()` and `combine(a, b)` of the scan operator are declared by the prelude
+// which is prepended on the host.
+override INCLUSIVE: bool = true;
+// Invocations per workgroup and elements per tile, a power of two
+override WG_SIZE: u32 = 256u;
+
+// Tile states. Only the aggregate of the tile itself is known, or also its inclusive prefix.
+const FLAG_NOT_READY: u32 = 0u;
+const FLAG_AGGREGATE: u32 = 1u;
+const FLAG_PREFIX: u32 = 2u;
+const FLAG_MASK: u32 = 3u;
+// Polls of a predecessor tile before the workgroup stops waiting and reduces it itself
+const SPIN_LIMIT: u32 = 128u;
+
+struct Scan {
+    len: u32,
+};
+
+@group(0) @binding(0) var<storage, read> input: array<Elem>;
+@group(0) @binding(1) var<storage, read_write> output: array<Elem>;
+// Word 0 hands out the tile indices in the order the workgroups start. Then every tile has two
+// words, each holding 16 bits of the value and the flag, so the value and the flag are published
+// together without depending on the ordering of separate stores.
+@group(0) @binding(2) var<storage, read_write> tile_state: array<atomic<u32>>;
+// Number of elements to scan, the buffers may be longer
+@group(0) @binding(3) var<uniform> scan: Scan;
+
+var<workgroup> wg_tile: u32;
+var<workgroup> wg_data: array<Elem, WG_SIZE>;
+// Combination of the tiles looked back at so far
+var<workgroup> wg_prefix: Elem;
+// Predecessor tile the look-back is waiting for
+var<workgroup> wg_lookback: u32;
+var<workgroup> wg_done: u32;
+
+fn load_input(i: u32) -> Elem {
+    if (i < scan.len) {
+        return input[i];
+    }
+    return identity();
+}
+
+fn publish(tile: u32, value: Elem, flag: u32) {
+    let bits = bitcast<u32>(value);
+    atomicStore(&tile_state[1u + 2u * tile], (bits << 16u) | flag);
+    atomicStore(&tile_state[2u + 2u * tile], (bits & 0xffff0000u) | flag);
+}
+
+struct TileState {
+    flag: u32,
+    value: Elem,
+}
+
+fn read_tile(tile: u32) -> TileState {
+    let lo = atomicLoad(&tile_state[1u + 2u * tile]);
+    let hi = atomicLoad(&tile_state[2u + 2u * tile]);
+    // The halves of an aggregate and of the prefix replacing it can be mixed, wait until both
+    // come from the same store
+    if ((lo & FLAG_MASK) != (hi & FLAG_MASK)) {
+        return TileState(FLAG_NOT_READY, identity());
+    }
+    let bits = (lo >> 16u) | (hi & 0xffff0000u);
+    return TileState(lo & FLAG_MASK, bitcast<Elem>(bits));
+}
+
+// Reduce the input of `tile` with the whole workgroup, used when its workgroup does not make
+// progress. Must be called from uniform control flow.
+fn reduce_tile(tile: u32, lid: u32) -> Elem {
+    wg_data[lid] = load_input(tile * WG_SIZE + lid);
+    for (var stride = WG_SIZE / 2u; stride > 0u; stride = stride >> 1u) {
+        workgroupBarrier();
+        if (lid < stride) {
+            wg_data[lid] = combine(wg_data[lid], wg_data[lid + stride]);
+        }
+    }
+    workgroupBarrier();
+    return wg_data[0];
+}
+
+@compute @workgroup_size(WG_SIZE)
+fn main(@builtin(local_invocation_id) lid: vec3<u32>) {
+    // Tiles are numbered in the order the workgroups start, so the predecessors of a tile have
+    // always started before it
+    if (lid.x == 0u) {
+        wg_tile = atomicAdd(&tile_state[0], 1u);
+    }
+    let tile = workgroupUniformLoad(&wg_tile);
+    let n_tiles = (scan.len + WG_SIZE - 1u) / WG_SIZE;
+    if (tile >= n_tiles) {
+        return;
+    }
+
+    // Inclusive Hillis-Steele scan of the tile
+    let global_idx = tile * WG_SIZE + lid.x;
+    let v = load_input(global_idx);
+    wg_data[lid.x] = v;
+    for (var offset = 1u; offset < WG_SIZE; offset = offset << 1u) {
+        workgroupBarrier();
+        var x = wg_data[lid.x];
+        if (lid.x >= offset) {
+            x = combine(wg_data[lid.x - offset], x);
+        }
+        workgroupBarrier();
+        wg_data[lid.x] = x;
+    }
+    workgroupBarrier();
+    let local_inclusive = wg_data[lid.x];
+    var local_exclusive = identity();
+    if (lid.x > 0u) {
+        local_exclusive = wg_data[lid.x - 1u];
+    }
+    let aggregate = wg_data[WG_SIZE - 1u];
+
+    // Publish the aggregate right away, so the successors do not have to wait for the look-back
+    if (lid.x == 0u) {
+        wg_prefix = identity();
+        if (tile == 0u) {
+            publish(tile, aggregate, FLAG_PREFIX);
+            wg_done = 1u;
+        } else {
+            publish(tile, aggregate, FLAG_AGGREGATE);
+            wg_lookback = tile - 1u;
+            wg_done = 0u;
+        }
+    }
+
+    loop {
+        // One invocation walks back over the predecessors until it finds an inclusive prefix
+        if (lid.x == 0u && wg_done == 0u) {
+            var spins = 0u;
+            while (spins < SPIN_LIMIT) {
+                let state = read_tile(wg_lookback);
+                if (state.flag == FLAG_PREFIX) {
+                    wg_prefix = combine(state.value, wg_prefix);
+                    wg_done = 1u;
+                    break;
+                }
+                if (state.flag == FLAG_AGGREGATE) {
+                    wg_prefix = combine(state.value, wg_prefix);
+                    wg_lookback -= 1u;
+                    spins = 0u;
+                } else {
+                    spins += 1u;
+                }
+            }
+        }
+        if (workgroupUniformLoad(&wg_done) == 1u) {
+            break;
+        }
+
+        // The predecessor has not published anything in time. Without a guarantee that its
+        // workgroup is ever scheduled again, reduce its input here instead of waiting for it.
+        let stalled = workgroupUniformLoad(&wg_lookback);
+        let reduced = reduce_tile(stalled, lid.x);
+        if (lid.x == 0u) {
+            wg_prefix = combine(reduced, wg_prefix);
+            if (stalled == 0u) {
+                wg_done = 1u;
+            } else {
+                wg_lookback = stalled - 1u;
+            }
+        }
+    }
+
+    // `workgroupUniformLoad` synchronizes the workgroup, so the prefix is visible to all
+    let prefix = wg_prefix;
+    if (lid.x == 0u && tile > 0u) {
+        publish(tile, combine(prefix, aggregate), FLAG_PREFIX);
+    }
+
+    if (global_idx < scan.len) {
+        if (INCLUSIVE) {
+            output[global_idx] = combine(prefix, local_inclusive);
+        } else {
+            output[global_idx] = combine(prefix, local_exclusive);
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use crate::utils::{LevelUniforms, StorageBinding, split_dispatch_3d};
+
+/// Passes of a blocked scan which reduces the levels first and scans each level once the
+/// carries from the level above are known, so every level is written once instead of being
+/// written by the block scan and then again by the add-carry pass.
+///
+/// Shared by the block scans whose shaders have the `block_reduce` and `block_scan_carry` entry
+/// points, with the bindings of their `block_scan_write_sum` entry points.
+pub struct FusedCarry {
+    // The first level reads from `input` in the out-of-place scan
+    pipeline_input_reduce: wgpu::ComputePipeline,
+    pipeline_input_scan: wgpu::ComputePipeline,
+    pipeline_reduce: wgpu::ComputePipeline,
+    pipeline_scan: wgpu::ComputePipeline,
+    // The levels above the first one, level `i` at index `i - 1`
+    bind_groups_reduce: Vec<wgpu::BindGroup>,
+    bind_groups_scan: Vec<wgpu::BindGroup>,
+}
+
+/// Bind groups of the first level, see [`FusedCarry::first_level`].
+#[derive(Clone)]
+pub struct FusedCarryFirstLevel {
+    from_input: bool,
+    reduce: wgpu::BindGroup,
+    scan: wgpu::BindGroup,
+}
+
+/// Bind group of a level with the block sums, or the carries, in `sums`. The reduction of the
+/// out-of-place first level reads the input instead of the data, so it does not bind the data.
+fn create_bind_group(
+    device: &wgpu::Device,
+    pipeline: &wgpu::ComputePipeline,
+    data: Option<wgpu::BindingResource<'_>>,
+    input: Option<wgpu::BindingResource<'_>>,
+    sums: &wgpu::Buffer,
+    uniform: wgpu::BindingResource<'_>,
+) -> wgpu::BindGroup {
+    let mut entries = vec![
+        wgpu::BindGroupEntry {
+            binding: 1,
+            resource: sums.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+            binding: 2,
+            resource: uniform,
+        },
+    ];
+    if let Some(data) = data {
+        entries.push(wgpu::BindGroupEntry {
+            binding: 0,
+            resource: data,
+        });
+    }
+    if let Some(input) = input {
+        entries.push(wgpu::BindGroupEntry {
+            binding: 3,
+            resource: input,
+        });
+    }
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("fused-carry bind group"),
+        layout: &pipeline.get_bind_group_layout(0),
+        entries: &entries,
+    })
+}
+
+impl FusedCarry {
+    pub fn new(
+        device: &wgpu::Device,
+        block_scan_shader: &wgpu::ShaderModule,
+        constants: &[(&str, f64)],
+        data_buffers: &[wgpu::Buffer],
+        level_uniforms: &LevelUniforms,
+    ) -> Self {
+        let create_pipeline = |label: &str, entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: None,
+                module: block_scan_shader,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants,
+                    ..Default::default()
+                },
+                cache: Default::default(),
+            })
+        };
+        let pipeline_reduce = create_pipeline("block_reduce pipeline", "block_reduce");
+        let pipeline_scan = create_pipeline("block_scan_carry pipeline", "block_scan_carry");
+        let pipeline_input_reduce = create_pipeline(
+            "block_reduce_from_input pipeline",
+            "block_reduce_from_input",
+        );
+        let pipeline_input_scan = create_pipeline(
+            "block_scan_carry_from_input pipeline",
+            "block_scan_carry_from_input",
+        );
+
+        // Level i with the block sums, or the carries, in level i + 1
+        let create_level_bind_group = |pipeline: &wgpu::ComputePipeline, i: usize| {
+            create_bind_group(
+                device,
+                pipeline,
+                Some(data_buffers[i].as_entire_binding()),
+                None,
+                &data_buffers[i + 1],
+                level_uniforms.binding(i),
+            )
+        };
+        let upper_levels = 1..data_buffers.len() - 1;
+        let bind_groups_reduce = upper_levels
+            .clone()
+            .map(|i| create_level_bind_group(&pipeline_reduce, i))
+            .collect();
+        let bind_groups_scan = upper_levels
+            .map(|i| create_level_bind_group(&pipeline_scan, i))
+            .collect();
+
+        Self {
+            pipeline_input_reduce,
+            pipeline_input_scan,
+            pipeline_reduce,
+            pipeline_scan,
+            bind_groups_reduce,
+            bind_groups_scan,
+        }
+    }
+
+    /// Bind groups of the first level which scans `data`, after reading it from `input` if it is
+    /// set. `None` if the context has a single level, which needs no carries.
+    pub fn first_level(
+        &self,
+        device: &wgpu::Device,
+        data: &StorageBinding,
+        input: Option<&StorageBinding>,
+        data_buffers: &[wgpu::Buffer],
+        level_uniforms: &LevelUniforms,
+    ) -> Option<FusedCarryFirstLevel> {
+        let sums = data_buffers.get(1)?;
+        let (pipeline_reduce, pipeline_scan) = if input.is_some() {
+            (&self.pipeline_input_reduce, &self.pipeline_input_scan)
+        } else {
+            (&self.pipeline_reduce, &self.pipeline_scan)
+        };
+        let reduce = create_bind_group(
+            device,
+            pipeline_reduce,
+            input.is_none().then(|| data.resource()),
+            input.map(StorageBinding::resource),
+            sums,
+            level_uniforms.binding(0),
+        );
+        let scan = create_bind_group(
+            device,
+            pipeline_scan,
+            Some(data.resource()),
+            input.map(StorageBinding::resource),
+            sums,
+            level_uniforms.binding(0),
+        );
+        Some(FusedCarryFirstLevel {
+            from_input: input.is_some(),
+            reduce,
+            scan,
+        })
+    }
+
+    /// Reduce every level below the top one of `lens` into the block sums of the level above it.
+    pub fn encode_reduce(
+        &self,
+        pass: &mut wgpu::ComputePass<'_>,
+        first_level: &FusedCarryFirstLevel,
+        lens: &[usize],
+        block_len: usize,
+        max_dim: u32,
+    ) {
+        for (i, &level_len) in lens[..lens.len() - 1].iter().enumerate() {
+            let (pipeline, bind_group) = match i {
+                0 if first_level.from_input => (&self.pipeline_input_reduce, &first_level.reduce),
+                0 => (&self.pipeline_reduce, &first_level.reduce),
+                _ => (&self.pipeline_reduce, &self.bind_groups_reduce[i - 1]),
+            };
+            let workgroups_needed = level_len.div_ceil(block_len).max(1) as u32;
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            let [x, y, z] = split_dispatch_3d(workgroups_needed, max_dim);
+            pass.dispatch_workgroups(x, y, z);
+        }
+    }
+
+    /// Scan every level below the top one of `lens`, from the top down, once the level above it
+    /// has been scanned.
+    pub fn encode_scan(
+        &self,
+        pass: &mut wgpu::ComputePass<'_>,
+        first_level: &FusedCarryFirstLevel,
+        lens: &[usize],
+        block_len: usize,
+        max_dim: u32,
+    ) {
+        for (i, &level_len) in lens[..lens.len() - 1].iter().enumerate().rev() {
+            let (pipeline, bind_group) = match i {
+                0 if first_level.from_input => (&self.pipeline_input_scan, &first_level.scan),
+                0 => (&self.pipeline_scan, &first_level.scan),
+                _ => (&self.pipeline_scan, &self.bind_groups_scan[i - 1]),
+            };
+            let workgroups_needed = level_len.div_ceil(block_len).max(1) as u32;
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            let [x, y, z] = split_dispatch_3d(workgroups_needed, max_dim);
+            pass.dispatch_workgroups(x, y, z);
+        }
+    }
+}
@@ -1,27 +1,43 @@
-use crate::utils::{align_up, init_wgpu};
+use crate::prefix_scan::{BufferRange, PrefixScan, ScanBuffers, ScanConfig, ScanKind};
+use crate::readback::{Readback, ReadbackBuffer, WorkDone};
+use crate::scan_element::ScanElement;
+use crate::scan_op::{check_op, wgsl_prelude};
+use crate::utils::{
+    UniformBuffer, align_up, check_buffer_ranges, check_device, check_len, check_workgroup_size,
+    read_buffer, shader_source, split_dispatch_3d, wait_for_previous_submit,
+};
+use std::marker::PhantomData;
 use std::num::NonZeroU64;
-use std::sync::mpsc::channel;
-use wgpu::util::DeviceExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-fn split_dispatch_3d(workgroups_needed: u32, max_dim: u32) -> [u32; 3] {
-    let x = workgroups_needed.min(max_dim);
-    let remaining_after_x = (workgroups_needed + x - 1) / x;
-    let y = remaining_after_x.min(max_dim);
-
-    let xy = (x as u64) * (y as u64);
-    let z = ((workgroups_needed as u64) + xy - 1) / xy;
-    assert!(z <= max_dim as u64, "dispatch exceeds max_dim^3");
-
-    [x, y, z as u32]
-}
+const DEFAULT_WG_SIZE: u32 = 64;
 
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct Uniform {
     step: u32, // this has to be a power of 2
-    _pad: [u32; 3],
+    padded_len: u32,
+    len: u32,
+    _pad: u32,
+}
+
+/// The sweeps produce an exclusive scan in place, so the inclusive scan keeps a copy of the
+/// input and adds it back at the end.
+struct InclusivePass {
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+    input: wgpu::Buffer,
+}
+
+/// The out-of-place scan keeps the input in a buffer of its own, which the first up-sweep step
+/// reads instead of the data.
+struct InputPass {
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+    input: wgpu::Buffer,
 }
-pub struct GlobalBlellochGpuContext {
+
+pub struct GlobalBlellochGpuContext<T: ScanElement = u32> {
     device: wgpu::Device,
     pub queue: wgpu::Queue,
     up_sweep_pipeline: wgpu::ComputePipeline,
@@ -30,39 +46,182 @@ pub struct GlobalBlellochGpuContext {
     up_sweep_bind_group: wgpu::BindGroup,
     last_zero_bind_group: wgpu::BindGroup,
     down_sweep_bind_group: wgpu::BindGroup,
+    inclusive_pass: Option<InclusivePass>,
+    input_pass: Option<InputPass>,
     data: wgpu::Buffer,
-    uniform: wgpu::Buffer,
-    readback: wgpu::Buffer,
-    n: usize,
-    max_steps: u32,
+    readback: ReadbackBuffer,
+    uniforms: UniformBuffer,
+    wg_size: u32,
+    capacity: usize,
+    len: AtomicUsize,
     uniform_stride: u32,
+    _marker: PhantomData<T>,
 }
 
-impl GlobalBlellochGpuContext {
-    pub async fn new(n: usize) -> anyhow::Result<Self> {
-        assert!(
-            n.is_power_of_two(),
-            "Number of elements of data has to be a power of 2."
-        );
+impl<T: ScanElement> GlobalBlellochGpuContext<T> {
+    /// The sweeps need a power-of-two length, so the data is padded internally. The exclusive
+    /// scan of the first `len` elements does not depend on the padding, so it does not need to
+    /// be cleared between runs.
+    fn padded_len(&self) -> usize {
+        self.len().max(1).next_power_of_two()
+    }
+
+    /// Record the up-sweep, which starts the scan by writing the uniforms of the current length.
+    pub fn encode_up_sweep(&self, encoder: &mut wgpu::CommandEncoder) {
+        let max_dim = self.device.limits().max_compute_workgroups_per_dimension;
+        let padded_len = self.padded_len();
+
+        // Create a byte array of the uniforms with the stride. Each scan writes its own, so
+        // scans of different lengths can share an encoder.
+        let stride = self.uniform_stride as usize;
+        let mut blob = vec![0u8; self.uniforms.buffer().size() as usize];
+        for i in 0..blob.len() / stride {
+            let u = Uniform {
+                step: 2u32 << i,
+                padded_len: padded_len as u32,
+                len: self.len() as u32,
+                _pad: 0,
+            };
+            let bytes = bytemuck::bytes_of(&u);
+            let offset = i * stride;
+            blob[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+        self.uniforms.encode_write(encoder, &blob);
+
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+        for i in 0..padded_len.ilog2() {
+            let step = 2u32 << i; // same as uniform
+            let active = padded_len as u32 / step;
+            let workgroups_needed = active.div_ceil(self.wg_size).max(1);
+            let [x, y, z] = split_dispatch_3d(workgroups_needed, max_dim);
+
+            match &self.input_pass {
+                Some(input_pass) if i == 0 => {
+                    pass.set_pipeline(&input_pass.pipeline);
+                    pass.set_bind_group(0, &input_pass.bind_group, &[]);
+                }
+                _ => {
+                    let offset_bytes = i * self.uniform_stride;
+                    pass.set_pipeline(&self.up_sweep_pipeline);
+                    pass.set_bind_group(0, &self.up_sweep_bind_group, &[offset_bytes]);
+                }
+            }
+            pass.dispatch_workgroups(x, y, z);
+        }
+    }
+
+    pub fn encode_set_last_zero(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+        pass.set_pipeline(&self.last_zero_pipeline);
+        pass.set_bind_group(0, &self.last_zero_bind_group, &[]);
+        pass.dispatch_workgroups(1, 1, 1);
+    }
+
+    pub fn encode_down_sweep(&self, encoder: &mut wgpu::CommandEncoder) {
+        let max_dim = self.device.limits().max_compute_workgroups_per_dimension;
+        let padded_len = self.padded_len();
+
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+        pass.set_pipeline(&self.down_sweep_pipeline);
+
+        for i in (0..padded_len.ilog2()).rev() {
+            let step = 2u32 << i; // same as uniform
+            let active = padded_len as u32 / step;
+            let workgroups_needed = active.div_ceil(self.wg_size).max(1);
+            let [x, y, z] = split_dispatch_3d(workgroups_needed, max_dim);
+
+            let offset_bytes = i * self.uniform_stride;
+            pass.set_bind_group(0, &self.down_sweep_bind_group, &[offset_bytes]);
+            pass.dispatch_workgroups(x, y, z);
+        }
+    }
+
+    pub fn encode_inclusive(&self, encoder: &mut wgpu::CommandEncoder) {
+        let Some(inclusive_pass) = &self.inclusive_pass else {
+            return;
+        };
+        let max_dim = self.device.limits().max_compute_workgroups_per_dimension;
+
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+        pass.set_pipeline(&inclusive_pass.pipeline);
+        pass.set_bind_group(0, &inclusive_pass.bind_group, &[]);
+        let workgroups_needed = (self.len() as u32).div_ceil(self.wg_size).max(1);
+        let [x, y, z] = split_dispatch_3d(workgroups_needed, max_dim);
+        pass.dispatch_workgroups(x, y, z);
+    }
+
+    /// Buffer the input is uploaded to.
+    pub fn input_buffer(&self) -> &wgpu::Buffer {
+        match &self.input_pass {
+            Some(input_pass) => &input_pass.input,
+            None => &self.data,
+        }
+    }
+
+    /// Buffer holding the result, padded to a power of two.
+    pub fn output_buffer(&self) -> &wgpu::Buffer {
+        &self.data
+    }
+
+    pub fn get_command_encoder(&self) -> wgpu::CommandEncoder {
+        self.device.create_command_encoder(&Default::default())
+    }
+
+    pub fn submit(&self, encoder: wgpu::CommandEncoder) {
+        self.queue.submit([encoder.finish()]);
+    }
+}
 
-        let (device, queue) = init_wgpu().await;
+impl<T: ScanElement> ScanBuffers for GlobalBlellochGpuContext<T> {
+    fn input_buffer(&self) -> &wgpu::Buffer {
+        GlobalBlellochGpuContext::input_buffer(self)
+    }
+
+    fn output_buffer(&self) -> &wgpu::Buffer {
+        GlobalBlellochGpuContext::output_buffer(self)
+    }
+}
+
+impl<T: ScanElement> PrefixScan<T> for GlobalBlellochGpuContext<T> {
+    fn from_device(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        capacity: usize,
+        config: &ScanConfig,
+    ) -> anyhow::Result<Self> {
+        let wg_size = config.workgroup_size.unwrap_or(DEFAULT_WG_SIZE);
+        check_workgroup_size(device, wg_size, 0)?;
+        let padded_capacity = capacity.max(1).next_power_of_two();
+        check_device(
+            device,
+            wgpu::Features::empty(),
+            (padded_capacity * size_of::<T>()) as u64,
+            padded_capacity.div_ceil(wg_size as usize) as u32,
+        )?;
+        check_op::<T>(config.op)?;
+
+        let prelude = wgsl_prelude::<T>(config.op);
+        let constants = [("WG_SIZE", wg_size as f64)];
+        let compilation_options = wgpu::PipelineCompilationOptions {
+            constants: &constants,
+            ..Default::default()
+        };
 
         let up_sweep_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("up-sweep shader"),
-            source: wgpu::ShaderSource::Wgsl(
-                include_str!("global_blelloch_scan_up_sweep.wgsl").into(),
-            ),
+            source: shader_source(&prelude, include_str!("global_blelloch_scan_up_sweep.wgsl")),
         });
 
         let last_zero_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("set-last_zero shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("set_last_zero.wgsl").into()),
+            source: shader_source(&prelude, include_str!("set_last_zero.wgsl")),
         });
 
         let down_sweep_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("down-sweep shader"),
-            source: wgpu::ShaderSource::Wgsl(
-                include_str!("global_blelloch_scan_down_sweep.wgsl").into(),
+            source: shader_source(
+                &prelude,
+                include_str!("global_blelloch_scan_down_sweep.wgsl"),
             ),
         });
 
@@ -108,7 +267,7 @@ impl GlobalBlellochGpuContext {
             layout: Some(&sweep_pipeline_layout),
             module: &up_sweep_shader,
             entry_point: None,
-            compilation_options: Default::default(),
+            compilation_options: compilation_options.clone(),
             cache: Default::default(),
         });
 
@@ -127,22 +286,23 @@ impl GlobalBlellochGpuContext {
                 layout: Some(&sweep_pipeline_layout),
                 module: &down_sweep_shader,
                 entry_point: None,
-                compilation_options: Default::default(),
+                compilation_options: compilation_options.clone(),
                 cache: Default::default(),
             });
 
-        let byte_len = (n * size_of::<u32>()) as u64;
+        let byte_len = (capacity * size_of::<T>()).max(4) as u64;
 
         let data = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("data"),
-            size: byte_len,
+            size: (padded_capacity * size_of::<T>()) as u64,
             usage: wgpu::BufferUsages::STORAGE
                 | wgpu::BufferUsages::COPY_DST
                 | wgpu::BufferUsages::COPY_SRC,
             mapped_at_creation: false,
         });
 
-        let max_steps = n.next_power_of_two().ilog2();
+        // One uniform per step of the longest scan, at least one for the other passes
+        let max_steps = padded_capacity.ilog2().max(1);
 
         // Calculate stride between uniforms in the aggregate buffer
         let align = device.limits().min_uniform_buffer_offset_alignment as usize;
@@ -150,33 +310,18 @@ impl GlobalBlellochGpuContext {
         let stride = align_up(uni_size, align);
         let uniform_stride = stride as u32;
 
-        // Create a byte array of the uniforms with the stride
-        let mut blob = vec![0u8; stride * (max_steps as usize)];
-        for i in 0..max_steps {
-            let u = Uniform {
-                step: 2u32 << i,
-                _pad: [0; 3],
-            };
-            let bytes = bytemuck::bytes_of(&u);
-            let offset = (i as usize) * stride;
-            blob[offset..offset + bytes.len()].copy_from_slice(bytes);
-        }
+        // The contents are written by every recorded scan
+        let uniforms = UniformBuffer::new(
+            device,
+            queue,
+            "uniform",
+            (stride * max_steps as usize) as u64,
+        );
 
-        let uniform = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("uniform"),
-            contents: &blob,
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
-        let readback = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("readback"),
-            size: byte_len,
-            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
-            mapped_at_creation: false,
-        });
+        let readback = ReadbackBuffer::new(device, byte_len);
 
         let uni_binding = wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-            buffer: &uniform,
+            buffer: uniforms.buffer(),
             offset: 0,
             size: NonZeroU64::new(uni_size as u64),
         });
@@ -196,13 +341,20 @@ impl GlobalBlellochGpuContext {
             ],
         });
 
+        // The lengths are the same in every step, so the other passes bind the first one
         let last_zero_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("bg0"),
             layout: &last_zero_pipeline.get_bind_group_layout(0),
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: data.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: data.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uni_binding.clone(),
+                },
+            ],
         });
 
         let down_sweep_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -215,139 +367,232 @@ impl GlobalBlellochGpuContext {
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: uni_binding,
+                    resource: uni_binding.clone(),
                 },
             ],
         });
 
-        Ok(Self {
-            device,
-            queue,
+        let input_pass = config.out_of_place.then(|| {
+            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("up-sweep input shader"),
+                source: shader_source(
+                    &prelude,
+                    include_str!("global_blelloch_scan_up_sweep_input.wgsl"),
+                ),
+            });
+            let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("up-sweep input pipeline"),
+                layout: None,
+                module: &shader,
+                entry_point: None,
+                compilation_options: compilation_options.clone(),
+                cache: Default::default(),
+            });
+            let input = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("input"),
+                size: byte_len,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_DST
+                    | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            });
+            // The first uniform is the one of the first step
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("up-sweep input bind group"),
+                layout: &pipeline.get_bind_group_layout(0),
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: data.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: uni_binding.clone(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: input.as_entire_binding(),
+                    },
+                ],
+            });
+            InputPass {
+                pipeline,
+                bind_group,
+                input,
+            }
+        });
+
+        let inclusive_pass = (config.kind == ScanKind::Inclusive).then(|| {
+            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("inclusive shader"),
+                source: shader_source(
+                    &prelude,
+                    include_str!("global_blelloch_scan_inclusive.wgsl"),
+                ),
+            });
+            let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("inclusive pipeline"),
+                layout: None,
+                module: &shader,
+                entry_point: None,
+                compilation_options: compilation_options.clone(),
+                cache: Default::default(),
+            });
+            // The out-of-place scan still has the input, so only the in-place one keeps a copy
+            let input = match &input_pass {
+                Some(input_pass) => input_pass.input.clone(),
+                None => device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("input copy"),
+                    size: byte_len,
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+            };
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("inclusive bind group"),
+                layout: &pipeline.get_bind_group_layout(0),
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: data.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: input.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: uni_binding,
+                    },
+                ],
+            });
+            InclusivePass {
+                pipeline,
+                bind_group,
+                input,
+            }
+        });
+
+        let context = Self {
+            device: device.clone(),
+            queue: queue.clone(),
             up_sweep_pipeline,
             last_zero_pipeline,
             down_sweep_pipeline,
             up_sweep_bind_group,
             last_zero_bind_group,
             down_sweep_bind_group,
+            inclusive_pass,
+            input_pass,
             data,
-            uniform,
             readback,
-            n,
-            max_steps,
+            uniforms,
+            wg_size,
+            capacity,
+            len: AtomicUsize::new(capacity),
             uniform_stride,
-        })
+            _marker: PhantomData,
+        };
+        context.set_len(capacity)?;
+        Ok(context)
     }
 
-    pub fn upload_data(&self, input: &[u32]) {
-        self.queue
-            .write_buffer(&self.data, 0, bytemuck::cast_slice(input));
+    fn capacity(&self) -> usize {
+        self.capacity
     }
 
-    pub fn read_computed_data(&self) -> anyhow::Result<Vec<u32>> {
-        // Copy the result buffer to the temp buffer to bring the data to the CPU land
-        let mut encoder = self.device.create_command_encoder(&Default::default());
-        encoder.copy_buffer_to_buffer(&self.data, 0, &self.readback, 0, self.data.size());
-
-        self.queue.submit([encoder.finish()]);
-
-        let slice = self.readback.slice(..);
-        // The mapping process is async, so we'll need to create a channel to get
-        // the success flag for our mapping
-        let (tx, rx) = channel();
-
-        // We send the success or failure of our mapping via a callback
-        slice.map_async(wgpu::MapMode::Read, move |result| {
-            tx.send(result).unwrap();
-        });
-
-        // The callback we submitted to map async will only get called after the
-        // device is polled or the queue submitted
-        self.device.poll(wgpu::PollType::wait_indefinitely())?;
-
-        // We check if the mapping was successful here
-        rx.recv()??;
-
-        // We then get the bytes that were stored in the buffer
-        let bytes = slice.get_mapped_range();
-        let out_u32: &[u32] = bytemuck::cast_slice(&bytes);
-        let v = out_u32.to_vec();
-
-        drop(bytes);
-        self.readback.unmap();
-
-        Ok(v)
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
     }
 
-    pub fn encode_up_sweep(&self, encoder: &mut wgpu::CommandEncoder) {
-        const WG_SIZE: u32 = 64;
-        let max_dim = self.device.limits().max_compute_workgroups_per_dimension;
+    fn set_len(&self, len: usize) -> anyhow::Result<()> {
+        check_len(self.capacity, len)?;
+        self.len.store(len, Ordering::Relaxed);
+        Ok(())
+    }
 
-        let mut pass = encoder.begin_compute_pass(&Default::default());
-        pass.set_pipeline(&self.up_sweep_pipeline);
-        for i in 0..self.max_steps {
-            let step = 2u32 << i; // same as uniform
-            let active = self.n as u32 / step;
-            let workgroups_needed = active.div_ceil(WG_SIZE).max(1);
-            let [x, y, z] = split_dispatch_3d(workgroups_needed, max_dim);
+    fn upload_data(&self, input: &[T]) -> anyhow::Result<()> {
+        self.set_len(input.len())?;
+        self.queue
+            .write_buffer(self.input_buffer(), 0, bytemuck::cast_slice(input));
+        Ok(())
+    }
 
-            let offset_bytes = i * self.uniform_stride;
-            pass.set_bind_group(0, &self.up_sweep_bind_group, &[offset_bytes]);
-            pass.dispatch_workgroups(x, y, z);
+    fn encode_scan(&self, encoder: &mut wgpu::CommandEncoder) {
+        let len = self.len();
+        if len == 0 {
+            return;
+        }
+        if let (Some(inclusive_pass), None) = (&self.inclusive_pass, &self.input_pass) {
+            encoder.copy_buffer_to_buffer(
+                &self.data,
+                0,
+                &inclusive_pass.input,
+                0,
+                (len * size_of::<T>()) as u64,
+            );
         }
+        self.encode_up_sweep(encoder);
+        self.encode_set_last_zero(encoder);
+        self.encode_down_sweep(encoder);
+        self.encode_inclusive(encoder);
     }
 
-    pub fn encode_set_last_zero(&self, encoder: &mut wgpu::CommandEncoder) {
-        let mut pass = encoder.begin_compute_pass(&Default::default());
-        pass.set_pipeline(&self.last_zero_pipeline);
-        pass.set_bind_group(0, &self.last_zero_bind_group, &[]);
-        pass.dispatch_workgroups(1, 1, 1);
+    fn encode_scan_buffer(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        input: BufferRange<'_>,
+        output: Option<BufferRange<'_>>,
+    ) -> anyhow::Result<()> {
+        let output = check_buffer_ranges::<T>(input, output)?;
+        self.set_len(input.len)?;
+        if input.len == 0 {
+            return Ok(());
+        }
+        // The sweeps run over the data padded to a power of two, which does not fit into the
+        // caller's range, so unlike the other scans the ranges are always copied
+        let byte_len = (input.len * size_of::<T>()) as u64;
+        encoder.copy_buffer_to_buffer(input.buffer, input.offset, self.input_buffer(), 0, byte_len);
+        self.encode_scan(encoder);
+        encoder.copy_buffer_to_buffer(&self.data, 0, output.buffer, output.offset, byte_len);
+        Ok(())
     }
 
-    pub fn encode_down_sweep(&self, encoder: &mut wgpu::CommandEncoder) {
-        const WG_SIZE: u32 = 64;
-        let max_dim = self.device.limits().max_compute_workgroups_per_dimension;
-
-        let mut pass = encoder.begin_compute_pass(&Default::default());
-        pass.set_pipeline(&self.down_sweep_pipeline);
-
-        for i in (0..self.max_steps).rev() {
-            let step = 2u32 << i; // same as uniform
-            let active = self.n as u32 / step;
-            let workgroups_needed = active.div_ceil(WG_SIZE).max(1);
-            let [x, y, z] = split_dispatch_3d(workgroups_needed, max_dim);
-
-            let offset_bytes = i * self.uniform_stride;
-            pass.set_bind_group(0, &self.down_sweep_bind_group, &[offset_bytes]);
-            pass.dispatch_workgroups(x, y, z);
-        }
+    fn run_prefix_scan(&self) {
+        let mut encoder = self.get_command_encoder();
+        self.encode_scan(&mut encoder);
+        self.submit(encoder);
     }
 
-    pub fn get_command_encoder(&self) -> wgpu::CommandEncoder {
-        self.device.create_command_encoder(&Default::default())
+    fn read_computed_data(&self) -> anyhow::Result<Vec<T>> {
+        read_buffer(
+            &self.device,
+            &self.queue,
+            &self.data,
+            &self.readback,
+            self.len(),
+        )
     }
 
-    pub fn run_prefix_sum(&self) {
-        let mut encoder = self.device.create_command_encoder(&Default::default());
-        self.encode_up_sweep(&mut encoder);
-        self.encode_set_last_zero(&mut encoder);
-        self.encode_down_sweep(&mut encoder);
-        self.submit(encoder);
+    fn read_computed_data_async(&self) -> Readback<T> {
+        Readback::new(
+            &self.device,
+            &self.queue,
+            &self.data,
+            &self.readback,
+            self.len(),
+        )
     }
-    pub fn submit(&self, encoder: wgpu::CommandEncoder) {
-        self.queue.submit([encoder.finish()]);
+
+    fn wait_for_previous_submit(&self) -> anyhow::Result<()> {
+        wait_for_previous_submit(&self.device, &self.queue)
     }
 
-    pub fn wait_for_previous_submit(&self) -> anyhow::Result<()> {
-        let (tx, rx) = channel();
-        self.queue.on_submitted_work_done(move || {
-            let _ = tx.send(());
-        });
-        self.device.poll(wgpu::PollType::wait_indefinitely())?;
-        let _ = rx.recv();
-        Ok(())
+    fn wait_for_previous_submit_async(&self) -> WorkDone {
+        WorkDone::new(&self.device, &self.queue)
     }
 
-    pub fn wait_idle(&self) -> anyhow::Result<()> {
+    fn wait_idle(&self) -> anyhow::Result<()> {
         self.device.poll(wgpu::PollType::wait_indefinitely())?;
         Ok(())
     }
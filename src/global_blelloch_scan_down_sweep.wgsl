@@ -1,21 +1,28 @@
+// `Elem`, `identity()` and `combine(a, b)` of the scan operator are declared by the prelude
+// which is prepended on the host.
+// Invocations per workgroup, a power of two
+override WG_SIZE: u32 = 64u;
+
 struct Uniform {
   step: u32, // this has to be a power of 2
+  // Length of the scan padded to a power of 2, the buffer may be longer
+  padded_len: u32,
 };
 
-@group(0) @binding(0) var<storage, read_write> data: array<u32>;
+@group(0) @binding(0) var<storage, read_write> data: array<Elem>;
 @group(0) @binding(1) var<uniform> uni: Uniform;
 
 @compute
-@workgroup_size(64)
+@workgroup_size(WG_SIZE)
 fn main(
   @builtin(global_invocation_id) gid: vec3<u32>,
   @builtin(num_workgroups) nwg: vec3<u32>,
 ) {
-    let n = arrayLength(&data);
+    let n = uni.padded_len;
         let step = uni.step;
         let half = step >> 1u;
 
-        let width = nwg.x * 64u;
+        let width = nwg.x * WG_SIZE;
         let plane = width * nwg.y;
         let t = gid.x + gid.y * width + gid.z * plane;
 
@@ -27,6 +34,6 @@ fn main(
         let prev = i - half;
 
         let left = data[i];
-        data[i] = data[i] + data[prev];
+        data[i] = combine(data[i], data[prev]);
         data[prev] = left;
 }
\ No newline at end of file
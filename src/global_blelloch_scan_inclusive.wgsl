@@ -0,0 +1,31 @@
+// `Elem`, `identity()` and `combine(a, b)` of the scan operator are declared by the prelude
+// which is prepended on the host.
+// Invocations per workgroup, a power of two
+override WG_SIZE: u32 = 64u;
+
+struct Uniform {
+  step: u32,
+  padded_len: u32,
+  // Number of elements to scan, the buffers may be longer
+  len: u32,
+};
+
+@group(0) @binding(0) var<storage, read_write> data: array<Elem>;
+@group(0) @binding(1) var<storage, read> input: array<Elem>;
+@group(0) @binding(2) var<uniform> uni: Uniform;
+
+@compute
+@workgroup_size(WG_SIZE)
+fn main(
+  @builtin(global_invocation_id) gid: vec3<u32>,
+  @builtin(num_workgroups) nwg: vec3<u32>,
+) {
+    let n = uni.len;
+    let width = nwg.x * WG_SIZE;
+    let plane = width * nwg.y;
+    let i = gid.x + gid.y * width + gid.z * plane;
+    if (i >= n) { return; }
+
+    // Combining with the element itself turns the exclusive scan into the inclusive one
+    data[i] = combine(data[i], input[i]);
+}
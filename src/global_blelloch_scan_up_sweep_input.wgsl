@@ -0,0 +1,40 @@
+// `Elem`, `identity()` and `combine(a, b)` of the scan operator are declared by the prelude
+// which is prepended on the host.
+// Invocations per workgroup, a power of two
+override WG_SIZE: u32 = 64u;
+
+struct Uniform {
+  step: u32, // always 2, this pass replaces the first up-sweep step
+  padded_len: u32,
+  // Number of elements in the input, the padding past it reads as the identity
+  len: u32,
+};
+
+@group(0) @binding(0) var<storage, read_write> data: array<Elem>;
+@group(0) @binding(1) var<uniform> uni: Uniform;
+@group(0) @binding(2) var<storage, read> input: array<Elem>;
+
+fn load_input(i: u32) -> Elem {
+    if (i < uni.len) {
+        return input[i];
+    }
+    return identity();
+}
+
+// First up-sweep step of the out-of-place scan. It reads the pairs from the input instead of the
+// data, and also writes the left element of each pair which the step leaves untouched.
+@compute
+@workgroup_size(WG_SIZE)
+fn main(
+  @builtin(global_invocation_id) gid: vec3<u32>,
+  @builtin(num_workgroups) nwg: vec3<u32>,
+) {
+    let width = nwg.x * WG_SIZE;
+    let plane = width * nwg.y;
+    let t = gid.x + gid.y * width + gid.z * plane;
+    if (t >= uni.padded_len / 2u) { return; }
+
+    let left = load_input(2u * t);
+    data[2u * t] = left;
+    data[2u * t + 1u] = combine(left, load_input(2u * t + 1u));
+}
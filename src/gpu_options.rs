@@ -0,0 +1,107 @@
+use crate::scan_error::ScanError;
+
+/// How a context picks the adapter when it creates its own device.
+///
+/// The defaults match a plain `wgpu` setup preferring a high-performance adapter, e.g. the
+/// discrete GPU of a laptop with two GPUs. A software adapter such as llvmpipe or lavapipe can be
+/// targeted with [`with_force_fallback_adapter`](Self::with_force_fallback_adapter) or an
+/// adapter name filter.
+#[derive(Clone, Debug)]
+pub struct GpuOptions {
+    /// Backends the instance is allowed to use.
+    pub backends: wgpu::Backends,
+    pub power_preference: wgpu::PowerPreference,
+    /// Only pick an adapter which is implemented in software.
+    pub force_fallback_adapter: bool,
+    /// Pick the first adapter whose name contains this string, ignoring case. Takes precedence
+    /// over the power preference.
+    pub adapter_name: Option<String>,
+}
+
+impl Default for GpuOptions {
+    fn default() -> Self {
+        Self {
+            backends: wgpu::Backends::all(),
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter: false,
+            adapter_name: None,
+        }
+    }
+}
+
+impl GpuOptions {
+    pub fn with_backends(mut self, backends: wgpu::Backends) -> Self {
+        self.backends = backends;
+        self
+    }
+
+    pub fn with_power_preference(mut self, power_preference: wgpu::PowerPreference) -> Self {
+        self.power_preference = power_preference;
+        self
+    }
+
+    pub fn with_force_fallback_adapter(mut self, force_fallback_adapter: bool) -> Self {
+        self.force_fallback_adapter = force_fallback_adapter;
+        self
+    }
+
+    pub fn with_adapter_name(mut self, adapter_name: impl Into<String>) -> Self {
+        self.adapter_name = Some(adapter_name.into());
+        self
+    }
+
+    /// Override the options with the environment variables which are set:
+    ///
+    /// - `WGPU_BACKEND`: comma separated list of backends, e.g. `vulkan,gl`.
+    /// - `WGPU_POWER_PREF`: `low`, `high` or `none`.
+    /// - `WGPU_FORCE_FALLBACK_ADAPTER`: `1` or `true` to force a software adapter.
+    /// - `WGPU_ADAPTER_NAME`: adapter name filter.
+    pub fn with_env_overrides(mut self) -> Self {
+        if let Some(backends) = wgpu::Backends::from_env() {
+            self.backends = backends;
+        }
+        if let Some(power_preference) = wgpu::PowerPreference::from_env() {
+            self.power_preference = power_preference;
+        }
+        if let Ok(force_fallback) = std::env::var("WGPU_FORCE_FALLBACK_ADAPTER") {
+            self.force_fallback_adapter = matches!(force_fallback.as_str(), "1" | "true");
+        }
+        if let Ok(adapter_name) = std::env::var("WGPU_ADAPTER_NAME") {
+            self.adapter_name = Some(adapter_name);
+        }
+        self
+    }
+
+    /// Pick the adapter, which can also be used to query its capabilities before creating a
+    /// context, see [`PrefixScan::is_supported`](crate::prefix_scan::PrefixScan::is_supported).
+    pub async fn request_adapter(&self) -> Result<wgpu::Adapter, ScanError> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: self.backends,
+            ..Default::default()
+        });
+        match &self.adapter_name {
+            Some(name) => {
+                let filter = name.to_lowercase();
+                instance
+                    .enumerate_adapters(self.backends)
+                    .await
+                    .into_iter()
+                    .find(|adapter| {
+                        let info = adapter.get_info();
+                        info.name.to_lowercase().contains(&filter)
+                            && (!self.force_fallback_adapter
+                                || info.device_type == wgpu::DeviceType::Cpu)
+                    })
+                    .ok_or_else(|| ScanError::NoMatchingAdapter(name.clone()))
+            }
+            None => instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: self.power_preference,
+                    compatible_surface: None,
+                    force_fallback_adapter: self.force_fallback_adapter,
+                })
+                .await
+                .map_err(ScanError::NoAdapter),
+        }
+    }
+}
@@ -1,16 +1,47 @@
-use crate::utils::{align_up, init_wgpu};
+use crate::prefix_scan::{BufferRange, PrefixScan, ScanBuffers, ScanConfig, ScanKind};
+use crate::readback::{Readback, ReadbackBuffer, WorkDone};
+use crate::scan_element::ScanElement;
+use crate::scan_op::{check_op, wgsl_prelude};
+use crate::utils::{
+    BindGroupCache, StorageBinding, UniformBuffer, align_up, can_bind_range, check_buffer_ranges,
+    check_device, check_len, check_workgroup_size, read_buffer, shader_source,
+    wait_for_previous_submit,
+};
+use std::marker::PhantomData;
 use std::num::NonZeroU64;
-use std::sync::mpsc::channel;
-use wgpu::util::DeviceExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const DEFAULT_WG_SIZE: u32 = 64;
 
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct Uniforms {
     step: u32,
-    _pad: [u32; 3],
+    len: u32,
+    _pad: [u32; 2],
+}
+
+/// Separate output of the out-of-place scan. The steps alternate between `data1` and the output
+/// such that the last one writes the output, and the first one reads the input in `data0`.
+struct OutOfPlace {
+    output: wgpu::Buffer,
+    input_to_output: wgpu::BindGroup,
+    data1_to_output: wgpu::BindGroup,
+    output_to_data1: wgpu::BindGroup,
+}
+
+/// Steps of a buffer scan which read the caller's input range and write the output range, going
+/// through `data0` and `data1` in between.
+#[derive(Clone)]
+struct RangeSteps {
+    // `None` if both ranges are in one buffer, which a step cannot read and write at once
+    input_to_output: Option<wgpu::BindGroup>,
+    input_to_data0: wgpu::BindGroup,
+    data0_to_output: wgpu::BindGroup,
+    data1_to_output: wgpu::BindGroup,
 }
 
-pub struct HillisSteeleGpuContext {
+pub struct HillisSteeleGpuContext<T: ScanElement = u32> {
     device: wgpu::Device,
     pub queue: wgpu::Queue,
     pipeline: wgpu::ComputePipeline,
@@ -18,19 +49,194 @@ pub struct HillisSteeleGpuContext {
     bind_group_1: wgpu::BindGroup,
     data0: wgpu::Buffer,
     data1: wgpu::Buffer,
-    readback: wgpu::Buffer,
-    n: usize,
-    max_steps: u32,
+    out_of_place: Option<OutOfPlace>,
+    range_steps: BindGroupCache<RangeSteps>,
+    readback: ReadbackBuffer,
+    uniforms: UniformBuffer,
+    kind: ScanKind,
+    wg_size: u32,
+    capacity: usize,
+    len: AtomicUsize,
     uniform_stride: u32,
+    _marker: PhantomData<T>,
+}
+
+impl<T: ScanElement> HillisSteeleGpuContext<T> {
+    /// Number of steps needed to scan `len` elements.
+    fn steps(&self, len: usize) -> u32 {
+        let steps = len.next_power_of_two().ilog2();
+        // The exclusive scan shifts the input in the first step, so it needs one even for len = 1
+        if self.kind == ScanKind::Exclusive && len > 0 {
+            steps.max(1)
+        } else {
+            steps
+        }
+    }
+
+    /// Bind group of step `i` out of `steps`.
+    fn bind_group(&self, i: u32, steps: u32) -> &wgpu::BindGroup {
+        match &self.out_of_place {
+            None if i.is_multiple_of(2) => &self.bind_group_0,
+            None => &self.bind_group_1,
+            Some(out_of_place) => match (i == 0, (steps - 1 - i).is_multiple_of(2)) {
+                (true, true) => &out_of_place.input_to_output,
+                (true, false) => &self.bind_group_0,
+                (false, true) => &out_of_place.data1_to_output,
+                (false, false) => &out_of_place.output_to_data1,
+            },
+        }
+    }
+
+    /// Bind group of a step which reads `src` and writes `dst`.
+    fn create_bind_group(
+        &self,
+        label: &str,
+        src: wgpu::BindingResource<'_>,
+        dst: wgpu::BindingResource<'_>,
+    ) -> wgpu::BindGroup {
+        self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(label),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: src,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: dst,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: self.uniforms.buffer(),
+                        offset: 0,
+                        size: NonZeroU64::new(size_of::<Uniforms>() as u64),
+                    }),
+                },
+            ],
+        })
+    }
+
+    /// Bind groups of the steps of a buffer scan from `input` to `output`.
+    fn range_steps(&self, input: &StorageBinding, output: &StorageBinding) -> RangeSteps {
+        self.range_steps
+            .get_or_create(output, Some(input), || RangeSteps {
+                input_to_output: (input.buffer() != output.buffer()).then(|| {
+                    self.create_bind_group(
+                        "input to output bg",
+                        input.resource(),
+                        output.resource(),
+                    )
+                }),
+                input_to_data0: self.create_bind_group(
+                    "input to data0 bg",
+                    input.resource(),
+                    self.data0.as_entire_binding(),
+                ),
+                data0_to_output: self.create_bind_group(
+                    "data0 to output bg",
+                    self.data0.as_entire_binding(),
+                    output.resource(),
+                ),
+                data1_to_output: self.create_bind_group(
+                    "data1 to output bg",
+                    self.data1.as_entire_binding(),
+                    output.resource(),
+                ),
+            })
+    }
+
+    /// Record a write of the uniforms of every step of a scan over `len` elements into
+    /// `encoder`. Each scan writes its own, so scans of different lengths can share an encoder.
+    fn encode_uniforms(&self, encoder: &mut wgpu::CommandEncoder, len: usize) {
+        // Create a byte array of the uniforms with the stride
+        let stride = self.uniform_stride as usize;
+        let mut blob = vec![0u8; self.uniforms.buffer().size() as usize];
+        for i in 0..blob.len() / stride {
+            let u = Uniforms {
+                step: 1u32 << i,
+                len: len as u32,
+                _pad: [0; 2],
+            };
+            let bytes = bytemuck::bytes_of(&u);
+            let offset = i * stride;
+            blob[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+        self.uniforms.encode_write(encoder, &blob);
+    }
+
+    /// Workgroups of a step over `len` elements.
+    fn dispatch_size(&self, len: usize) -> [u32; 2] {
+        let workgroups_needed = len.div_ceil(self.wg_size as usize) as u32;
+        let max_dim = self.device.limits().max_compute_workgroups_per_dimension;
+        let x = workgroups_needed.min(max_dim);
+        let y = workgroups_needed.div_ceil(x);
+        [x, y]
+    }
+
+    /// Buffer the input is uploaded to.
+    pub fn input_buffer(&self) -> &wgpu::Buffer {
+        &self.data0
+    }
+
+    /// Buffer holding the result. Unless the scan is out of place, this is whichever buffer the
+    /// last step has written to, which depends on the length.
+    pub fn output_buffer(&self) -> &wgpu::Buffer {
+        if let Some(out_of_place) = &self.out_of_place {
+            return &out_of_place.output;
+        }
+        let steps = self.steps(self.len());
+        if steps == 0 {
+            &self.data0
+        } else if (steps - 1).is_multiple_of(2) {
+            &self.data1
+        } else {
+            &self.data0
+        }
+    }
+
+    pub fn get_command_encoder(&self) -> wgpu::CommandEncoder {
+        self.device.create_command_encoder(&Default::default())
+    }
+
+    pub fn submit(&self, encoder: wgpu::CommandEncoder) {
+        self.queue.submit([encoder.finish()]);
+    }
+}
+
+impl<T: ScanElement> ScanBuffers for HillisSteeleGpuContext<T> {
+    fn input_buffer(&self) -> &wgpu::Buffer {
+        HillisSteeleGpuContext::input_buffer(self)
+    }
+
+    fn output_buffer(&self) -> &wgpu::Buffer {
+        HillisSteeleGpuContext::output_buffer(self)
+    }
 }
 
-impl HillisSteeleGpuContext {
-    pub async fn new(n: usize) -> anyhow::Result<Self> {
-        let (device, queue) = init_wgpu().await;
+impl<T: ScanElement> PrefixScan<T> for HillisSteeleGpuContext<T> {
+    fn from_device(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        capacity: usize,
+        config: &ScanConfig,
+    ) -> anyhow::Result<Self> {
+        let wg_size = config.workgroup_size.unwrap_or(DEFAULT_WG_SIZE);
+        check_workgroup_size(device, wg_size, 0)?;
+        check_device(
+            device,
+            wgpu::Features::empty(),
+            (capacity * size_of::<T>()) as u64,
+            capacity.div_ceil(wg_size as usize) as u32,
+        )?;
+        check_op::<T>(config.op)?;
+
+        let prelude = wgsl_prelude::<T>(config.op);
 
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: None,
-            source: wgpu::ShaderSource::Wgsl(include_str!("hillis_steele_scan.wgsl").into()),
+            source: shader_source(&prelude, include_str!("hillis_steele_scan.wgsl")),
         });
 
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -84,11 +290,18 @@ impl HillisSteeleGpuContext {
             layout: Some(&pipeline_layout),
             module: &shader,
             entry_point: None,
-            compilation_options: Default::default(),
+            compilation_options: wgpu::PipelineCompilationOptions {
+                constants: &[
+                    ("INCLUSIVE", config.kind.inclusive_constant()),
+                    ("WG_SIZE", wg_size as f64),
+                ],
+                ..Default::default()
+            },
             cache: Default::default(),
         });
 
-        let byte_len = (n * size_of::<u32>()) as u64;
+        // Zero-sized buffers cannot be bound, so keep at least one element
+        let byte_len = (capacity * size_of::<T>()).max(4) as u64;
 
         let data0 = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("data0"),
@@ -107,7 +320,9 @@ impl HillisSteeleGpuContext {
             mapped_at_creation: false,
         });
 
-        let max_steps = n.next_power_of_two().ilog2();
+        // One uniform per step of the longest scan, at least two for the buffer scans which go
+        // through one of the context's buffers
+        let max_steps = capacity.next_power_of_two().ilog2().max(2);
 
         // Calculate stride between uniforms in the aggregate buffer
         let align = device.limits().min_uniform_buffer_offset_alignment as usize;
@@ -115,33 +330,18 @@ impl HillisSteeleGpuContext {
         let stride = align_up(uni_size, align);
         let uniform_stride = stride as u32;
 
-        // Create a byte array of the uniforms with the stride
-        let mut blob = vec![0u8; stride * (max_steps as usize)];
-        for i in 0..max_steps {
-            let u = Uniforms {
-                step: 1u32 << i,
-                _pad: [0; 3],
-            };
-            let bytes = bytemuck::bytes_of(&u);
-            let offset = (i as usize) * stride;
-            blob[offset..offset + bytes.len()].copy_from_slice(bytes);
-        }
-
-        let uniform = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("uniform"),
-            contents: &blob,
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+        // The contents are written by every recorded scan
+        let uniforms = UniformBuffer::new(
+            device,
+            queue,
+            "uniform",
+            (stride * max_steps as usize) as u64,
+        );
 
-        let readback = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("readback"),
-            size: byte_len,
-            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
-            mapped_at_creation: false,
-        });
+        let readback = ReadbackBuffer::new(device, byte_len);
 
         let uni_binding = wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-            buffer: &uniform,
+            buffer: uniforms.buffer(),
             offset: 0,
             size: NonZeroU64::new(uni_size as u64),
         });
@@ -179,109 +379,214 @@ impl HillisSteeleGpuContext {
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
-                    resource: uni_binding,
+                    resource: uni_binding.clone(),
                 },
             ],
         });
 
-        Ok(Self {
-            device,
-            queue,
+        let out_of_place = config.out_of_place.then(|| {
+            let output = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("output"),
+                size: byte_len,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_DST
+                    | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            });
+            let create_bind_group = |label: &str, src: &wgpu::Buffer, dst: &wgpu::Buffer| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some(label),
+                    layout: &pipeline.get_bind_group_layout(0),
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: src.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: dst.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: uni_binding.clone(),
+                        },
+                    ],
+                })
+            };
+            OutOfPlace {
+                input_to_output: create_bind_group("input to output bg", &data0, &output),
+                data1_to_output: create_bind_group("data1 to output bg", &data1, &output),
+                output_to_data1: create_bind_group("output to data1 bg", &output, &data1),
+                output,
+            }
+        });
+
+        let context = Self {
+            device: device.clone(),
+            queue: queue.clone(),
             pipeline,
             bind_group_0,
             bind_group_1,
             data0,
             data1,
+            out_of_place,
+            range_steps: BindGroupCache::new(),
             readback,
-            n,
-            max_steps,
+            uniforms,
+            kind: config.kind,
+            wg_size,
+            capacity,
+            len: AtomicUsize::new(capacity),
             uniform_stride,
-        })
+            _marker: PhantomData,
+        };
+        context.set_len(capacity)?;
+        Ok(context)
     }
 
-    pub fn upload_data(&self, input: &[u32]) {
-        self.queue
-            .write_buffer(&self.data0, 0, bytemuck::cast_slice(input));
+    fn capacity(&self) -> usize {
+        self.capacity
     }
 
-    pub fn read_computed_data(&self) -> anyhow::Result<Vec<u32>> {
-        // Copy the result buffer to the temp buffer to bring the data to the CPU land
-        let result_buf = if self.max_steps == 0 {
-            &self.data0
-        } else if (self.max_steps - 1) % 2 == 0 {
-            &self.data1
-        } else {
-            &self.data0
-        };
-        let mut encoder = self.device.create_command_encoder(&Default::default());
-        encoder.copy_buffer_to_buffer(&result_buf, 0, &self.readback, 0, self.data0.size());
-
-        self.queue.submit([encoder.finish()]);
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
 
-        let slice = self.readback.slice(..);
-        // The mapping process is async, so we'll need to create a channel to get
-        // the success flag for our mapping
-        let (tx, rx) = channel();
+    fn set_len(&self, len: usize) -> anyhow::Result<()> {
+        check_len(self.capacity, len)?;
+        self.len.store(len, Ordering::Relaxed);
+        Ok(())
+    }
 
-        // We send the success or failure of our mapping via a callback
-        slice.map_async(wgpu::MapMode::Read, move |result| {
-            tx.send(result).unwrap();
-        });
+    fn upload_data(&self, input: &[T]) -> anyhow::Result<()> {
+        self.set_len(input.len())?;
+        self.queue
+            .write_buffer(&self.data0, 0, bytemuck::cast_slice(input));
+        Ok(())
+    }
 
-        // The callback we submitted to map async will only get called after the
-        // device is polled or the queue submitted
-        self.device.poll(wgpu::PollType::wait_indefinitely())?;
+    fn encode_scan(&self, encoder: &mut wgpu::CommandEncoder) {
+        let len = self.len();
+        if len == 0 {
+            return;
+        }
+        let steps = self.steps(len);
+        if steps == 0 {
+            // A single element is its own inclusive scan, it only has to reach the output.
+            if let Some(out_of_place) = &self.out_of_place {
+                let byte_len = size_of::<T>() as u64;
+                encoder.copy_buffer_to_buffer(&self.data0, 0, &out_of_place.output, 0, byte_len);
+            }
+            return;
+        }
+        self.encode_uniforms(encoder, len);
+
+        let [x, y] = self.dispatch_size(len);
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+        pass.set_pipeline(&self.pipeline);
+        for i in 0..steps {
+            let offset_bytes = i * self.uniform_stride;
+            pass.set_bind_group(0, self.bind_group(i, steps), &[offset_bytes]);
+            pass.dispatch_workgroups(x, y, 1);
+        }
+    }
 
-        // We check if the mapping was successful here
-        rx.recv()??;
+    fn encode_scan_buffer(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        input: BufferRange<'_>,
+        output: Option<BufferRange<'_>>,
+    ) -> anyhow::Result<()> {
+        let output = check_buffer_ranges::<T>(input, output)?;
+        self.set_len(input.len)?;
+        if input.len == 0 {
+            return Ok(());
+        }
+        let byte_len = (input.len * size_of::<T>()) as u64;
+        if !can_bind_range(&self.device, input, 1) || !can_bind_range(&self.device, output, 1) {
+            encoder.copy_buffer_to_buffer(input.buffer, input.offset, &self.data0, 0, byte_len);
+            self.encode_scan(encoder);
+            encoder.copy_buffer_to_buffer(
+                self.output_buffer(),
+                0,
+                output.buffer,
+                output.offset,
+                byte_len,
+            );
+            return Ok(());
+        }
 
-        // We then get the bytes that were stored in the buffer
-        let bytes = slice.get_mapped_range();
-        let out_u32: &[u32] = bytemuck::cast_slice(&bytes);
-        let v = out_u32.to_vec();
+        let mut steps = self.steps(input.len);
+        let in_place = input.buffer == output.buffer && input.offset == output.offset;
+        if steps == 0 && in_place {
+            return Ok(());
+        }
+        let range_steps = self.range_steps(
+            &StorageBinding::of_range::<T>(input),
+            &StorageBinding::of_range::<T>(output),
+        );
+        // The steps past the ones the length needs copy the elements, so ranges of one buffer
+        // are scanned through `data0`
+        steps = steps.max(match range_steps.input_to_output {
+            Some(_) => 1,
+            None => 2,
+        });
+        self.encode_uniforms(encoder, input.len);
+
+        let [x, y] = self.dispatch_size(input.len);
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+        pass.set_pipeline(&self.pipeline);
+        for i in 0..steps {
+            // Step i > 0 reads `data0` if i is odd and `data1` otherwise
+            let bind_group = match (i, i == steps - 1) {
+                (0, true) => range_steps.input_to_output.as_ref().unwrap(),
+                (0, false) => &range_steps.input_to_data0,
+                (i, true) if i % 2 == 1 => &range_steps.data0_to_output,
+                (_, true) => &range_steps.data1_to_output,
+                (i, false) if i % 2 == 1 => &self.bind_group_0,
+                (_, false) => &self.bind_group_1,
+            };
+            pass.set_bind_group(0, bind_group, &[i * self.uniform_stride]);
+            pass.dispatch_workgroups(x, y, 1);
+        }
+        Ok(())
+    }
 
-        drop(bytes);
-        self.readback.unmap();
+    fn run_prefix_scan(&self) {
+        let mut encoder = self.get_command_encoder();
+        self.encode_scan(&mut encoder);
+        self.submit(encoder);
+    }
 
-        Ok(v)
+    fn read_computed_data(&self) -> anyhow::Result<Vec<T>> {
+        read_buffer(
+            &self.device,
+            &self.queue,
+            self.output_buffer(),
+            &self.readback,
+            self.len(),
+        )
     }
 
-    pub fn run_prefix_scan(&self) {
-        const WG_SIZE: u32 = 64;
-        let workgroups_needed = self.n.div_ceil(WG_SIZE as usize) as u32;
+    fn read_computed_data_async(&self) -> Readback<T> {
+        Readback::new(
+            &self.device,
+            &self.queue,
+            self.output_buffer(),
+            &self.readback,
+            self.len(),
+        )
+    }
 
-        let max_dim = self.device.limits().max_compute_workgroups_per_dimension;
-        let x = workgroups_needed.min(max_dim);
-        let y = (workgroups_needed + x - 1) / x;
-        let mut encoder = self.device.create_command_encoder(&Default::default());
-        {
-            let mut pass = encoder.begin_compute_pass(&Default::default());
-            pass.set_pipeline(&self.pipeline);
-            for i in 0..self.max_steps {
-                let offset_bytes = i * self.uniform_stride;
-                let bg = if i % 2 == 0 {
-                    &self.bind_group_0
-                } else {
-                    &self.bind_group_1
-                };
-                pass.set_bind_group(0, bg, &[offset_bytes]);
-                pass.dispatch_workgroups(x, y, 1);
-            }
-        }
-        self.queue.submit([encoder.finish()]);
+    fn wait_for_previous_submit(&self) -> anyhow::Result<()> {
+        wait_for_previous_submit(&self.device, &self.queue)
     }
 
-    pub fn wait_for_previous_submit(&self) -> anyhow::Result<()> {
-        let (tx, rx) = channel();
-        self.queue.on_submitted_work_done(move || {
-            let _ = tx.send(());
-        });
-        self.device.poll(wgpu::PollType::wait_indefinitely())?;
-        let _ = rx.recv();
-        Ok(())
+    fn wait_for_previous_submit_async(&self) -> WorkDone {
+        WorkDone::new(&self.device, &self.queue)
     }
 
-    pub fn wait_idle(&self) -> anyhow::Result<()> {
+    fn wait_idle(&self) -> anyhow::Result<()> {
         self.device.poll(wgpu::PollType::wait_indefinitely())?;
         Ok(())
     }
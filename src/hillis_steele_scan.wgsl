@@ -1,20 +1,40 @@
+// `Elem`, `identity()` and `combine(a, b)` of the scan operator are declared by the prelude
+// which is prepended on the host.
+override INCLUSIVE: bool = true;
+// Invocations per workgroup, a power of two
+override WG_SIZE: u32 = 64u;
+
 struct Uniforms {
   step: u32,
+  // Number of elements to scan, the buffers may be longer
+  len: u32,
 };
 
-@group(0) @binding(0) var<storage, read> src: array<u32>;
-@group(0) @binding(1) var<storage, read_write> dst: array<u32>;
+@group(0) @binding(0) var<storage, read> src: array<Elem>;
+@group(0) @binding(1) var<storage, read_write> dst: array<Elem>;
 @group(0) @binding(2) var<uniform> uni: Uniforms;
 
+fn load(i: u32) -> Elem {
+    // The exclusive scan is the inclusive scan of the input shifted right by one element,
+    // which is applied while reading in the first step
+    if (!INCLUSIVE && uni.step == 1u) {
+        if (i == 0u) {
+            return identity();
+        }
+        return src[i - 1u];
+    }
+    return src[i];
+}
+
 @compute
-@workgroup_size(64)
+@workgroup_size(WG_SIZE)
 fn main(
   @builtin(global_invocation_id) gid: vec3<u32>,
   @builtin(num_workgroups) nwg: vec3<u32>,
 ) {
-    let total = arrayLength(&src);
+    let total = uni.len;
 
-    let width = nwg.x * 64u;
+    let width = nwg.x * WG_SIZE;
     let i = gid.x + gid.y * width;
 
     if (i >= total) {
@@ -22,8 +42,8 @@ fn main(
     }
 
     if (i < uni.step) {
-        dst[i] = src[i];
+        dst[i] = load(i);
     } else {
-        dst[i] = src[i] + src[i - uni.step];
+        dst[i] = combine(load(i - uni.step), load(i));
     }
 }
\ No newline at end of file
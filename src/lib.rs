@@ -1,6 +1,18 @@
 pub mod block_blelloch_scan;
+mod block_levels;
+pub mod chunked_scan;
 pub mod cpu_prefix_scan;
+pub mod decoupled_lookback_scan;
+mod fused_carry;
 pub mod global_blelloch_scan;
+pub mod gpu_options;
 pub mod hillis_steele_scan;
+pub mod prefix_scan;
+pub mod readback;
+pub mod reduce_then_scan;
+pub mod scan_element;
+pub mod scan_error;
+pub mod scan_op;
+pub mod segmented_scan;
 pub mod subgroup_scan;
 mod utils;
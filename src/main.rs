@@ -1,19 +1,20 @@
 extern crate core;
 
 use wgpu_prefix_sum_demo::cpu_prefix_scan::cpu_prefix_sum;
+use wgpu_prefix_sum_demo::prefix_scan::PrefixScan;
 use wgpu_prefix_sum_demo::subgroup_scan::SubgroupScanGpuContext;
 
 fn main() -> anyhow::Result<()> {
-    let n = 10_000_000u32.next_power_of_two() as usize;
+    let n = 10_000_000usize;
     let data = vec![1u32; n];
     let cpu_res = cpu_prefix_sum(&data);
 
-    let gpu_ctx = pollster::block_on(SubgroupScanGpuContext::new(n))?;
+    let gpu_ctx = pollster::block_on(SubgroupScanGpuContext::<u32>::new(n))?;
     println!("n: {}", n);
-    gpu_ctx.upload_data(&data);
-    gpu_ctx.run_prefix_sum();
+    gpu_ctx.upload_data(&data)?;
+    gpu_ctx.run_prefix_scan();
     let gpu_res = gpu_ctx.read_computed_data()?;
 
-    assert_eq!(cpu_res[n - 1], gpu_res[n - 1] + data[n - 1]);
+    assert_eq!(cpu_res, gpu_res);
     Ok(())
 }
@@ -0,0 +1,368 @@
+use crate::gpu_options::GpuOptions;
+use crate::readback::{Readback, WorkDone};
+use crate::scan_element::ScanElement;
+use crate::scan_op::ScanOp;
+use crate::utils::init_wgpu;
+
+/// Which form of the scan a context produces.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ScanKind {
+    /// Element `i` of the result includes input element `i`, like [`cpu_prefix_sum`].
+    ///
+    /// [`cpu_prefix_sum`]: crate::cpu_prefix_scan::cpu_prefix_sum
+    #[default]
+    Inclusive,
+    /// Element `i` of the result only covers the input elements before `i`, and the first
+    /// element is the identity of the operator.
+    Exclusive,
+}
+
+impl ScanKind {
+    /// Value of the `INCLUSIVE` pipeline-overridable constant in the shaders.
+    pub(crate) fn inclusive_constant(self) -> f64 {
+        match self {
+            ScanKind::Inclusive => 1.0,
+            ScanKind::Exclusive => 0.0,
+        }
+    }
+}
+
+/// Largest [`ScanConfig::elems_per_thread`] the block scans support.
+pub const MAX_ELEMS_PER_THREAD: u32 = 16;
+
+/// Most scans a context can record, with [`PrefixScan::encode_scan`] or
+/// [`PrefixScan::encode_scan_buffer`], before the encoders they are recorded into are submitted.
+/// Their uniforms are staged in this many slots, which are reused in turn.
+pub const MAX_RECORDED_SCANS: usize = 32;
+
+/// Range of the subgroup sizes a device may run the subgroup scan with, powers of two between
+/// [`wgpu::MINIMUM_SUBGROUP_MIN_SIZE`] and [`wgpu::MAXIMUM_SUBGROUP_MAX_SIZE`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SubgroupSizes {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl SubgroupSizes {
+    /// Every subgroup size a device may have.
+    pub const ALL: Self = Self {
+        min: wgpu::MINIMUM_SUBGROUP_MIN_SIZE,
+        max: wgpu::MAXIMUM_SUBGROUP_MAX_SIZE,
+    };
+
+    /// Exactly `size` invocations per subgroup.
+    pub fn exact(size: u32) -> Self {
+        Self {
+            min: size,
+            max: size,
+        }
+    }
+
+    /// The subgroup sizes the adapter reports.
+    pub fn of_adapter(info: &wgpu::AdapterInfo) -> Self {
+        Self {
+            min: info.subgroup_min_size,
+            max: info.subgroup_max_size,
+        }
+    }
+
+    /// The size every subgroup has, if there is only one.
+    pub fn fixed(self) -> Option<u32> {
+        (self.min == self.max).then_some(self.min)
+    }
+
+    /// Whether every size in `other` is one of these sizes.
+    pub fn covers(self, other: Self) -> bool {
+        self.min <= other.min && other.max <= self.max
+    }
+}
+
+/// Options fixed when a context is created.
+#[derive(Clone, Debug)]
+pub struct ScanConfig {
+    pub kind: ScanKind,
+    pub op: ScanOp,
+    /// Keep the input in a buffer of its own and write the result to a separate output buffer,
+    /// instead of scanning in place. The first pass reads the input and writes the output, so it
+    /// costs no extra copy.
+    pub out_of_place: bool,
+    /// Number of consecutive elements each invocation of the blocked Blelloch and subgroup scans
+    /// loads and scans in registers before the workgroup scans the per-invocation totals. Larger
+    /// blocks need fewer levels and dispatches, and multiples of four are loaded as `vec4`s.
+    /// Between 1 and [`MAX_ELEMS_PER_THREAD`], the other algorithms ignore it.
+    pub elems_per_thread: u32,
+    /// Invocations per workgroup of every GPU scan and of the carries of the chunked scan, a
+    /// power of two. `None` keeps the default of the algorithm. The blocks of the blocked scans
+    /// are `workgroup_size * elems_per_thread` elements long, and the tiles of the decoupled
+    /// look-back scan `workgroup_size` elements.
+    pub workgroup_size: Option<u32>,
+    /// Reduce the levels of the blocked Blelloch and subgroup scans first and scan the data once
+    /// the carries are known, instead of scanning the blocks and adding the carries in a separate
+    /// pass, so the data is written once. Always on in the `ReduceThenScanGpuContext`.
+    pub fused_carry: bool,
+    /// Pad the shared memory of the blocked Blelloch scan with one element every 32, so the
+    /// power-of-two strides of its up- and down-sweeps do not hit the same memory bank. The
+    /// other algorithms ignore it.
+    pub bank_conflict_free: bool,
+    /// Subgroup sizes the device may run the subgroup scan with. The workgroup memory for the
+    /// subgroup sums is sized for the smallest one, and only a single size (`min == max`) is
+    /// compiled into the shaders, otherwise the size is read from the subgroup builtins. Without
+    /// subgroup operations, the emulated subgroups have the single size, or 32 invocations
+    /// otherwise. `None` takes the sizes of the adapter when the context creates its own device,
+    /// and allows any size on a caller-owned device.
+    ///
+    /// Sizes which do not cover those of the adapter are rejected with
+    /// [`ScanError::UncoveredSubgroupSizes`](crate::scan_error::ScanError::UncoveredSubgroupSizes).
+    /// A `wgpu::Device` does not know its adapter, so for a caller-owned device pass
+    /// [`SubgroupSizes::of_adapter`] of the adapter it was requested from.
+    pub subgroup_sizes: Option<SubgroupSizes>,
+    /// Adapter selection, only used by the constructors which create their own device.
+    pub gpu: GpuOptions,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        Self {
+            kind: ScanKind::default(),
+            op: ScanOp::default(),
+            out_of_place: false,
+            elems_per_thread: 1,
+            workgroup_size: None,
+            fused_carry: false,
+            bank_conflict_free: false,
+            subgroup_sizes: None,
+            gpu: GpuOptions::default(),
+        }
+    }
+}
+
+impl ScanConfig {
+    pub fn with_kind(mut self, kind: ScanKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn with_op(mut self, op: ScanOp) -> Self {
+        self.op = op;
+        self
+    }
+
+    pub fn with_out_of_place(mut self, out_of_place: bool) -> Self {
+        self.out_of_place = out_of_place;
+        self
+    }
+
+    pub fn with_elems_per_thread(mut self, elems_per_thread: u32) -> Self {
+        self.elems_per_thread = elems_per_thread;
+        self
+    }
+
+    pub fn with_workgroup_size(mut self, workgroup_size: u32) -> Self {
+        self.workgroup_size = Some(workgroup_size);
+        self
+    }
+
+    pub fn with_fused_carry(mut self, fused_carry: bool) -> Self {
+        self.fused_carry = fused_carry;
+        self
+    }
+
+    pub fn with_bank_conflict_free(mut self, bank_conflict_free: bool) -> Self {
+        self.bank_conflict_free = bank_conflict_free;
+        self
+    }
+
+    pub fn with_subgroup_sizes(mut self, subgroup_sizes: SubgroupSizes) -> Self {
+        self.subgroup_sizes = Some(subgroup_sizes);
+        self
+    }
+
+    pub fn with_gpu(mut self, gpu: GpuOptions) -> Self {
+        self.gpu = gpu;
+        self
+    }
+}
+
+/// `len` elements of a caller-owned buffer, starting `offset` bytes into it.
+#[derive(Copy, Clone, Debug)]
+pub struct BufferRange<'a> {
+    pub buffer: &'a wgpu::Buffer,
+    pub offset: u64,
+    pub len: usize,
+}
+
+impl<'a> BufferRange<'a> {
+    pub fn new(buffer: &'a wgpu::Buffer, offset: u64, len: usize) -> Self {
+        Self {
+            buffer,
+            offset,
+            len,
+        }
+    }
+}
+
+/// Common surface of all the prefix scan implementations.
+///
+/// Every algorithm follows the same flow: create a context for up to `capacity` elements, upload
+/// the input, record or run the scan, wait for the GPU and read the result back. Code written
+/// against `dyn PrefixScan` can swap algorithms without touching the call sites.
+///
+/// The buffers, pipelines and bind groups are created once for the capacity, and each upload sets
+/// the length of the following scans, so inputs of a different length every frame do not require
+/// a new context.
+pub trait PrefixScan<T: ScanElement = u32> {
+    /// Create a context which scans up to `capacity` elements on its own device, with the
+    /// default config.
+    fn new(capacity: usize) -> impl Future<Output = anyhow::Result<Self>>
+    where
+        Self: Sized,
+    {
+        Self::with_config(capacity, ScanConfig::default())
+    }
+
+    /// Create a context which scans up to `capacity` elements on its own device, created on the
+    /// adapter picked according to `config.gpu`.
+    ///
+    /// Returns [`ScanError::MissingFeatures`](crate::scan_error::ScanError::MissingFeatures) if the
+    /// adapter lacks a feature the algorithm needs.
+    fn with_config(
+        capacity: usize,
+        config: ScanConfig,
+    ) -> impl Future<Output = anyhow::Result<Self>>
+    where
+        Self: Sized,
+    {
+        async move {
+            let (device, queue, config) =
+                init_wgpu(config, Self::required_features(), Self::optional_features()).await?;
+            Self::from_device(&device, &queue, capacity, &config)
+        }
+    }
+
+    /// Device features the algorithm needs. Only these and the
+    /// [`optional_features`](Self::optional_features) the adapter has are requested when a
+    /// context creates its own device.
+    fn required_features() -> wgpu::Features
+    where
+        Self: Sized,
+    {
+        wgpu::Features::empty()
+    }
+
+    /// Device features the algorithm uses when the device has them, falling back to slower code
+    /// otherwise.
+    fn optional_features() -> wgpu::Features
+    where
+        Self: Sized,
+    {
+        wgpu::Features::empty()
+    }
+
+    /// Whether the algorithm can run on `adapter`.
+    fn is_supported(adapter: &wgpu::Adapter) -> bool
+    where
+        Self: Sized,
+    {
+        adapter.features().contains(Self::required_features())
+    }
+
+    /// Create a context which scans up to `capacity` elements on a caller-owned device, so the
+    /// scan can share buffers with the rest of the application. The length starts out as the
+    /// capacity.
+    ///
+    /// Returns an error if the device lacks a feature or limit the algorithm needs, or if the
+    /// operator cannot be applied to `T`.
+    fn from_device(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        capacity: usize,
+        config: &ScanConfig,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized;
+
+    /// Maximum number of elements the context can scan.
+    fn capacity(&self) -> usize;
+
+    /// Number of elements the next scan covers.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Set the number of elements the following scans cover, without touching the data.
+    ///
+    /// The length is passed to the shaders through uniforms which every recorded scan writes from
+    /// the command encoder, so scans of different lengths can be recorded into one encoder, and
+    /// the length may change between recording a scan and submitting it, for up to
+    /// [`MAX_RECORDED_SCANS`] scans recorded before a submit.
+    ///
+    /// Returns [`ScanError::LenExceedsCapacity`](crate::scan_error::ScanError::LenExceedsCapacity)
+    /// if `len` is larger than the capacity.
+    fn set_len(&self, len: usize) -> anyhow::Result<()>;
+
+    /// Upload the input data to the context and set the length to `input.len()`.
+    ///
+    /// Returns an error if the input is longer than the capacity.
+    fn upload_data(&self, input: &[T]) -> anyhow::Result<()>;
+
+    /// Record the scan into the caller's command encoder. Nothing is submitted.
+    fn encode_scan(&self, encoder: &mut wgpu::CommandEncoder);
+
+    /// Record a scan of the elements in `input` into the caller's command encoder, writing the
+    /// result to `output`, or back over the input if it is `None`. Nothing is submitted, so the
+    /// scan can be chained with the passes producing and consuming the data without any round
+    /// trip to the CPU. Sets the length to `input.len`.
+    ///
+    /// The ranges are bound to the scan directly if their buffers have `STORAGE` usage and their
+    /// offsets are aligned to `min_storage_buffer_offset_alignment`, with bind groups created
+    /// the first time a range is scanned and kept for the following scans. Otherwise they are
+    /// copied to and from the context's buffers on the GPU, which only needs offsets which are
+    /// multiples of 4. So do the ranges of the global Blelloch scan, which pads the data, and
+    /// ranges which end within a `vec4` chunk of the blocked scans. The input buffer needs
+    /// `COPY_SRC` and the output buffer `COPY_DST` usage either way. The result is only written to
+    /// the output range, [`read_computed_data`](Self::read_computed_data) does not return it.
+    ///
+    /// Returns [`ScanError::InvalidBufferRange`](crate::scan_error::ScanError::InvalidBufferRange)
+    /// if a range cannot be copied, and an error if the input is longer than the capacity.
+    fn encode_scan_buffer(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        input: BufferRange<'_>,
+        output: Option<BufferRange<'_>>,
+    ) -> anyhow::Result<()>;
+
+    /// Record the scan into a new command encoder and submit it.
+    fn run_prefix_scan(&self);
+
+    /// Read the first [`len`](Self::len) elements of the scan result back to the CPU.
+    fn read_computed_data(&self) -> anyhow::Result<Vec<T>>;
+
+    /// Start reading the result back like [`read_computed_data`](Self::read_computed_data),
+    /// without blocking the thread. The returned [`Readback`] can be awaited on any executor, or
+    /// checked with [`Readback::try_poll`] from a loop which must not wait. Native backends only
+    /// resolve it while the device is polled, see [`Readback::poll_in_background`].
+    fn read_computed_data_async(&self) -> Readback<T>;
+
+    /// Block until the work submitted so far has been completed.
+    fn wait_for_previous_submit(&self) -> anyhow::Result<()>;
+
+    /// Like [`wait_for_previous_submit`](Self::wait_for_previous_submit), without blocking the
+    /// thread. The returned [`WorkDone`] can be awaited or checked with [`WorkDone::try_poll`], and
+    /// is only resolved while the device is polled, see [`WorkDone::poll_in_background`].
+    fn wait_for_previous_submit_async(&self) -> WorkDone;
+
+    /// Block until the device becomes idle.
+    fn wait_idle(&self) -> anyhow::Result<()>;
+}
+
+/// Buffers of a GPU context, which other passes can read the input from and the result of the
+/// last scan from.
+pub trait ScanBuffers {
+    /// Buffer [`upload_data`](PrefixScan::upload_data) writes the input to.
+    fn input_buffer(&self) -> &wgpu::Buffer;
+
+    /// Buffer holding the result of the last scan, at the start of the buffer.
+    fn output_buffer(&self) -> &wgpu::Buffer;
+}
@@ -0,0 +1,461 @@
+use crate::scan_error::ScanError;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+/// Result of a wgpu callback, together with the task waiting for it.
+#[derive(Default)]
+struct Shared {
+    result: Option<Result<(), ScanError>>,
+    waker: Option<Waker>,
+    // The callback has been called, the result may have been taken since
+    resolved: bool,
+    // Nobody takes the result anymore, so the callback cleans up after itself
+    abandoned: bool,
+}
+
+type Slot = Arc<Mutex<Shared>>;
+
+/// Store the first result of the slot and wake the task waiting for it. Returns whether the slot
+/// has been abandoned.
+fn resolve(slot: &Slot, result: Result<(), ScanError>) -> bool {
+    let (waker, abandoned) = {
+        let mut shared = slot.lock().unwrap();
+        shared.result.get_or_insert(result);
+        shared.resolved = true;
+        (shared.waker.take(), shared.abandoned)
+    };
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+    abandoned
+}
+
+/// Devices polled on a background thread, with the slots waiting for them. One thread polls each
+/// device until all of its slots are resolved.
+static POLLERS: Mutex<Vec<Poller>> = Mutex::new(Vec::new());
+
+struct Poller {
+    device: wgpu::Device,
+    waiters: Vec<Slot>,
+}
+
+/// Poll `device` on its background thread until `slot` is resolved, starting the thread if the
+/// device has none.
+fn poll_in_background(device: &wgpu::Device, slot: Slot) {
+    let mut pollers = POLLERS.lock().unwrap();
+    if let Some(poller) = pollers.iter_mut().find(|poller| poller.device == *device) {
+        poller.waiters.push(slot);
+        return;
+    }
+    pollers.push(Poller {
+        device: device.clone(),
+        waiters: vec![slot],
+    });
+    let device = device.clone();
+    std::thread::spawn(move || {
+        loop {
+            // Returns once the work submitted so far is done, which calls the callbacks
+            let lost = device.poll(wgpu::PollType::wait_indefinitely()).is_err();
+            let mut pollers = POLLERS.lock().unwrap();
+            let i = pollers
+                .iter()
+                .position(|poller| poller.device == device)
+                .expect("the poller of the device is registered");
+            if lost {
+                let waiters = pollers.swap_remove(i).waiters;
+                drop(pollers);
+                for slot in &waiters {
+                    resolve(slot, Err(ScanError::DeviceLost));
+                }
+                return;
+            }
+            let waiters = &mut pollers[i].waiters;
+            waiters.retain(|slot| !slot.lock().unwrap().resolved);
+            if waiters.is_empty() {
+                pollers.swap_remove(i);
+                return;
+            }
+        }
+    });
+}
+
+/// Fills the slot when the callback is called. wgpu drops the callback without calling it if the
+/// device is lost, which fills the slot with [`ScanError::DeviceLost`] instead.
+struct Resolver(Slot);
+
+impl Resolver {
+    /// Returns whether the slot has been abandoned.
+    fn resolve(&self, result: Result<(), ScanError>) -> bool {
+        resolve(&self.0, result)
+    }
+}
+
+impl Drop for Resolver {
+    fn drop(&mut self) {
+        self.resolve(Err(ScanError::DeviceLost));
+    }
+}
+
+/// Work on the GPU which a wgpu callback reports the completion of.
+struct Pending {
+    /// `None` if the work was already done when it was created.
+    device: Option<wgpu::Device>,
+    slot: Slot,
+}
+
+impl Pending {
+    fn new(device: &wgpu::Device) -> (Self, Resolver) {
+        let slot = Slot::default();
+        let pending = Self {
+            device: Some(device.clone()),
+            slot: slot.clone(),
+        };
+        (pending, Resolver(slot))
+    }
+
+    fn done() -> Self {
+        let slot = Slot::default();
+        slot.lock().unwrap().result = Some(Ok(()));
+        Self { device: None, slot }
+    }
+
+    /// Native backends only call the callbacks while the device is polled, so poll it without
+    /// blocking and take the result if the callback has been called.
+    fn try_poll(&self) -> Option<anyhow::Result<()>> {
+        if let Some(device) = &self.device
+            && let Err(e) = device.poll(wgpu::PollType::Poll)
+        {
+            return Some(Err(e.into()));
+        }
+        let result = self.slot.lock().unwrap().result.take()?;
+        Some(result.map_err(Into::into))
+    }
+
+    /// Like `try_poll`, but leave the waker of `cx` to be woken by the callback if it has not
+    /// been called yet.
+    fn poll(&self, cx: &mut Context<'_>) -> Poll<anyhow::Result<()>> {
+        if let Some(result) = self.try_poll() {
+            return Poll::Ready(result);
+        }
+        // Checked again under the lock, so a callback called in between is not missed
+        let mut shared = self.slot.lock().unwrap();
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result.map_err(Into::into)),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Poll the device on its background thread until the callback is called.
+    fn poll_in_background(&self) {
+        if let Some(device) = &self.device {
+            poll_in_background(device, self.slot.clone());
+        }
+    }
+}
+
+/// Completion of the work submitted to a queue, returned by
+/// [`PrefixScan::wait_for_previous_submit_async`](crate::prefix_scan::PrefixScan::wait_for_previous_submit_async).
+///
+/// Can be awaited on any executor, or checked from a frame loop with [`try_poll`](Self::try_poll).
+/// Neither blocks the thread. The future is woken by the wgpu callback, which native backends
+/// only call while the device is polled, by the frame loop, [`try_poll`](Self::try_poll) or
+/// [`poll_in_background`](Self::poll_in_background).
+pub struct WorkDone(Pending);
+
+impl WorkDone {
+    pub(crate) fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let (pending, resolver) = Pending::new(device);
+        queue.on_submitted_work_done(move || {
+            resolver.resolve(Ok(()));
+        });
+        Self(pending)
+    }
+
+    pub(crate) fn done() -> Self {
+        Self(Pending::done())
+    }
+
+    /// Poll the device without blocking. Returns `None` while the work is still running, the
+    /// result is only returned once.
+    pub fn try_poll(&mut self) -> Option<anyhow::Result<()>> {
+        self.0.try_poll()
+    }
+
+    /// Poll the device on a background thread until the work is done, for executors such as
+    /// `pollster` when nothing else polls the device. The thread is shared by everything waiting
+    /// on the device.
+    pub fn poll_in_background(self) -> Self {
+        self.0.poll_in_background();
+        self
+    }
+}
+
+impl Future for WorkDone {
+    type Output = anyhow::Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.0.poll(cx)
+    }
+}
+
+/// Mappable buffer of a context which the scan results are copied into to read them back.
+///
+/// Only one readback can map it at a time, which the buffer keeps track of.
+pub(crate) struct ReadbackBuffer {
+    buffer: wgpu::Buffer,
+    in_flight: Arc<AtomicBool>,
+}
+
+impl ReadbackBuffer {
+    pub(crate) fn new(device: &wgpu::Device, size: u64) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Self {
+            buffer,
+            in_flight: Arc::default(),
+        }
+    }
+}
+
+enum ReadbackState<T> {
+    Mapping {
+        pending: Pending,
+        readback: wgpu::Buffer,
+        in_flight: Arc<AtomicBool>,
+        byte_len: u64,
+        // Submission of the copy into the readback buffer
+        submission: wgpu::SubmissionIndex,
+    },
+    Done(Vec<T>),
+    Failed(Option<ScanError>),
+}
+
+/// Scan result on its way back to the CPU, returned by
+/// [`PrefixScan::read_computed_data_async`](crate::prefix_scan::PrefixScan::read_computed_data_async).
+///
+/// It is resolved by the `map_async` callback of the readback buffer, and can be awaited on any
+/// executor or checked from a frame loop with [`try_poll`](Self::try_poll). Neither blocks the
+/// thread. Native backends only call the callback while the device is polled, by the frame loop,
+/// [`try_poll`](Self::try_poll) or [`poll_in_background`](Self::poll_in_background).
+///
+/// A context has one readback buffer, so starting another readback while one is pending fails
+/// with [`ScanError::ReadbackPending`]. A dropped readback releases the buffer once its mapping
+/// completes, the next time the device is polled.
+pub struct Readback<T> {
+    state: ReadbackState<T>,
+}
+
+// The state is never pinned
+impl<T> Unpin for Readback<T> {}
+
+impl<T: bytemuck::Pod> Readback<T> {
+    /// Copy the first `len` elements of `src` into the mappable `readback` buffer, submit the copy
+    /// and start mapping it.
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        src: &wgpu::Buffer,
+        readback: &ReadbackBuffer,
+        len: usize,
+    ) -> Self {
+        // Mapping an empty range is not allowed
+        if len == 0 {
+            return Self::done(vec![]);
+        }
+        // The buffer cannot be copied into or mapped again before the pending readback unmaps it.
+        // The mapping of a dropped readback may only be waiting for the device to be polled.
+        let mut busy = readback.in_flight.swap(true, Ordering::AcqRel);
+        if busy {
+            let _ = device.poll(wgpu::PollType::Poll);
+            busy = readback.in_flight.swap(true, Ordering::AcqRel);
+        }
+        if busy {
+            return Self {
+                state: ReadbackState::Failed(Some(ScanError::ReadbackPending)),
+            };
+        }
+        let byte_len = (len * size_of::<T>()) as u64;
+
+        // Copy the result buffer to the temp buffer to bring the data to the CPU land
+        let mut encoder = device.create_command_encoder(&Default::default());
+        encoder.copy_buffer_to_buffer(src, 0, &readback.buffer, 0, byte_len);
+        let submission = queue.submit([encoder.finish()]);
+
+        let (pending, resolver) = Pending::new(device);
+        let buffer = readback.buffer.clone();
+        let in_flight = readback.in_flight.clone();
+        readback
+            .buffer
+            .slice(..byte_len)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let mapped = result.is_ok();
+                let abandoned = resolver.resolve(result.map_err(ScanError::MapFailed));
+                // A failed mapping leaves the buffer unmapped, and a mapping nobody reads anymore
+                // is released here
+                if mapped && abandoned {
+                    buffer.unmap();
+                }
+                if !mapped || abandoned {
+                    in_flight.store(false, Ordering::Release);
+                }
+            });
+        Self {
+            state: ReadbackState::Mapping {
+                pending,
+                readback: readback.buffer.clone(),
+                in_flight: readback.in_flight.clone(),
+                byte_len,
+                submission,
+            },
+        }
+    }
+
+    pub(crate) fn done(data: Vec<T>) -> Self {
+        Self {
+            state: ReadbackState::Done(data),
+        }
+    }
+
+    /// Poll the device without blocking. Returns `None` while the result is not mapped yet, the
+    /// result is only returned once.
+    pub fn try_poll(&mut self) -> Option<anyhow::Result<Vec<T>>> {
+        match &mut self.state {
+            ReadbackState::Mapping { pending, .. } => {
+                let result = pending.try_poll()?;
+                Some(self.finish(result))
+            }
+            _ => Some(self.take_ready()),
+        }
+    }
+
+    /// Poll the device on a background thread until the result is mapped, for executors such as
+    /// `pollster` when nothing else polls the device. The thread is shared by everything waiting
+    /// on the device.
+    pub fn poll_in_background(self) -> Self {
+        if let ReadbackState::Mapping { pending, .. } = &self.state {
+            pending.poll_in_background();
+        }
+        self
+    }
+
+    /// Block until the copy is done and the result is mapped, and read it.
+    pub(crate) fn wait(mut self) -> anyhow::Result<Vec<T>> {
+        if let ReadbackState::Mapping {
+            pending,
+            submission,
+            ..
+        } = &self.state
+            && let Some(device) = &pending.device
+            && let Err(e) = device.poll(wgpu::PollType::Wait {
+                submission_index: Some(submission.clone()),
+                timeout: None,
+            })
+        {
+            return self.finish(Err(e.into()));
+        }
+        self.try_poll()
+            .unwrap_or_else(|| Err(ScanError::DeviceLost.into()))
+    }
+
+    /// Take the result of a readback which is not mapping anymore.
+    fn take_ready(&mut self) -> anyhow::Result<Vec<T>> {
+        match &mut self.state {
+            ReadbackState::Done(data) => Ok(std::mem::take(data)),
+            ReadbackState::Failed(error) => {
+                Err(error.take().unwrap_or(ScanError::DeviceLost).into())
+            }
+            ReadbackState::Mapping { .. } => unreachable!("the readback is still mapping"),
+        }
+    }
+
+    /// Read the mapped result and release the readback buffer, or leave the buffer to the
+    /// callback if polling the device failed.
+    fn finish(&mut self, result: anyhow::Result<()>) -> anyhow::Result<Vec<T>> {
+        let state = std::mem::replace(&mut self.state, ReadbackState::Done(vec![]));
+        let ReadbackState::Mapping {
+            pending,
+            readback,
+            in_flight,
+            byte_len,
+            ..
+        } = state
+        else {
+            unreachable!("the readback is not mapping");
+        };
+        match result {
+            Ok(()) => {
+                let data = read_mapped(&readback, byte_len);
+                in_flight.store(false, Ordering::Release);
+                Ok(data)
+            }
+            Err(e) => {
+                abandon(&pending, &readback, &in_flight);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<T: bytemuck::Pod> Future for Readback<T> {
+    type Output = anyhow::Result<Vec<T>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match &self.state {
+            ReadbackState::Mapping { pending, .. } => match pending.poll(cx) {
+                Poll::Ready(result) => Poll::Ready(self.finish(result)),
+                Poll::Pending => Poll::Pending,
+            },
+            _ => Poll::Ready(self.take_ready()),
+        }
+    }
+}
+
+impl<T> Drop for Readback<T> {
+    fn drop(&mut self) {
+        if let ReadbackState::Mapping {
+            pending,
+            readback,
+            in_flight,
+            ..
+        } = &self.state
+        {
+            abandon(pending, readback, in_flight);
+        }
+    }
+}
+
+/// Release the readback buffer of a mapping whose result is not read. The buffer is unmapped here
+/// if the mapping has succeeded, otherwise the callback releases it once it is called.
+fn abandon(pending: &Pending, readback: &wgpu::Buffer, in_flight: &AtomicBool) {
+    let mut shared = pending.slot.lock().unwrap();
+    if !shared.resolved {
+        shared.abandoned = true;
+        return;
+    }
+    // A failed mapping was released by the callback, and a taken result was read
+    if let Some(Ok(())) = shared.result.take() {
+        drop(shared);
+        readback.unmap();
+        in_flight.store(false, Ordering::Release);
+    }
+}
+
+/// Copy the mapped bytes out of the readback buffer and unmap it for the next readback.
+fn read_mapped<T: bytemuck::Pod>(readback: &wgpu::Buffer, byte_len: u64) -> Vec<T> {
+    let slice = readback.slice(..byte_len);
+    let bytes = slice.get_mapped_range();
+    let v = bytemuck::cast_slice(&bytes).to_vec();
+    drop(bytes);
+    readback.unmap();
+    v
+}
@@ -0,0 +1,35 @@
+use crate::block_blelloch_scan::block_scan;
+use crate::block_levels::{BlockScan, impl_block_context};
+use crate::prefix_scan::ScanConfig;
+use crate::scan_element::ScanElement;
+use crate::scan_op::{check_op, wgsl_prelude};
+
+/// Blocked scan which reduces the blocks first and scans them once their carries are known.
+///
+/// This is the [`BlockBlellochGpuContext`] with [`ScanConfig::fused_carry`] always on: the first
+/// pass only writes the block sums of every level, the top level is scanned, and then every level
+/// is scanned again starting from the carries of the level above it. The data is read twice but
+/// written once, while the blocked Blelloch scan writes the scanned blocks and then reads and
+/// writes them again to add the carries. The other options of the [`ScanConfig`] apply as for the
+/// blocked Blelloch scan.
+pub struct ReduceThenScanGpuContext<T: bytemuck::Pod = u32>(BlockScan<T>);
+
+impl_block_context!(ReduceThenScanGpuContext, wgpu::Features::empty(), create);
+
+fn create<T: ScanElement>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    capacity: usize,
+    config: &ScanConfig,
+) -> anyhow::Result<BlockScan<T>> {
+    check_op::<T>(config.op)?;
+    let config = config.clone().with_fused_carry(true);
+    block_scan(
+        device,
+        queue,
+        capacity,
+        &config,
+        &wgsl_prelude::<T>(config.op),
+        true,
+    )
+}
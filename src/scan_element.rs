@@ -0,0 +1,148 @@
+use crate::scan_op::ScanOp;
+
+pub(crate) mod sealed {
+    use crate::scan_op::ScanOp;
+
+    /// The host side of the operators, which is only defined for the operators
+    /// [`check_op`](crate::scan_op::check_op) accepts for the type.
+    pub trait Sealed: Sized {
+        /// Identity element of `op`, used for padding and as the first element of exclusive
+        /// scans.
+        ///
+        /// Panics if `op` is bitwise and the type is not an integer, the scans reject that pair
+        /// with [`ScanError::UnsupportedOp`](crate::scan_error::ScanError::UnsupportedOp) first.
+        fn identity(op: ScanOp) -> Self;
+
+        /// WGSL expression of [`Sealed::identity`], which panics for the same pairs.
+        fn wgsl_identity(op: ScanOp) -> &'static str;
+
+        /// Apply `op` as the shaders do it, integers wrap around on overflow. Panics for the same
+        /// pairs as [`Sealed::identity`].
+        fn combine(op: ScanOp, a: Self, b: Self) -> Self;
+    }
+}
+
+/// Element types the scans support.
+///
+/// The trait is sealed since every element type needs a matching WGSL storage type, which is
+/// declared as `Elem` in the prelude prepended to the shaders.
+pub trait ScanElement: bytemuck::Pod + std::fmt::Debug + PartialEq + sealed::Sealed {
+    /// Name of the matching WGSL scalar type.
+    const WGSL_TYPE: &'static str;
+
+    /// Whether the bitwise operators can be applied.
+    const IS_INTEGER: bool;
+}
+
+impl ScanElement for u32 {
+    const WGSL_TYPE: &'static str = "u32";
+    const IS_INTEGER: bool = true;
+}
+
+impl sealed::Sealed for u32 {
+    fn identity(op: ScanOp) -> Self {
+        match op {
+            ScanOp::Add | ScanOp::Max | ScanOp::Or | ScanOp::Xor => 0,
+            ScanOp::Mul => 1,
+            ScanOp::Min | ScanOp::And => u32::MAX,
+        }
+    }
+
+    fn wgsl_identity(op: ScanOp) -> &'static str {
+        match op {
+            ScanOp::Add | ScanOp::Max | ScanOp::Or | ScanOp::Xor => "0u",
+            ScanOp::Mul => "1u",
+            ScanOp::Min | ScanOp::And => "4294967295u",
+        }
+    }
+
+    fn combine(op: ScanOp, a: Self, b: Self) -> Self {
+        match op {
+            ScanOp::Add => a.wrapping_add(b),
+            ScanOp::Mul => a.wrapping_mul(b),
+            ScanOp::Min => a.min(b),
+            ScanOp::Max => a.max(b),
+            ScanOp::And => a & b,
+            ScanOp::Or => a | b,
+            ScanOp::Xor => a ^ b,
+        }
+    }
+}
+
+impl ScanElement for i32 {
+    const WGSL_TYPE: &'static str = "i32";
+    const IS_INTEGER: bool = true;
+}
+
+impl sealed::Sealed for i32 {
+    fn identity(op: ScanOp) -> Self {
+        match op {
+            ScanOp::Add | ScanOp::Or | ScanOp::Xor => 0,
+            ScanOp::Mul => 1,
+            ScanOp::Min => i32::MAX,
+            ScanOp::Max => i32::MIN,
+            ScanOp::And => -1,
+        }
+    }
+
+    fn wgsl_identity(op: ScanOp) -> &'static str {
+        match op {
+            ScanOp::Add | ScanOp::Or | ScanOp::Xor => "0i",
+            ScanOp::Mul => "1i",
+            ScanOp::Min => "2147483647i",
+            // -2147483648i would negate an out-of-range literal
+            ScanOp::Max => "i32(-2147483648)",
+            ScanOp::And => "-1i",
+        }
+    }
+
+    fn combine(op: ScanOp, a: Self, b: Self) -> Self {
+        match op {
+            ScanOp::Add => a.wrapping_add(b),
+            ScanOp::Mul => a.wrapping_mul(b),
+            ScanOp::Min => a.min(b),
+            ScanOp::Max => a.max(b),
+            ScanOp::And => a & b,
+            ScanOp::Or => a | b,
+            ScanOp::Xor => a ^ b,
+        }
+    }
+}
+
+impl ScanElement for f32 {
+    const WGSL_TYPE: &'static str = "f32";
+    const IS_INTEGER: bool = false;
+}
+
+impl sealed::Sealed for f32 {
+    fn identity(op: ScanOp) -> Self {
+        match op {
+            ScanOp::Add => 0.0,
+            ScanOp::Mul => 1.0,
+            ScanOp::Min => f32::INFINITY,
+            ScanOp::Max => f32::NEG_INFINITY,
+            ScanOp::And | ScanOp::Or | ScanOp::Xor => unreachable!("{op:?} is not defined for f32"),
+        }
+    }
+
+    fn wgsl_identity(op: ScanOp) -> &'static str {
+        match op {
+            ScanOp::Add => "0.0f",
+            ScanOp::Mul => "1.0f",
+            // There are no literals for the infinities
+            ScanOp::Min => "bitcast<f32>(0x7f800000u)",
+            ScanOp::Max => "bitcast<f32>(0xff800000u)",
+            ScanOp::And | ScanOp::Or | ScanOp::Xor => unreachable!("{op:?} is not defined for f32"),
+        }
+    }
+
+    fn combine(op: ScanOp, a: Self, b: Self) -> Self {
+        match op {
+            ScanOp::Add => a + b,
+            ScanOp::Mul => a * b,
+            ScanOp::Min => a.min(b),
+            ScanOp::Max => a.max(b),
+            ScanOp::And | ScanOp::Or | ScanOp::Xor => unreachable!("{op:?} is not defined for f32"),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use crate::prefix_scan::{MAX_ELEMS_PER_THREAD, SubgroupSizes};
+use crate::scan_op::ScanOp;
+use std::fmt;
+
+/// Errors reported by the scan contexts.
+///
+/// The contexts return [`anyhow::Result`], so callers which want to recover from a particular
+/// error can get it back with `error.downcast_ref::<ScanError>()`.
+#[derive(Debug)]
+pub enum ScanError {
+    /// No adapter could be found.
+    NoAdapter(wgpu::RequestAdapterError),
+    /// No adapter name contains the name filter of the `GpuOptions`.
+    NoMatchingAdapter(String),
+    /// The adapter could not create a device.
+    RequestDevice(wgpu::RequestDeviceError),
+    /// The device does not support features the algorithm needs.
+    MissingFeatures(wgpu::Features),
+    /// The data is larger than a limit of the device allows.
+    SizeTooLarge {
+        /// Name of the exceeded limit.
+        limit_name: &'static str,
+        size: u64,
+        limit: u64,
+    },
+    /// The operator cannot be applied to the element type.
+    UnsupportedOp {
+        op: ScanOp,
+        element_type: &'static str,
+    },
+    /// The element type cannot be stored in a storage buffer.
+    InvalidElementSize(usize),
+    /// The number of elements per invocation is out of the supported range.
+    InvalidElemsPerThread(u32),
+    /// The workgroup size is not a power of two of at least 2.
+    InvalidWorkgroupSize(u32),
+    /// The subgroup sizes are not an ascending range of supported powers of two.
+    InvalidSubgroupSizes { min: u32, max: u32 },
+    /// The adapter may run subgroups of sizes outside the configured ones.
+    UncoveredSubgroupSizes {
+        sizes: SubgroupSizes,
+        adapter: SubgroupSizes,
+    },
+    /// Fewer head flags than elements were passed to a segmented scan.
+    FlagsTooShort { len: usize, flags_len: usize },
+    /// The scan length exceeds the capacity the context was created with.
+    LenExceedsCapacity { len: usize, capacity: usize },
+    /// A caller-provided buffer range cannot be scanned.
+    InvalidBufferRange(&'static str),
+    /// The context cannot perform the requested operation.
+    Unsupported(&'static str),
+    /// Mapping the readback buffer failed.
+    MapFailed(wgpu::BufferAsyncError),
+    /// The context's readback buffer is still in use by a pending readback.
+    ReadbackPending,
+    /// The device was lost before the submitted work completed.
+    DeviceLost,
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanError::NoAdapter(_) => f.write_str("No adapter found"),
+            ScanError::NoMatchingAdapter(name) => write!(f, "No adapter named like \"{name}\""),
+            ScanError::RequestDevice(_) => f.write_str("Failed to create device"),
+            ScanError::MissingFeatures(missing) => {
+                let names: Vec<&str> = missing.iter_names().map(|(name, _)| name).collect();
+                write!(
+                    f,
+                    "Device does not support the required features {}",
+                    names.join(" | ")
+                )
+            }
+            ScanError::SizeTooLarge {
+                limit_name,
+                size,
+                limit,
+            } => write!(f, "{size} exceeds {limit_name} of {limit}"),
+            ScanError::UnsupportedOp { op, element_type } => {
+                write!(
+                    f,
+                    "{op:?} requires an integer element type, got {element_type}"
+                )
+            }
+            ScanError::InvalidElementSize(size) => {
+                write!(f, "Element size of {size} bytes is not a multiple of 4")
+            }
+            ScanError::InvalidElemsPerThread(elems) => write!(
+                f,
+                "Elements per thread must be between 1 and {MAX_ELEMS_PER_THREAD}, got {elems}"
+            ),
+            ScanError::InvalidWorkgroupSize(size) => write!(
+                f,
+                "Workgroup size must be a power of two of at least 2, got {size}"
+            ),
+            ScanError::InvalidSubgroupSizes { min, max } => write!(
+                f,
+                "Subgroup sizes must be powers of two between {} and {}, got {min} to {max}",
+                wgpu::MINIMUM_SUBGROUP_MIN_SIZE,
+                wgpu::MAXIMUM_SUBGROUP_MAX_SIZE
+            ),
+            ScanError::UncoveredSubgroupSizes { sizes, adapter } => write!(
+                f,
+                "Subgroup sizes {} to {} do not cover the sizes {} to {} of the adapter",
+                sizes.min, sizes.max, adapter.min, adapter.max
+            ),
+            ScanError::FlagsTooShort { len, flags_len } => {
+                write!(f, "{flags_len} head flags do not cover {len} elements")
+            }
+            ScanError::LenExceedsCapacity { len, capacity } => {
+                write!(
+                    f,
+                    "Length {len} exceeds the capacity of {capacity} elements"
+                )
+            }
+            ScanError::InvalidBufferRange(reason) => write!(f, "Invalid buffer range: {reason}"),
+            ScanError::Unsupported(operation) => write!(f, "Unsupported operation: {operation}"),
+            ScanError::MapFailed(_) => f.write_str("Failed to map the readback buffer"),
+            ScanError::ReadbackPending => f.write_str("A readback of the context is still pending"),
+            ScanError::DeviceLost => f.write_str("Device was lost"),
+        }
+    }
+}
+
+impl std::error::Error for ScanError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScanError::NoAdapter(e) => Some(e),
+            ScanError::RequestDevice(e) => Some(e),
+            ScanError::MapFailed(e) => Some(e),
+            _ => None,
+        }
+    }
+}
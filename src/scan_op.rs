@@ -0,0 +1,195 @@
+use crate::scan_element::ScanElement;
+use crate::scan_error::ScanError;
+
+/// The associative operator a scan combines the elements with.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ScanOp {
+    /// Prefix sum.
+    #[default]
+    Add,
+    /// Prefix product.
+    Mul,
+    /// Running minimum.
+    Min,
+    /// Running maximum.
+    Max,
+    /// Prefix bitwise and. Integer elements only.
+    And,
+    /// Prefix bitwise or. Integer elements only.
+    Or,
+    /// Prefix bitwise xor. Integer elements only.
+    Xor,
+}
+
+impl ScanOp {
+    pub fn is_bitwise(self) -> bool {
+        matches!(self, ScanOp::And | ScanOp::Or | ScanOp::Xor)
+    }
+
+    /// Body of the WGSL `combine(a, b)` function.
+    fn wgsl_combine(self) -> &'static str {
+        match self {
+            ScanOp::Add => "a + b",
+            ScanOp::Mul => "a * b",
+            ScanOp::Min => "min(a, b)",
+            ScanOp::Max => "max(a, b)",
+            ScanOp::And => "a & b",
+            ScanOp::Or => "a | b",
+            ScanOp::Xor => "a ^ b",
+        }
+    }
+
+    /// WGSL builtin reducing a value over the subgroup.
+    fn wgsl_subgroup_reduce(self) -> &'static str {
+        match self {
+            ScanOp::Add => "subgroupAdd",
+            ScanOp::Mul => "subgroupMul",
+            ScanOp::Min => "subgroupMin",
+            ScanOp::Max => "subgroupMax",
+            ScanOp::And => "subgroupAnd",
+            ScanOp::Or => "subgroupOr",
+            ScanOp::Xor => "subgroupXor",
+        }
+    }
+
+    /// WGSL builtin for the exclusive subgroup scan, if there is one for this operator.
+    fn wgsl_subgroup_exclusive_scan(self) -> Option<&'static str> {
+        match self {
+            ScanOp::Add => Some("subgroupExclusiveAdd"),
+            ScanOp::Mul => Some("subgroupExclusiveMul"),
+            _ => None,
+        }
+    }
+}
+
+/// Check that `op` can be applied to `T`.
+pub(crate) fn check_op<T: ScanElement>(op: ScanOp) -> Result<(), ScanError> {
+    if op.is_bitwise() && !T::IS_INTEGER {
+        return Err(ScanError::UnsupportedOp {
+            op,
+            element_type: T::WGSL_TYPE,
+        });
+    }
+    Ok(())
+}
+
+/// WGSL declarations prepended to every shader: the element type `Elem`, the `identity()` of
+/// the operator and `combine(a, b)` which applies it.
+pub(crate) fn wgsl_prelude<T: ScanElement>(op: ScanOp) -> String {
+    format!(
+        "alias Elem = {ty};
+
+fn identity() -> Elem {{
+    return {identity};
+}}
+
+fn combine(a: Elem, b: Elem) -> Elem {{
+    return {combine};
+}}
+",
+        ty = T::WGSL_TYPE,
+        identity = T::wgsl_identity(op),
+        combine = op.wgsl_combine(),
+    )
+}
+
+/// [`wgsl_prelude`] plus the `SubgroupBuiltins` entry point input, `subgroup_of(builtins)`
+/// returning the `Subgroup` of the invocation, and the subgroup-wide `subgroup_reduce(v, sg)` and
+/// `subgroup_exclusive_scan(v, sg)` of the operator.
+///
+/// Only addition and multiplication have exclusive scan builtins, the other operators use a
+/// Kogge-Stone scan over `subgroupShuffleUp`.
+pub(crate) fn wgsl_subgroup_prelude<T: ScanElement>(op: ScanOp) -> String {
+    let exclusive_scan = match op.wgsl_subgroup_exclusive_scan() {
+        Some(builtin) => format!("    return {builtin}(v);"),
+        None => "    var x = v;
+    for (var delta = 1u; delta < sg.size; delta = delta << 1u) {
+        let y = subgroupShuffleUp(x, delta);
+        if (sg.lane >= delta) {
+            x = combine(y, x);
+        }
+    }
+    // Shift the inclusive scan by one lane to make it exclusive
+    let shifted = subgroupShuffleUp(x, 1u);
+    return select(identity(), shifted, sg.lane > 0u);"
+            .to_string(),
+    };
+    format!(
+        "{prelude}
+struct SubgroupBuiltins {{
+    @builtin(subgroup_size) size: u32,
+    @builtin(subgroup_invocation_id) lane: u32,
+    @builtin(subgroup_id) id: u32,
+}}
+
+fn subgroup_of(builtins: SubgroupBuiltins) -> Subgroup {{
+    // A single possible size is a constant the loops over the subgroup can be unrolled with
+    let size = select(builtins.size, SUBGROUP_MIN_SIZE, SUBGROUP_MIN_SIZE == SUBGROUP_MAX_SIZE);
+    return Subgroup(size, builtins.lane, builtins.id);
+}}
+
+fn subgroup_reduce(v: Elem, sg: Subgroup) -> Elem {{
+    return {reduce}(v);
+}}
+
+fn subgroup_exclusive_scan(v: Elem, sg: Subgroup) -> Elem {{
+{exclusive_scan}
+}}
+",
+        prelude = wgsl_prelude::<T>(op),
+        reduce = op.wgsl_subgroup_reduce(),
+    )
+}
+
+/// [`wgsl_prelude`] plus the declarations of [`wgsl_subgroup_prelude`] emulated in workgroup
+/// memory, for devices without subgroup operations.
+pub(crate) fn wgsl_emulated_subgroup_prelude<T: ScanElement>(op: ScanOp) -> String {
+    format!(
+        "{}\n{}",
+        wgsl_prelude::<T>(op),
+        include_str!("subgroup_emulated.wgsl")
+    )
+}
+
+/// A user-defined monoid for the block Blelloch scan, written as WGSL snippets.
+///
+/// The WGSL type must have the same memory layout as the host element type, which has to be
+/// `bytemuck::Pod`. For example `(count, mean)` pairs can be declared as
+/// `struct Pair { count: u32, mean: f32 }` on the GPU and as a `#[repr(C)]` struct with the same
+/// fields on the host. The `combine` must be associative and `identity` neutral on both sides.
+#[derive(Clone, Debug, Default)]
+pub struct WgslMonoid {
+    /// WGSL declarations the monoid needs, e.g. the struct of the element type and helper
+    /// functions.
+    pub declarations: String,
+    /// Name of the WGSL element type.
+    pub ty: String,
+    /// WGSL expression of the identity element.
+    pub identity: String,
+    /// Statements of `fn combine(a: Elem, b: Elem) -> Elem` including the `return`, where `a`
+    /// precedes `b` in the input.
+    pub combine: String,
+}
+
+impl WgslMonoid {
+    /// WGSL prelude declaring `Elem`, `identity()` and `combine(a, b)` like [`wgsl_prelude`].
+    pub(crate) fn wgsl_prelude(&self) -> String {
+        format!(
+            "{declarations}
+alias Elem = {ty};
+
+fn identity() -> Elem {{
+    return {identity};
+}}
+
+fn combine(a: Elem, b: Elem) -> Elem {{
+    {combine}
+}}
+",
+            declarations = self.declarations,
+            ty = self.ty,
+            identity = self.identity,
+            combine = self.combine,
+        )
+    }
+}
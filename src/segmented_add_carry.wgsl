@@ -0,0 +1,56 @@
+// `Elem`, `identity()` and `combine(a, b)` of the scan operator are declared by the prelude
+// which is prepended on the host.
+// Matches the workgroup size of the block scan
+override WG_SIZE: u32 = 64u;
+
+struct Level {
+    len: u32,
+};
+
+@group(0) @binding(0) var<storage, read_write> global_data: array<Elem>;
+@group(0) @binding(1) var<storage, read> global_flags: array<u32>;
+@group(0) @binding(2) var<storage, read> block_sum: array<Elem>;
+// Number of elements on the level of `global_data`, the buffers may be longer
+@group(0) @binding(3) var<uniform> level: Level;
+
+// Local index of the first segment start in the block
+var<workgroup> first_head: atomic<u32>;
+
+fn linearize_workgroup_id(wid: vec3<u32>, num_wg: vec3<u32>) -> u32 {
+    // linear = x + y*X + z*(X*Y)
+    return wid.x + wid.y * num_wg.x + wid.z * (num_wg.x * num_wg.y);
+}
+
+@compute @workgroup_size(WG_SIZE)
+fn add_carry(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>,
+) {
+    let n_data = level.len;
+    let n_blocks = (n_data + WG_SIZE - 1u) / WG_SIZE;
+
+    // The block sums are scanned inclusively, so the carry is the previous entry and the first
+    // block has none.
+    let wg_linear = linearize_workgroup_id(wid, num_wg);
+    if (wg_linear >= n_blocks || wg_linear == 0u) {
+        return;
+    }
+
+    if (lid.x == 0u) {
+        atomicStore(&first_head, WG_SIZE);
+    }
+    workgroupBarrier();
+
+    let global_idx = wg_linear * WG_SIZE + lid.x;
+    let in_range = global_idx < n_data;
+    if (in_range && global_flags[global_idx] != 0u) {
+        atomicMin(&first_head, lid.x);
+    }
+    workgroupBarrier();
+
+    // The carry stops at the first segment start of the block
+    if (in_range && lid.x < atomicLoad(&first_head)) {
+        global_data[global_idx] = combine(block_sum[wg_linear - 1u], global_data[global_idx]);
+    }
+}
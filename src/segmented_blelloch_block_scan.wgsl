@@ -0,0 +1,217 @@
+// `Elem`, `identity()` and `combine(a, b)` of the scan operator are declared by the prelude
+// which is prepended on the host.
+// Invocations per workgroup, a power of two
+override WG_SIZE: u32 = 64u;
+// Scan inclusively instead of exclusively. Only used for the input level, the block sums are
+// always scanned inclusively.
+override INCLUSIVE: bool = false;
+
+struct Level {
+    len: u32,
+};
+
+@group(0) @binding(0) var<storage, read_write> global_data: array<Elem>;
+@group(0) @binding(1) var<storage, read> global_flags: array<u32>;
+@group(0) @binding(2) var<storage, read_write> block_sum: array<Elem>;
+@group(0) @binding(3) var<storage, read_write> block_flags: array<u32>;
+// Number of elements on this level, the buffers may be longer
+@group(0) @binding(4) var<uniform> level: Level;
+// Input of the out-of-place scan, only read by the first level
+@group(0) @binding(5) var<storage, read> input_data: array<Elem>;
+
+// Value of a range of elements, together with whether a segment starts within the range.
+struct Segment {
+    flag: u32,
+    value: Elem,
+}
+
+var<workgroup> local_data: array<Segment, WG_SIZE>;
+
+/**
+ * Combine two adjacent ranges. A segment start in `b` drops everything accumulated in `a`.
+ */
+fn combine_segments(a: Segment, b: Segment) -> Segment {
+    if (b.flag != 0u) {
+        return b;
+    }
+    return Segment(a.flag, combine(a.value, b.value));
+}
+
+fn linearize_workgroup_id(wid: vec3<u32>, num_wg: vec3<u32>) -> u32 {
+    // linear = x + y*X + z*(X*Y)
+    return wid.x + wid.y * num_wg.x + wid.z * (num_wg.x * num_wg.y);
+}
+
+/**
+ * Get local and global index.
+ */
+fn get_indices(lid: vec3<u32>, wid: vec3<u32>, num_wg: vec3<u32>) -> array<u32, 2> {
+    let local_idx = lid.x;
+    let wg_linear = linearize_workgroup_id(wid, num_wg);
+    let block_base = wg_linear * WG_SIZE;
+    let global_idx = block_base + local_idx;
+    return array<u32, 2>(local_idx, global_idx);
+}
+
+/**
+ * Load an element and its flag of the data scanned in place, an empty segment past the end.
+ */
+fn load_global_data(n: u32, global_idx: u32) -> Segment {
+    if (global_idx < n) {
+        return Segment(global_flags[global_idx], global_data[global_idx]);
+    }
+    return Segment(0u, identity());
+}
+
+/**
+ * Load an element and its flag of the input of the out-of-place scan, an empty segment past the
+ * end.
+ */
+fn load_input_data(n: u32, global_idx: u32) -> Segment {
+    if (global_idx < n) {
+        return Segment(global_flags[global_idx], input_data[global_idx]);
+    }
+    return Segment(0u, identity());
+}
+
+/**
+ * Write out the local scan result to the global storage.
+ */
+fn copy_local_data_to_global(n: u32, local_idx: u32, global_idx: u32, value: Segment) {
+    if (global_idx < n) {
+        var result = local_data[local_idx].value;
+        if (INCLUSIVE) {
+            result = combine_segments(local_data[local_idx], value).value;
+        } else if (value.flag != 0u) {
+            // The exclusive scan restarts with the identity at a segment start
+            result = identity();
+        }
+        global_data[global_idx] = result;
+    }
+}
+
+/**
+ * Execute up-sweep step of the Blelloch scan over the segments.
+ */
+fn up_sweep(local_idx: u32) {
+    var step = 2u;
+    while (step <= WG_SIZE) {
+        let num_targets = WG_SIZE / step;
+        if (local_idx < num_targets) {
+            let target_idx = (local_idx + 1u) * step - 1u;
+            local_data[target_idx] = combine_segments(local_data[target_idx - (step >> 1u)], local_data[target_idx]);
+        }
+        workgroupBarrier();
+        step = step << 1u;
+    }
+}
+
+/**
+ * Execute down-sweep step of the Blelloch scan over the segments.
+ */
+fn down_sweep(local_idx: u32) {
+    var step = WG_SIZE;
+    while (step >= 2u) {
+        let num_targets = WG_SIZE / step;
+        if (local_idx < num_targets) {
+            let target_idx = (local_idx + 1u) * step - 1u;
+            let prev_idx = target_idx - (step >> 1u);
+            let prev_val = local_data[prev_idx];
+            local_data[prev_idx] = local_data[target_idx];
+            local_data[target_idx] = combine_segments(local_data[target_idx], prev_val);
+        }
+        workgroupBarrier();
+        step = step >> 1u;
+    }
+}
+
+/**
+ * Scan the block of `value`s and write the block sum and flag. The result is written to
+ * `global_data`.
+ */
+fn scan_write_sum(n: u32, local_idx: u32, global_idx: u32, wg_linear: u32, value: Segment) {
+    local_data[local_idx] = value;
+    workgroupBarrier();
+
+    up_sweep(local_idx);
+
+    // write out the block sum and whether a segment starts in the block before clearing it
+    let n_blocks = (n + WG_SIZE - 1u) / WG_SIZE;
+    if (local_idx == 0u) {
+        if (wg_linear < n_blocks) {
+            block_sum[wg_linear] = local_data[WG_SIZE - 1u].value;
+            block_flags[wg_linear] = local_data[WG_SIZE - 1u].flag;
+        }
+        local_data[WG_SIZE - 1u] = Segment(0u, identity());
+    }
+    workgroupBarrier();
+
+    down_sweep(local_idx);
+
+    copy_local_data_to_global(n, local_idx, global_idx, value);
+}
+
+/**
+ * Scan the block of `value`s which fits into one block. The result is written to `global_data`.
+ */
+fn scan_no_sum(n: u32, local_idx: u32, global_idx: u32, value: Segment) {
+    local_data[local_idx] = value;
+    workgroupBarrier();
+
+    up_sweep(local_idx);
+
+    if (local_idx == 0u) {
+        local_data[WG_SIZE - 1u] = Segment(0u, identity());
+    }
+    workgroupBarrier();
+
+    down_sweep(local_idx);
+
+    copy_local_data_to_global(n, local_idx, global_idx, value);
+}
+
+@compute @workgroup_size(WG_SIZE)
+fn block_scan_write_sum(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>
+) {
+    let n = level.len;
+    let indices = get_indices(lid, wid, num_wg);
+    let wg_linear = linearize_workgroup_id(wid, num_wg);
+    scan_write_sum(n, indices[0], indices[1], wg_linear, load_global_data(n, indices[1]));
+}
+
+@compute @workgroup_size(WG_SIZE)
+fn block_scan_write_sum_from_input(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>
+) {
+    let n = level.len;
+    let indices = get_indices(lid, wid, num_wg);
+    let wg_linear = linearize_workgroup_id(wid, num_wg);
+    scan_write_sum(n, indices[0], indices[1], wg_linear, load_input_data(n, indices[1]));
+}
+
+@compute @workgroup_size(WG_SIZE)
+fn block_scan_no_sum(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>
+) {
+    let n = level.len;
+    let indices = get_indices(lid, wid, num_wg);
+    scan_no_sum(n, indices[0], indices[1], load_global_data(n, indices[1]));
+}
+
+@compute @workgroup_size(WG_SIZE)
+fn block_scan_no_sum_from_input(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>
+) {
+    let n = level.len;
+    let indices = get_indices(lid, wid, num_wg);
+    scan_no_sum(n, indices[0], indices[1], load_input_data(n, indices[1]));
+}
@@ -0,0 +1,745 @@
+use crate::prefix_scan::{BufferRange, PrefixScan, ScanConfig};
+use crate::readback::{Readback, ReadbackBuffer, WorkDone};
+use crate::scan_element::ScanElement;
+use crate::scan_error::ScanError;
+use crate::scan_op::{check_op, wgsl_prelude};
+use crate::utils::{
+    BindGroupCache, LevelUniforms, StorageBinding, align_up, bind_ranges, check_buffer_ranges,
+    check_device, check_len, check_workgroup_size, level_lens, read_buffer, shader_source,
+    split_dispatch_3d, wait_for_previous_submit,
+};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Blocked Blelloch scan which restarts at every segment start.
+///
+/// Segment starts are marked by nonzero head flags, one `u32` per element, uploaded with
+/// [`upload_flags`](Self::upload_flags) or written to [`flags_buffer`](Self::flags_buffer) on the
+/// GPU. Without any flags set, the result is the same as the unsegmented scan.
+///
+/// The flags must cover the length of the scan, so when the scans get longer the flags are
+/// uploaded before the data, and after it when they get shorter. The segmented scans load one
+/// element per invocation and add the carries in a separate pass, so
+/// [`ScanConfig::elems_per_thread`], [`ScanConfig::fused_carry`] and
+/// [`ScanConfig::subgroup_sizes`] are rejected.
+pub struct SegmentedBlockBlellochGpuContext<T: ScanElement = u32>(SegmentedScan<T>);
+
+/// Subgroup scan which restarts at every segment start, see [`SegmentedBlockBlellochGpuContext`].
+pub struct SegmentedSubgroupScanGpuContext<T: ScanElement = u32>(SegmentedScan<T>);
+
+/// Block scan hierarchy shared by the segmented contexts. Next to the data, every level has a
+/// buffer of flags which tells whether a segment starts within the corresponding range of the
+/// input, so the add-carry passes can stop the carries at the segment boundaries.
+struct SegmentedScan<T: ScanElement> {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    wg_size: u32,
+    // The input level is scanned according to the scan kind, the block sums always inclusively.
+    // It reads from the input in the out-of-place scan.
+    pipeline_first_write_sum: wgpu::ComputePipeline,
+    pipeline_first_no_sum: wgpu::ComputePipeline,
+    pipeline_input_write_sum: wgpu::ComputePipeline,
+    pipeline_input_no_sum: wgpu::ComputePipeline,
+    pipeline_write_sum: wgpu::ComputePipeline,
+    pipeline_no_sum: wgpu::ComputePipeline,
+    pipeline_add_carry: wgpu::ComputePipeline,
+    // The levels above the input level, level `i` at index `i - 1`
+    bind_groups_write_sum: Vec<wgpu::BindGroup>,
+    bind_groups_no_sum: Vec<wgpu::BindGroup>,
+    bind_groups_add_carry: Vec<wgpu::BindGroup>,
+    // The input level binds the data being scanned, which is a range of the caller's buffer in a
+    // buffer scan
+    first_levels: BindGroupCache<FirstLevel>,
+    input: Option<wgpu::Buffer>,
+    data_buffers: Vec<wgpu::Buffer>,
+    flag_buffers: Vec<wgpu::Buffer>,
+    level_uniforms: LevelUniforms,
+    readback: ReadbackBuffer,
+    capacity: usize,
+    len: AtomicUsize,
+    // Number of elements the uploaded flags cover, all of them until flags are uploaded
+    flags_len: AtomicUsize,
+    _marker: PhantomData<T>,
+}
+
+/// Bind groups of the input level. The ones which also bind the level above it are `None` if the
+/// scan has a single level.
+#[derive(Clone)]
+struct FirstLevel {
+    from_input: bool,
+    write_sum: Option<wgpu::BindGroup>,
+    no_sum: wgpu::BindGroup,
+    add_carry: Option<wgpu::BindGroup>,
+}
+
+impl<T: ScanElement> SegmentedScan<T> {
+    fn create(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        capacity: usize,
+        config: &ScanConfig,
+        required_features: wgpu::Features,
+        default_wg_size: u32,
+        block_scan_source: &str,
+    ) -> anyhow::Result<Self> {
+        if config.elems_per_thread != 1 {
+            return Err(ScanError::Unsupported("elems_per_thread in a segmented scan").into());
+        }
+        if config.fused_carry {
+            return Err(ScanError::Unsupported("fused carries in a segmented scan").into());
+        }
+        if config.subgroup_sizes.is_some() {
+            return Err(ScanError::Unsupported("subgroup sizes in a segmented scan").into());
+        }
+        let wg_size = config.workgroup_size.unwrap_or(default_wg_size);
+        // The block scans share one segment, a flag next to the element, per invocation, and
+        // the subgroup scan one more for the block
+        let segment_size = align_up(size_of::<u32>() + size_of::<T>(), align_of::<T>().max(4));
+        check_workgroup_size(device, wg_size, (wg_size as u64 + 1) * segment_size as u64)?;
+        check_device(
+            device,
+            required_features,
+            (capacity * size_of::<T>()) as u64,
+            capacity.div_ceil(wg_size as usize) as u32,
+        )?;
+        check_op::<T>(config.op)?;
+
+        let prelude = wgsl_prelude::<T>(config.op);
+
+        // The GL backend caches programs per module and entry point regardless of the pipeline
+        // constants, so the input level gets a module of its own
+        let input_block_scan_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("segmented input block-scan shader"),
+            source: shader_source(&prelude, block_scan_source),
+        });
+        let block_scan_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("segmented block-scan shader"),
+            source: shader_source(&prelude, block_scan_source),
+        });
+
+        let add_carry_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("segmented add-carry shader"),
+            source: shader_source(&prelude, include_str!("segmented_add_carry.wgsl")),
+        });
+
+        let create_pipeline = |label: &str,
+                               module: &wgpu::ShaderModule,
+                               entry_point: &str,
+                               constants: &[(&str, f64)]| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: None,
+                module,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants,
+                    ..Default::default()
+                },
+                cache: Default::default(),
+            })
+        };
+
+        let input_constants = [
+            ("INCLUSIVE", config.kind.inclusive_constant()),
+            ("WG_SIZE", wg_size as f64),
+        ];
+        let block_sum_constants = [("INCLUSIVE", 1.0), ("WG_SIZE", wg_size as f64)];
+        let pipeline_first_write_sum = create_pipeline(
+            "segmented input block_scan_write_sum pipeline",
+            &input_block_scan_shader,
+            "block_scan_write_sum",
+            &input_constants,
+        );
+        let pipeline_first_no_sum = create_pipeline(
+            "segmented input block_scan_no_sum pipeline",
+            &input_block_scan_shader,
+            "block_scan_no_sum",
+            &input_constants,
+        );
+        // The out-of-place scan reads the input in the first level only
+        let pipeline_input_write_sum = create_pipeline(
+            "segmented input block_scan_write_sum_from_input pipeline",
+            &input_block_scan_shader,
+            "block_scan_write_sum_from_input",
+            &input_constants,
+        );
+        let pipeline_input_no_sum = create_pipeline(
+            "segmented input block_scan_no_sum_from_input pipeline",
+            &input_block_scan_shader,
+            "block_scan_no_sum_from_input",
+            &input_constants,
+        );
+        let pipeline_write_sum = create_pipeline(
+            "segmented block_scan_write_sum pipeline",
+            &block_scan_shader,
+            "block_scan_write_sum",
+            &block_sum_constants,
+        );
+        let pipeline_no_sum = create_pipeline(
+            "segmented block_scan_no_sum pipeline",
+            &block_scan_shader,
+            "block_scan_no_sum",
+            &block_sum_constants,
+        );
+        let pipeline_add_carry = create_pipeline(
+            "segmented add_carry pipeline",
+            &add_carry_shader,
+            "add_carry",
+            &[("WG_SIZE", wg_size as f64)],
+        );
+
+        let create_level_buffers = |elms: usize| {
+            let data = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("segmented block-sum"),
+                size: (elms * size_of::<T>()).max(4) as u64,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let flags = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("segmented block-flags"),
+                size: (elms * size_of::<u32>()).max(4) as u64,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            (data, flags)
+        };
+
+        let input = config.out_of_place.then(|| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("segmented input"),
+                size: (capacity * size_of::<T>()).max(4) as u64,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        });
+        // Build the buffers and bind groups of every level the longest scan needs, shorter
+        // scans use the first levels
+        let capacity_lens = level_lens(capacity, wg_size as usize);
+        let level_uniforms = LevelUniforms::new(device, queue, capacity_lens.len());
+        let (data_buffers, flag_buffers): (Vec<wgpu::Buffer>, Vec<wgpu::Buffer>) = capacity_lens
+            .iter()
+            .map(|&level_len| create_level_buffers(level_len))
+            .unzip();
+
+        // bind group: (prev_level -> this_level)
+        let bind_groups_write_sum: Vec<wgpu::BindGroup> = (2..data_buffers.len())
+            .map(|i| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("segmented block-scan bind group"),
+                    layout: &pipeline_write_sum.get_bind_group_layout(0),
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: data_buffers[i - 1].as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: flag_buffers[i - 1].as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: data_buffers[i].as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: flag_buffers[i].as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 4,
+                            resource: level_uniforms.binding(i - 1),
+                        },
+                    ],
+                })
+            })
+            .collect();
+
+        // Any level can be the last one, depending on the length
+        let bind_groups_no_sum: Vec<wgpu::BindGroup> = (1..data_buffers.len())
+            .map(|i| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("segmented block-scan bind group"),
+                    layout: &pipeline_no_sum.get_bind_group_layout(0),
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: data_buffers[i].as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: flag_buffers[i].as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 4,
+                            resource: level_uniforms.binding(i),
+                        },
+                    ],
+                })
+            })
+            .collect();
+
+        // Build Add-carry bind groups, the carry of level i is added to level i - 1
+        let bind_groups_add_carry: Vec<wgpu::BindGroup> = (2..data_buffers.len())
+            .map(|i| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("segmented add-carry bind group"),
+                    layout: &pipeline_add_carry.get_bind_group_layout(0),
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: data_buffers[i - 1].as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: flag_buffers[i - 1].as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: data_buffers[i].as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: level_uniforms.binding(i - 1),
+                        },
+                    ],
+                })
+            })
+            .collect();
+
+        let readback = ReadbackBuffer::new(device, (capacity * size_of::<T>()).max(4) as u64);
+
+        let scan = Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            wg_size,
+            pipeline_first_write_sum,
+            pipeline_first_no_sum,
+            pipeline_input_write_sum,
+            pipeline_input_no_sum,
+            pipeline_write_sum,
+            pipeline_no_sum,
+            pipeline_add_carry,
+            bind_groups_write_sum,
+            bind_groups_no_sum,
+            bind_groups_add_carry,
+            first_levels: BindGroupCache::new(),
+            input,
+            data_buffers,
+            flag_buffers,
+            level_uniforms,
+            readback,
+            capacity,
+            len: AtomicUsize::new(capacity),
+            flags_len: AtomicUsize::new(capacity),
+            _marker: PhantomData,
+        };
+        Ok(scan)
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    fn set_len(&self, len: usize) -> anyhow::Result<()> {
+        check_len(self.capacity, len)?;
+        let flags_len = self.flags_len.load(Ordering::Relaxed);
+        if len > flags_len {
+            return Err(ScanError::FlagsTooShort { len, flags_len }.into());
+        }
+        self.len.store(len, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn input_buffer(&self) -> &wgpu::Buffer {
+        self.input.as_ref().unwrap_or(&self.data_buffers[0])
+    }
+
+    fn upload_data(&self, input: &[T]) -> anyhow::Result<()> {
+        self.set_len(input.len())?;
+        self.queue
+            .write_buffer(self.input_buffer(), 0, bytemuck::cast_slice(input));
+        Ok(())
+    }
+
+    fn upload_flags(&self, flags: &[u32]) -> anyhow::Result<()> {
+        check_len(self.capacity, flags.len())?;
+        let len = self.len();
+        if flags.len() < len {
+            return Err(ScanError::FlagsTooShort {
+                len,
+                flags_len: flags.len(),
+            }
+            .into());
+        }
+        self.flags_len.store(flags.len(), Ordering::Relaxed);
+        self.queue
+            .write_buffer(&self.flag_buffers[0], 0, bytemuck::cast_slice(flags));
+        Ok(())
+    }
+
+    /// Bind groups of the input level which scans `data` with the flags of the context, after
+    /// reading it from `input` if it is set.
+    fn first_level(&self, data: &StorageBinding, input: Option<&StorageBinding>) -> FirstLevel {
+        self.first_levels.get_or_create(data, input, || {
+            let (pipeline_write_sum, pipeline_no_sum) = if input.is_some() {
+                (&self.pipeline_input_write_sum, &self.pipeline_input_no_sum)
+            } else {
+                (&self.pipeline_first_write_sum, &self.pipeline_first_no_sum)
+            };
+            let input_entry = input.map(|input| wgpu::BindGroupEntry {
+                binding: 5,
+                resource: input.resource(),
+            });
+            let flags = &self.flag_buffers[0];
+            let sums = self.data_buffers.get(1).zip(self.flag_buffers.get(1));
+            let write_sum = sums.map(|(sums, sum_flags)| {
+                let entries: Vec<wgpu::BindGroupEntry> = [
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: data.resource(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: flags.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: sums.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: sum_flags.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: self.level_uniforms.binding(0),
+                    },
+                ]
+                .into_iter()
+                .chain(input_entry.clone())
+                .collect();
+                self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("segmented block-scan bind group"),
+                    layout: &pipeline_write_sum.get_bind_group_layout(0),
+                    entries: &entries,
+                })
+            });
+            let entries: Vec<wgpu::BindGroupEntry> = [
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: data.resource(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: flags.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: self.level_uniforms.binding(0),
+                },
+            ]
+            .into_iter()
+            .chain(input_entry)
+            .collect();
+            let no_sum = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("segmented block-scan bind group"),
+                layout: &pipeline_no_sum.get_bind_group_layout(0),
+                entries: &entries,
+            });
+            let add_carry = sums.map(|(sums, _)| {
+                self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("segmented add-carry bind group"),
+                    layout: &self.pipeline_add_carry.get_bind_group_layout(0),
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: data.resource(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: flags.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: sums.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: self.level_uniforms.binding(0),
+                        },
+                    ],
+                })
+            });
+            FirstLevel {
+                from_input: input.is_some(),
+                write_sum,
+                no_sum,
+                add_carry,
+            }
+        })
+    }
+
+    fn encode_scan(&self, encoder: &mut wgpu::CommandEncoder) {
+        let input = self.input.as_ref().map(StorageBinding::entire);
+        self.encode_levels(
+            encoder,
+            &StorageBinding::entire(&self.data_buffers[0]),
+            input.as_ref(),
+        );
+    }
+
+    /// Record a scan of the first `len` elements of `data` into `encoder`, reading them from
+    /// `input` first if it is set.
+    fn encode_levels(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        data: &StorageBinding,
+        input: Option<&StorageBinding>,
+    ) {
+        let len = self.len();
+        if len == 0 {
+            return;
+        }
+        let first_level = self.first_level(data, input);
+        let max_dim = self.device.limits().max_compute_workgroups_per_dimension;
+        let lens = level_lens(len, self.wg_size as usize);
+        let last_idx = lens.len() - 1;
+
+        // Each scan writes its own lengths, so scans of different lengths can share an encoder
+        self.level_uniforms.encode_write(encoder, &lens);
+
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+
+        // apply the scan for block sums recursively until the size of the block sums array becomes smaller than one block size
+        for (i, &level_len) in lens[..last_idx].iter().enumerate() {
+            let (pipeline, bind_group) = match (i, &first_level.write_sum) {
+                (0, Some(bind_group)) if first_level.from_input => {
+                    (&self.pipeline_input_write_sum, bind_group)
+                }
+                (0, Some(bind_group)) => (&self.pipeline_first_write_sum, bind_group),
+                _ => (&self.pipeline_write_sum, &self.bind_groups_write_sum[i - 1]),
+            };
+            let workgroups_needed = (level_len as u32).div_ceil(self.wg_size).max(1);
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            let [x, y, z] = split_dispatch_3d(workgroups_needed, max_dim);
+            pass.dispatch_workgroups(x, y, z);
+        }
+
+        // The last sums also requires scan but no need to write the new block sums since it is already fitting in one block
+        let (pipeline, bind_group) = match last_idx {
+            0 if first_level.from_input => (&self.pipeline_input_no_sum, &first_level.no_sum),
+            0 => (&self.pipeline_first_no_sum, &first_level.no_sum),
+            _ => (
+                &self.pipeline_no_sum,
+                &self.bind_groups_no_sum[last_idx - 1],
+            ),
+        };
+        let workgroups_needed = (lens[last_idx] as u32).div_ceil(self.wg_size).max(1);
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        let [x, y, z] = split_dispatch_3d(workgroups_needed, max_dim);
+        pass.dispatch_workgroups(x, y, z);
+
+        // add carry to the previous data up to the first segment start of each block
+        pass.set_pipeline(&self.pipeline_add_carry);
+        for level in (1..=last_idx).rev() {
+            let bind_group = match (level, &first_level.add_carry) {
+                (1, Some(bind_group)) => bind_group,
+                _ => &self.bind_groups_add_carry[level - 2],
+            };
+            let block_len = lens[level - 1] as u32;
+            let workgroups_needed = block_len.div_ceil(self.wg_size).max(1);
+
+            pass.set_bind_group(0, bind_group, &[]);
+            let [x, y, z] = split_dispatch_3d(workgroups_needed, max_dim);
+            pass.dispatch_workgroups(x, y, z);
+        }
+    }
+
+    fn encode_scan_buffer(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        input: BufferRange<'_>,
+        output: Option<BufferRange<'_>>,
+    ) -> anyhow::Result<()> {
+        let output = check_buffer_ranges::<T>(input, output)?;
+        self.set_len(input.len)?;
+        if input.len == 0 {
+            return Ok(());
+        }
+        if let Some(bindings) = bind_ranges::<T>(&self.device, encoder, input, output, 1) {
+            self.encode_levels(encoder, &bindings.data, bindings.input.as_ref());
+            return Ok(());
+        }
+        let byte_len = (input.len * size_of::<T>()) as u64;
+        encoder.copy_buffer_to_buffer(input.buffer, input.offset, self.input_buffer(), 0, byte_len);
+        self.encode_scan(encoder);
+        encoder.copy_buffer_to_buffer(
+            &self.data_buffers[0],
+            0,
+            output.buffer,
+            output.offset,
+            byte_len,
+        );
+        Ok(())
+    }
+
+    fn run_prefix_scan(&self) {
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        self.encode_scan(&mut encoder);
+        self.queue.submit([encoder.finish()]);
+    }
+
+    fn read_computed_data(&self) -> anyhow::Result<Vec<T>> {
+        read_buffer(
+            &self.device,
+            &self.queue,
+            &self.data_buffers[0],
+            &self.readback,
+            self.len(),
+        )
+    }
+
+    fn read_computed_data_async(&self) -> Readback<T> {
+        Readback::new(
+            &self.device,
+            &self.queue,
+            &self.data_buffers[0],
+            &self.readback,
+            self.len(),
+        )
+    }
+
+    fn wait_idle(&self) -> anyhow::Result<()> {
+        self.device.poll(wgpu::PollType::wait_indefinitely())?;
+        Ok(())
+    }
+}
+
+/// Implement the public surface of a segmented context by delegating to its [`SegmentedScan`],
+/// created with the given features, default workgroup size and block-scan shader.
+macro_rules! impl_segmented_context {
+    ($context:ident, $required_features:expr, $default_wg_size:expr, $block_scan_source:expr) => {
+        impl<T: ScanElement> $context<T> {
+            /// Upload the head flags, a nonzero flag starts a new segment at that element. The
+            /// flags are not cleared between scans, so they must cover the current length and
+            /// the length of every following scan, which is rejected with
+            /// [`ScanError::FlagsTooShort`] otherwise.
+            pub fn upload_flags(&self, flags: &[u32]) -> anyhow::Result<()> {
+                self.0.upload_flags(flags)
+            }
+
+            /// Buffer holding the head flags, for filling them on the GPU. The flags written to
+            /// it are taken to cover the whole capacity.
+            pub fn flags_buffer(&self) -> &wgpu::Buffer {
+                self.0.flags_len.store(self.0.capacity, Ordering::Relaxed);
+                &self.0.flag_buffers[0]
+            }
+
+            /// Buffer the input is uploaded to, which is the output buffer unless the scan is out
+            /// of place.
+            pub fn input_buffer(&self) -> &wgpu::Buffer {
+                self.0.input_buffer()
+            }
+
+            /// Buffer holding the result.
+            pub fn output_buffer(&self) -> &wgpu::Buffer {
+                &self.0.data_buffers[0]
+            }
+        }
+
+        impl<T: ScanElement> PrefixScan<T> for $context<T> {
+            fn required_features() -> wgpu::Features {
+                $required_features
+            }
+
+            fn from_device(
+                device: &wgpu::Device,
+                queue: &wgpu::Queue,
+                capacity: usize,
+                config: &ScanConfig,
+            ) -> anyhow::Result<Self> {
+                Ok(Self(SegmentedScan::create(
+                    device,
+                    queue,
+                    capacity,
+                    config,
+                    Self::required_features(),
+                    $default_wg_size,
+                    $block_scan_source,
+                )?))
+            }
+
+            fn capacity(&self) -> usize {
+                self.0.capacity
+            }
+
+            fn len(&self) -> usize {
+                self.0.len()
+            }
+
+            fn set_len(&self, len: usize) -> anyhow::Result<()> {
+                self.0.set_len(len)
+            }
+
+            fn upload_data(&self, input: &[T]) -> anyhow::Result<()> {
+                self.0.upload_data(input)
+            }
+
+            fn encode_scan(&self, encoder: &mut wgpu::CommandEncoder) {
+                self.0.encode_scan(encoder);
+            }
+
+            fn encode_scan_buffer(
+                &self,
+                encoder: &mut wgpu::CommandEncoder,
+                input: BufferRange<'_>,
+                output: Option<BufferRange<'_>>,
+            ) -> anyhow::Result<()> {
+                self.0.encode_scan_buffer(encoder, input, output)
+            }
+
+            fn run_prefix_scan(&self) {
+                self.0.run_prefix_scan();
+            }
+
+            fn read_computed_data(&self) -> anyhow::Result<Vec<T>> {
+                self.0.read_computed_data()
+            }
+
+            fn read_computed_data_async(&self) -> Readback<T> {
+                self.0.read_computed_data_async()
+            }
+
+            fn wait_for_previous_submit(&self) -> anyhow::Result<()> {
+                wait_for_previous_submit(&self.0.device, &self.0.queue)
+            }
+
+            fn wait_for_previous_submit_async(&self) -> WorkDone {
+                WorkDone::new(&self.0.device, &self.0.queue)
+            }
+
+            fn wait_idle(&self) -> anyhow::Result<()> {
+                self.0.wait_idle()
+            }
+        }
+    };
+}
+
+impl_segmented_context!(
+    SegmentedBlockBlellochGpuContext,
+    wgpu::Features::empty(),
+    64,
+    include_str!("segmented_blelloch_block_scan.wgsl")
+);
+
+impl_segmented_context!(
+    SegmentedSubgroupScanGpuContext,
+    wgpu::Features::SUBGROUP,
+    128,
+    include_str!("segmented_subgroup_block_scan.wgsl")
+);
@@ -0,0 +1,235 @@
+// `Elem`, `identity()` and `combine(a, b)` of the scan operator are declared by the prelude
+// which is prepended on the host.
+// Invocations per workgroup, a power of two
+override WG_SIZE: u32 = 128u;
+// Scan inclusively instead of exclusively. Only used for the input level, the block sums are
+// always scanned inclusively.
+override INCLUSIVE: bool = false;
+
+struct Level {
+    len: u32,
+};
+
+@group(0) @binding(0) var<storage, read_write> global_data: array<Elem>;
+@group(0) @binding(1) var<storage, read> global_flags: array<u32>;
+@group(0) @binding(2) var<storage, read_write> block_sum: array<Elem>;
+@group(0) @binding(3) var<storage, read_write> block_flags: array<u32>;
+// Number of elements on this level, the buffers may be longer
+@group(0) @binding(4) var<uniform> level: Level;
+// Input of the out-of-place scan, only read by the first level
+@group(0) @binding(5) var<storage, read> input_data: array<Elem>;
+
+// Value of a range of elements, together with whether a segment starts within the range.
+struct Segment {
+    flag: u32,
+    value: Elem,
+}
+
+// For subgroup sums + offsets, sized for the worst case of one invocation per subgroup
+var<workgroup> local_data: array<Segment, WG_SIZE>;
+var<workgroup> block_segment: Segment;
+
+/**
+ * Combine two adjacent ranges. A segment start in `b` drops everything accumulated in `a`.
+ */
+fn combine_segments(a: Segment, b: Segment) -> Segment {
+    if (b.flag != 0u) {
+        return b;
+    }
+    return Segment(a.flag, combine(a.value, b.value));
+}
+
+fn shuffle_up(s: Segment, delta: u32) -> Segment {
+    return Segment(subgroupShuffleUp(s.flag, delta), subgroupShuffleUp(s.value, delta));
+}
+
+fn linearize_workgroup_id(wid: vec3<u32>, num_wg: vec3<u32>) -> u32 {
+    // linear = x + y*X + z*(X*Y)
+    return wid.x + wid.y * num_wg.x + wid.z * (num_wg.x * num_wg.y);
+}
+
+/**
+ * Load an element and its flag of the data scanned in place, an empty segment past the end.
+ */
+fn load_global_data(global_idx: u32) -> Segment {
+    if (global_idx < level.len) {
+        return Segment(global_flags[global_idx], global_data[global_idx]);
+    }
+    return Segment(0u, identity());
+}
+
+/**
+ * Load an element and its flag of the input of the out-of-place scan, an empty segment past the
+ * end.
+ */
+fn load_input_data(global_idx: u32) -> Segment {
+    if (global_idx < level.len) {
+        return Segment(global_flags[global_idx], input_data[global_idx]);
+    }
+    return Segment(0u, identity());
+}
+
+/**
+ * Kogge-Stone inclusive scan of `v` in the subgroup, there are no subgroup builtins for
+ * segments.
+ */
+fn subgroup_inclusive_scan_segments(v: Segment, sg_size: u32, sg_lane: u32) -> Segment {
+    var incl = v;
+    for (var delta = 1u; delta < sg_size; delta = delta << 1u) {
+        let other = shuffle_up(incl, delta);
+        if (sg_lane >= delta) {
+            incl = combine_segments(other, incl);
+        }
+    }
+    return incl;
+}
+
+/**
+ * Exclusive scan of `v` in the subgroup, given its inclusive scan `incl`.
+ */
+fn subgroup_exclusive_segments(incl: Segment, sg_lane: u32) -> Segment {
+    let excl = shuffle_up(incl, 1u);
+    if (sg_lane == 0u) {
+        return Segment(0u, identity());
+    }
+    return excl;
+}
+
+/**
+ * Scan the workgroup of segments `v` and write out the result. Returns the segment of the whole
+ * block.
+ */
+fn block_scan(
+    global_idx: u32,
+    v: Segment,
+    sg_size: u32,
+    sg_lane: u32,
+    sg_id: u32,
+) -> Segment {
+    let in_range = global_idx < level.len;
+
+    let sg_incl = subgroup_inclusive_scan_segments(v, sg_size, sg_lane);
+    let sg_excl = subgroup_exclusive_segments(sg_incl, sg_lane);
+
+    // The last lane holds the segment of the whole subgroup, the only subgroup is partially
+    // filled if it is larger than the workgroup
+    let sg_last_lane = min(sg_size, WG_SIZE - sg_id * sg_size) - 1u;
+    if (sg_lane == sg_last_lane) {
+        local_data[sg_id] = sg_incl;
+    }
+    workgroupBarrier();
+
+    // The first subgroup scans the subgroup segments exclusively, `sg_size` of them at a time,
+    // since there may be more subgroups than invocations in a subgroup
+    let num_sg = (WG_SIZE + sg_size - 1u) / sg_size;
+    if (sg_id == 0u) {
+        var run = Segment(0u, identity());
+        for (var base = 0u; base < num_sg; base = base + sg_size) {
+            let i = base + sg_lane;
+            var segments = Segment(0u, identity());
+            if (i < num_sg) {
+                segments = local_data[i];
+            }
+            let incl = subgroup_inclusive_scan_segments(segments, sg_size, sg_lane);
+            let excl = subgroup_exclusive_segments(incl, sg_lane);
+            if (i < num_sg) {
+                local_data[i] = combine_segments(run, excl);
+            }
+            // The last lane with a subgroup segment holds the total of this round
+            let last = min(sg_size, num_sg - base) - 1u;
+            run = combine_segments(
+                run,
+                Segment(subgroupShuffle(incl.flag, last), subgroupShuffle(incl.value, last)),
+            );
+        }
+        if (sg_lane == 0u) {
+            block_segment = run;
+        }
+    }
+    workgroupBarrier();
+
+    if (in_range) {
+        var result = combine_segments(local_data[sg_id], sg_excl).value;
+        if (INCLUSIVE) {
+            result = combine_segments(local_data[sg_id], sg_incl).value;
+        } else if (v.flag != 0u) {
+            // The exclusive scan restarts with the identity at a segment start
+            result = identity();
+        }
+        global_data[global_idx] = result;
+    }
+    return block_segment;
+}
+
+/**
+ * Store the block sum and whether a segment starts in the block for the next block scan.
+ */
+fn write_block_sum(lid: vec3<u32>, wg_linear: u32, block_total: Segment) {
+    let n_blocks = (level.len + WG_SIZE - 1u) / WG_SIZE;
+    if (lid.x == 0u && wg_linear < n_blocks) {
+        block_sum[wg_linear] = block_total.value;
+        block_flags[wg_linear] = block_total.flag;
+    }
+}
+
+@compute @workgroup_size(WG_SIZE)
+fn block_scan_write_sum(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>,
+    @builtin(subgroup_size) sg_size: u32,
+    @builtin(subgroup_invocation_id) sg_lane: u32,
+    @builtin(subgroup_id) sg_id: u32,
+) {
+    let wg_linear = linearize_workgroup_id(wid, num_wg);
+    let global_idx = wg_linear * WG_SIZE + lid.x;
+    let v = load_global_data(global_idx);
+    let block_total = block_scan(global_idx, v, sg_size, sg_lane, sg_id);
+    write_block_sum(lid, wg_linear, block_total);
+}
+
+@compute @workgroup_size(WG_SIZE)
+fn block_scan_write_sum_from_input(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>,
+    @builtin(subgroup_size) sg_size: u32,
+    @builtin(subgroup_invocation_id) sg_lane: u32,
+    @builtin(subgroup_id) sg_id: u32,
+) {
+    let wg_linear = linearize_workgroup_id(wid, num_wg);
+    let global_idx = wg_linear * WG_SIZE + lid.x;
+    let v = load_input_data(global_idx);
+    let block_total = block_scan(global_idx, v, sg_size, sg_lane, sg_id);
+    write_block_sum(lid, wg_linear, block_total);
+}
+
+@compute @workgroup_size(WG_SIZE)
+fn block_scan_no_sum(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>,
+    @builtin(subgroup_size) sg_size: u32,
+    @builtin(subgroup_invocation_id) sg_lane: u32,
+    @builtin(subgroup_id) sg_id: u32,
+) {
+    let wg_linear = linearize_workgroup_id(wid, num_wg);
+    let global_idx = wg_linear * WG_SIZE + lid.x;
+    let v = load_global_data(global_idx);
+    _ = block_scan(global_idx, v, sg_size, sg_lane, sg_id);
+}
+
+@compute @workgroup_size(WG_SIZE)
+fn block_scan_no_sum_from_input(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>,
+    @builtin(subgroup_size) sg_size: u32,
+    @builtin(subgroup_invocation_id) sg_lane: u32,
+    @builtin(subgroup_id) sg_id: u32,
+) {
+    let wg_linear = linearize_workgroup_id(wid, num_wg);
+    let global_idx = wg_linear * WG_SIZE + lid.x;
+    let v = load_input_data(global_idx);
+    _ = block_scan(global_idx, v, sg_size, sg_lane, sg_id);
+}
@@ -1,9 +1,17 @@
-@group(0) @binding(0) var<storage, read_write> data: array<u32>;
+// `Elem`, `identity()` and `combine(a, b)` of the scan operator are declared by the prelude
+// which is prepended on the host.
+struct Uniform {
+  step: u32,
+  padded_len: u32,
+};
+
+@group(0) @binding(0) var<storage, read_write> data: array<Elem>;
+@group(0) @binding(1) var<uniform> uni: Uniform;
 
 @compute @workgroup_size(1)
 fn main() {
-  let n = arrayLength(&data);
+  let n = uni.padded_len;
   if (n > 0u) {
-    data[n - 1u] = 0u;
+    data[n - 1u] = identity();
   }
 }
\ No newline at end of file
@@ -1,7 +1,20 @@
-const WG_SIZE: u32 = 128u;
+// `Elem`, `identity()` and `combine(a, b)` of the scan operator are declared by the prelude
+// which is prepended on the host.
+// Matches the workgroup size of the block scan
+override WG_SIZE: u32 = 128u;
+// Scan all levels inclusively instead of exclusively
+override INCLUSIVE: bool = false;
+// Elements per invocation of the block scan, the blocks are `WG_SIZE * ELEMS_PER_THREAD` long
+override ELEMS_PER_THREAD: u32 = 1u;
 
-@group(0) @binding(0) var<storage, read_write> global_data: array<u32>;
-@group(0) @binding(1) var<storage, read> block_sum: array<u32>;
+struct Level {
+    len: u32,
+};
+
+@group(0) @binding(0) var<storage, read_write> global_data: array<Elem>;
+@group(0) @binding(1) var<storage, read> block_sum: array<Elem>;
+// Number of elements on the level of `global_data`, the buffers may be longer
+@group(0) @binding(2) var<uniform> level: Level;
 
 fn linearize_workgroup_id(wid: vec3<u32>, num_wg: vec3<u32>) -> u32 {
     return wid.x + wid.y * num_wg.x + wid.z * (num_wg.x * num_wg.y);
@@ -14,8 +27,9 @@ fn add_carry(
     @builtin(num_workgroups) num_wg: vec3<u32>,
     @builtin(subgroup_invocation_id) sg_lane: u32,
 ) {
-    let n_data = arrayLength(&global_data);
-    let n_blocks = arrayLength(&block_sum);
+    let n_data = level.len;
+    let block_len = WG_SIZE * ELEMS_PER_THREAD;
+    let n_blocks = (n_data + block_len - 1u) / block_len;
 
     // wg_linear is the same as the target index of block_sum
     let wg_linear = linearize_workgroup_id(wid, num_wg);
@@ -23,14 +37,26 @@ fn add_carry(
         return;
     }
 
-    let global_idx = wg_linear * WG_SIZE + lid.x;
-    if (global_idx >= n_data) {
-        return;
+    // The inclusive scan of the block sums already contains the current block,
+    // so the carry is the previous entry and the first block has none.
+    var carry_idx = wg_linear;
+    if (INCLUSIVE) {
+        if (wg_linear == 0u) {
+            return;
+        }
+        carry_idx = wg_linear - 1u;
     }
 
     // Only lane0 of each subgroup need to read the block sum and broadcast it
-    let carry_seed = select(0u, block_sum[wg_linear], sg_lane == 0u);
+    let carry_seed = select(identity(), block_sum[carry_idx], sg_lane == 0u);
     let carry = subgroupBroadcastFirst(carry_seed);
 
-    global_data[global_idx] += carry;
-}
\ No newline at end of file
+    // Consecutive invocations access consecutive elements
+    for (var i = 0u; i < ELEMS_PER_THREAD; i = i + 1u) {
+        let global_idx = wg_linear * block_len + i * WG_SIZE + lid.x;
+        if (global_idx >= n_data) {
+            return;
+        }
+        global_data[global_idx] = combine(carry, global_data[global_idx]);
+    }
+}
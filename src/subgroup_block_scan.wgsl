@@ -1,111 +1,358 @@
-const WG_SIZE: u32 = 128u;
+// `Elem`, `identity()` and `combine(a, b)` of the scan operator, the `SubgroupBuiltins` input of
+// the entry points with `subgroup_of(builtins)`, `subgroup_reduce(v, sg)` and
+// `subgroup_exclusive_scan(v, sg)`, and the `Chunk` the elements are loaded and stored in, are
+// declared by the prelude which is prepended on the host. The subgroup functions either use the
+// subgroup builtins or emulate them in workgroup memory.
+// Invocations per workgroup, a power of two
+override WG_SIZE: u32 = 128u;
+const MAX_ELEMS_PER_THREAD: u32 = 16u;
+// Scan all levels inclusively instead of exclusively
+override INCLUSIVE: bool = false;
+// Consecutive elements each invocation scans in registers, a multiple of `CHUNK_LEN`
+override ELEMS_PER_THREAD: u32 = 1u;
+// Range of the subgroup sizes of the device, powers of two. If they are equal, the size is
+// known when the pipeline is compiled instead of being read from the builtin.
+override SUBGROUP_MIN_SIZE: u32 = 4u;
+override SUBGROUP_MAX_SIZE: u32 = 128u;
 
-@group(0) @binding(0) var<storage, read_write> global_data: array<u32>;
-@group(0) @binding(1) var<storage, read_write> block_sum: array<u32>;
+struct Level {
+    len: u32,
+};
 
-// For subgroup sum + offsets
-var<workgroup> local_data: array<u32, 128u>;
+// Subgroup of an invocation
+struct Subgroup {
+    // Invocations per subgroup, between SUBGROUP_MIN_SIZE and SUBGROUP_MAX_SIZE. The only
+    // subgroup is partially filled if it is larger than the workgroup.
+    size: u32,
+    // Index of the invocation in its subgroup, 0..size
+    lane: u32,
+    // Index of the subgroup in the workgroup, 0..WG_SIZE/size
+    id: u32,
+};
+
+// Padded to a whole number of chunks
+@group(0) @binding(0) var<storage, read_write> global_data: array<Chunk>;
+@group(0) @binding(1) var<storage, read_write> block_sum: array<Elem>;
+// Number of elements on this level, the buffers may be longer
+@group(0) @binding(2) var<uniform> level: Level;
+// Input of the out-of-place scan, only read by the first level
+@group(0) @binding(3) var<storage, read> input_data: array<Chunk>;
+
+// For subgroup sum + offsets, sized for the smallest subgroups
+var<workgroup> local_data: array<Elem, (WG_SIZE + SUBGROUP_MIN_SIZE - 1u) / SUBGROUP_MIN_SIZE>;
+// Elements of this invocation
+var<private> values: array<Elem, MAX_ELEMS_PER_THREAD>;
 
 fn linearize_workgroup_id(wid: vec3<u32>, num_wg: vec3<u32>) -> u32 {
     // linear = x + y*X + z*(X*Y)
     return wid.x + wid.y * num_wg.x + wid.z * (num_wg.x * num_wg.y);
 }
 
-@compute @workgroup_size(WG_SIZE)
-fn block_scan_write_sum(
-    @builtin(local_invocation_id) lid: vec3<u32>,
-    @builtin(workgroup_id) wid: vec3<u32>,
-    @builtin(num_workgroups) num_wg: vec3<u32>,
-    @builtin(subgroup_size) sg_size: u32, // maybe 32 or 64, depends on the GPU
-    @builtin(subgroup_invocation_id) sg_lane: u32, // 0..sg_size, most probably 0..32
-    @builtin(subgroup_id) sg_id: u32, // 0..workgroup_size/subgroup_size, most probably 0..4
-) {
-    let n = arrayLength(&global_data);
+/**
+ * Load the elements of the data scanned in place into `values`, the identity past the end.
+ */
+fn load_global_data(n: u32, global_idx: u32) {
+    for (var c = 0u; c < ELEMS_PER_THREAD / CHUNK_LEN; c = c + 1u) {
+        let base = global_idx + c * CHUNK_LEN;
+        var chunk: Chunk;
+        if (base < n) {
+            chunk = global_data[base / CHUNK_LEN];
+        }
+        for (var i = 0u; i < CHUNK_LEN; i = i + 1u) {
+            var v = identity();
+            if (base + i < n) {
+                v = chunk_get(chunk, i);
+            }
+            values[c * CHUNK_LEN + i] = v;
+        }
+    }
+}
 
-    let wg_linear = linearize_workgroup_id(wid, num_wg);
-    let global_idx = wg_linear * WG_SIZE + lid.x;
-    let in_range = global_idx < n;
-    var v = 0u;
-    if (in_range) {
-        v = global_data[global_idx];
+/**
+ * Load the elements of the input of the out-of-place scan into `values`, the identity past the
+ * end.
+ */
+fn load_input_data(n: u32, global_idx: u32) {
+    for (var c = 0u; c < ELEMS_PER_THREAD / CHUNK_LEN; c = c + 1u) {
+        let base = global_idx + c * CHUNK_LEN;
+        var chunk: Chunk;
+        if (base < n) {
+            chunk = input_data[base / CHUNK_LEN];
+        }
+        for (var i = 0u; i < CHUNK_LEN; i = i + 1u) {
+            var v = identity();
+            if (base + i < n) {
+                v = chunk_get(chunk, i);
+            }
+            values[c * CHUNK_LEN + i] = v;
+        }
+    }
+}
+
+/**
+ * Write the scanned `values` out to the global storage.
+ */
+fn store_global_data(n: u32, global_idx: u32) {
+    for (var c = 0u; c < ELEMS_PER_THREAD / CHUNK_LEN; c = c + 1u) {
+        let base = global_idx + c * CHUNK_LEN;
+        if (base >= n) {
+            break;
+        }
+        // The elements of the chunk past the end are written back unchanged
+        var chunk: Chunk;
+        if (base + CHUNK_LEN > n) {
+            chunk = global_data[base / CHUNK_LEN];
+        }
+        for (var i = 0u; i < CHUNK_LEN; i = i + 1u) {
+            if (base + i < n) {
+                chunk_set(&chunk, i, values[c * CHUNK_LEN + i]);
+            }
+        }
+        global_data[base / CHUNK_LEN] = chunk;
+    }
+}
+
+/**
+ * Subgroup sum `base + sg.lane` in `local_data` for the first subgroup, the identity past the
+ * last subgroup and for the other subgroups. Every subgroup takes part in the subgroup operations
+ * on the sums, since the emulated ones synchronize the whole workgroup.
+ */
+fn load_subgroup_sum(num_sg: u32, base: u32, sg: Subgroup) -> Elem {
+    if (sg.id == 0u && base + sg.lane < num_sg) {
+        return local_data[base + sg.lane];
+    }
+    return identity();
+}
+
+/**
+ * Sum the block of `values` without scanning it. Returns the block sum in the first invocation.
+ */
+fn reduce_block(sg: Subgroup) -> Elem {
+    var total = identity();
+    for (var i = 0u; i < ELEMS_PER_THREAD; i = i + 1u) {
+        total = combine(total, values[i]);
+    }
+    let sg_sum = subgroup_reduce(total, sg);
+    if (sg.lane == 0u) {
+        local_data[sg.id] = sg_sum;
     }
+    workgroupBarrier();
 
-    // exclusive scan result in the same subgroup until this element
-    let sg_prefix = subgroupExclusiveAdd(v);
+    // The first subgroup reduces the subgroup sums, `sg.size` of them at a time
+    let num_sg = (WG_SIZE + sg.size - 1u) / sg.size;
+    var block_total = identity();
+    for (var base = 0u; base < num_sg; base = base + sg.size) {
+        block_total = combine(block_total, subgroup_reduce(load_subgroup_sum(num_sg, base, sg), sg));
+    }
+    return block_total;
+}
+
+/**
+ * Scan the block of `values` starting from `carry` and write the result to `global_data`.
+ * Returns the block sum in the first invocation.
+ */
+fn scan_block(
+    global_idx: u32,
+    n: u32,
+    carry: Elem,
+    sg: Subgroup,
+) -> Elem {
+    // Total of the elements of this invocation, scanned across the subgroup
+    var total = identity();
+    for (var i = 0u; i < ELEMS_PER_THREAD; i = i + 1u) {
+        total = combine(total, values[i]);
+    }
+    // exclusive scan result in the same subgroup until this invocation
+    let sg_prefix = subgroup_exclusive_scan(total, sg);
     // calculate the sum of all elements in the subgroup.
     // The same result will be returned for the same subgroup, no matter which lane we are in.
-    let sg_sum = subgroupAdd(v);
+    let sg_sum = subgroup_reduce(total, sg);
     // Store the sum from each subgroup into workgroup shared
-    if (sg_lane == 0u) {
-        local_data[sg_id] = sg_sum;
+    if (sg.lane == 0u) {
+        local_data[sg.id] = sg_sum;
     }
     workgroupBarrier();
 
-    // Build offsets to collect the each subgroup's scan result
-    let num_sg = (WG_SIZE + sg_size - 1u) / sg_size;
-    if (lid.x == 0u) {
-        // run exclusive scan on the subgroup sum results array
-        var sg_sum_total = 0u;
-        for (var i = 0u; i < num_sg; i = i + 1u) {
-            let tmp = local_data[i];
-            local_data[i] = sg_sum_total;
-            sg_sum_total = sg_sum_total + tmp;
-        }
-        // store the block sum for the next block scan
-        let n_blocks = arrayLength(&block_sum);
-        if (wg_linear < n_blocks) {
-            block_sum[wg_linear] = sg_sum_total;
+    // Build offsets to collect the each subgroup's scan result. The first subgroup scans the
+    // subgroup sums exclusively, `sg.size` of them at a time, since there may be more subgroups
+    // than invocations in a subgroup.
+    let num_sg = (WG_SIZE + sg.size - 1u) / sg.size;
+    var sg_sum_total = identity();
+    for (var base = 0u; base < num_sg; base = base + sg.size) {
+        let sums = load_subgroup_sum(num_sg, base, sg);
+        let sums_prefix = subgroup_exclusive_scan(sums, sg);
+        if (sg.id == 0u && base + sg.lane < num_sg) {
+            local_data[base + sg.lane] = combine(sg_sum_total, sums_prefix);
         }
+        sg_sum_total = combine(sg_sum_total, subgroup_reduce(sums, sg));
     }
     workgroupBarrier();
 
-    // Add carry from each subgroups to the subgroup prefix
-    if (in_range) {
-        global_data[global_idx] = local_data[sg_id] + sg_prefix;
+    // Add carry from each subgroups to the subgroup prefix, then scan the elements of this
+    // invocation serially
+    var running = combine(carry, combine(local_data[sg.id], sg_prefix));
+    for (var i = 0u; i < ELEMS_PER_THREAD; i = i + 1u) {
+        let v = values[i];
+        if (INCLUSIVE) {
+            running = combine(running, v);
+            values[i] = running;
+        } else {
+            values[i] = running;
+            running = combine(running, v);
+        }
+    }
+    store_global_data(n, global_idx);
+    return sg_sum_total;
+}
+
+/**
+ * Carry of the blocks before `wg_linear` in the already scanned `block_sum`.
+ */
+fn block_carry(wg_linear: u32) -> Elem {
+    // The inclusive scan of the block sums already contains the current block,
+    // so the carry is the previous entry and the first block has none.
+    if (!INCLUSIVE) {
+        return block_sum[wg_linear];
+    }
+    if (wg_linear == 0u) {
+        return identity();
+    }
+    return block_sum[wg_linear - 1u];
+}
+
+/**
+ * Store the block sum for the next block scan.
+ */
+fn write_block_sum(local_idx: u32, wg_linear: u32, n: u32, block_total: Elem) {
+    let block_len = WG_SIZE * ELEMS_PER_THREAD;
+    let n_blocks = (n + block_len - 1u) / block_len;
+    if (local_idx == 0u && wg_linear < n_blocks) {
+        block_sum[wg_linear] = block_total;
     }
 }
 
 @compute @workgroup_size(WG_SIZE)
-fn block_scan_no_sum(
+fn block_scan_write_sum(
     @builtin(local_invocation_id) lid: vec3<u32>,
     @builtin(workgroup_id) wid: vec3<u32>,
     @builtin(num_workgroups) num_wg: vec3<u32>,
+    sg_builtins: SubgroupBuiltins,
+) {
+    let n = level.len;
+    let sg = subgroup_of(sg_builtins);
+    let wg_linear = linearize_workgroup_id(wid, num_wg);
+    let global_idx = (wg_linear * WG_SIZE + lid.x) * ELEMS_PER_THREAD;
+    load_global_data(n, global_idx);
+    let block_total = scan_block(global_idx, n, identity(), sg);
+    write_block_sum(lid.x, wg_linear, n, block_total);
+}
 
-    @builtin(subgroup_size) sg_size: u32,
-    @builtin(subgroup_invocation_id) sg_lane: u32,
-    @builtin(subgroup_id) sg_id: u32,
+@compute @workgroup_size(WG_SIZE)
+fn block_scan_write_sum_from_input(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>,
+    sg_builtins: SubgroupBuiltins,
 ) {
-    let n = arrayLength(&global_data);
+    let n = level.len;
+    let sg = subgroup_of(sg_builtins);
+    let wg_linear = linearize_workgroup_id(wid, num_wg);
+    let global_idx = (wg_linear * WG_SIZE + lid.x) * ELEMS_PER_THREAD;
+    load_input_data(n, global_idx);
+    let block_total = scan_block(global_idx, n, identity(), sg);
+    write_block_sum(lid.x, wg_linear, n, block_total);
+}
 
+@compute @workgroup_size(WG_SIZE)
+fn block_scan_no_sum(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>,
+    sg_builtins: SubgroupBuiltins,
+) {
+    let n = level.len;
+    let sg = subgroup_of(sg_builtins);
     let wg_linear = linearize_workgroup_id(wid, num_wg);
-    let global_idx = wg_linear * WG_SIZE + lid.x;
-    let in_range = global_idx < n;
-    var v = 0u;
-    if (in_range) {
-        v = global_data[global_idx];
-    }
+    let global_idx = (wg_linear * WG_SIZE + lid.x) * ELEMS_PER_THREAD;
+    load_global_data(n, global_idx);
+    _ = scan_block(global_idx, n, identity(), sg);
+}
 
-    let sg_prefix = subgroupExclusiveAdd(v);
-    let sg_sum    = subgroupAdd(v);
+@compute @workgroup_size(WG_SIZE)
+fn block_scan_no_sum_from_input(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>,
+    sg_builtins: SubgroupBuiltins,
+) {
+    let n = level.len;
+    let sg = subgroup_of(sg_builtins);
+    let wg_linear = linearize_workgroup_id(wid, num_wg);
+    let global_idx = (wg_linear * WG_SIZE + lid.x) * ELEMS_PER_THREAD;
+    load_input_data(n, global_idx);
+    _ = scan_block(global_idx, n, identity(), sg);
+}
 
-    if (sg_lane == 0u) {
-        local_data[sg_id] = sg_sum;
-    }
-    workgroupBarrier();
+@compute @workgroup_size(WG_SIZE)
+fn block_reduce(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>,
+    sg_builtins: SubgroupBuiltins,
+) {
+    let n = level.len;
+    let sg = subgroup_of(sg_builtins);
+    let wg_linear = linearize_workgroup_id(wid, num_wg);
+    let global_idx = (wg_linear * WG_SIZE + lid.x) * ELEMS_PER_THREAD;
+    load_global_data(n, global_idx);
+    let block_total = reduce_block(sg);
+    write_block_sum(lid.x, wg_linear, n, block_total);
+}
 
-    let num_sg = (WG_SIZE + sg_size - 1u) / sg_size;
-    if (lid.x == 0u) {
-        var run = 0u;
-        for (var i = 0u; i < num_sg; i = i + 1u) {
-            let tmp = local_data[i];
-            local_data[i] = run;
-            run = run + tmp;
-        }
-    }
-    workgroupBarrier();
+@compute @workgroup_size(WG_SIZE)
+fn block_reduce_from_input(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>,
+    sg_builtins: SubgroupBuiltins,
+) {
+    let n = level.len;
+    let sg = subgroup_of(sg_builtins);
+    let wg_linear = linearize_workgroup_id(wid, num_wg);
+    let global_idx = (wg_linear * WG_SIZE + lid.x) * ELEMS_PER_THREAD;
+    load_input_data(n, global_idx);
+    let block_total = reduce_block(sg);
+    write_block_sum(lid.x, wg_linear, n, block_total);
+}
 
-    let carry = local_data[sg_id];
-    if (in_range) {
-        global_data[global_idx] = carry + sg_prefix;
-    }
-}
\ No newline at end of file
+@compute @workgroup_size(WG_SIZE)
+fn block_scan_carry(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>,
+    sg_builtins: SubgroupBuiltins,
+) {
+    let n = level.len;
+    let sg = subgroup_of(sg_builtins);
+    let wg_linear = linearize_workgroup_id(wid, num_wg);
+    let global_idx = (wg_linear * WG_SIZE + lid.x) * ELEMS_PER_THREAD;
+    load_global_data(n, global_idx);
+    let carry = block_carry(wg_linear);
+    _ = scan_block(global_idx, n, carry, sg);
+}
+
+@compute @workgroup_size(WG_SIZE)
+fn block_scan_carry_from_input(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(num_workgroups) num_wg: vec3<u32>,
+    sg_builtins: SubgroupBuiltins,
+) {
+    let n = level.len;
+    let sg = subgroup_of(sg_builtins);
+    let wg_linear = linearize_workgroup_id(wid, num_wg);
+    let global_idx = (wg_linear * WG_SIZE + lid.x) * ELEMS_PER_THREAD;
+    load_input_data(n, global_idx);
+    let carry = block_carry(wg_linear);
+    _ = scan_block(global_idx, n, carry, sg);
+}
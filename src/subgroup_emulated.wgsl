@@ -0,0 +1,56 @@
+// Subgroup operations emulated in workgroup memory for devices without subgroups. The
+// invocations are split into subgroups of `SUBGROUP_MAX_SIZE` consecutive invocations, the
+// only one partially filled if it is larger than the workgroup, each of them scanned with a
+// Kogge-Stone scan in `subgroup_scratch`. Every invocation of the workgroup must call the
+// functions together, since they synchronize the whole workgroup.
+
+// Values of the invocations scanned across their subgroups
+var<workgroup> subgroup_scratch: array<Elem, WG_SIZE>;
+
+struct SubgroupBuiltins {
+    @builtin(local_invocation_index) local_index: u32,
+}
+
+fn subgroup_of(builtins: SubgroupBuiltins) -> Subgroup {
+    // The host sets both sizes to the emulated one
+    let size = SUBGROUP_MAX_SIZE;
+    return Subgroup(size, builtins.local_index % size, builtins.local_index / size);
+}
+
+/**
+ * Scan `v` inclusively across the subgroup, leaving the result of each invocation in its
+ * element of `subgroup_scratch`.
+ */
+fn subgroup_inclusive_scan_scratch(v: Elem, sg: Subgroup) {
+    let local_idx = sg.id * sg.size + sg.lane;
+    // The previous call may still be reading the scratch
+    workgroupBarrier();
+    subgroup_scratch[local_idx] = v;
+    workgroupBarrier();
+
+    // The loop bound is uniform, so every invocation reaches the barriers
+    for (var delta = 1u; delta < min(SUBGROUP_MAX_SIZE, WG_SIZE); delta = delta << 1u) {
+        var x = subgroup_scratch[local_idx];
+        if (sg.lane >= delta) {
+            x = combine(subgroup_scratch[local_idx - delta], x);
+        }
+        workgroupBarrier();
+        subgroup_scratch[local_idx] = x;
+        workgroupBarrier();
+    }
+}
+
+fn subgroup_reduce(v: Elem, sg: Subgroup) -> Elem {
+    subgroup_inclusive_scan_scratch(v, sg);
+    // The last invocation of the subgroup holds the total
+    return subgroup_scratch[min(sg.id * sg.size + sg.size, WG_SIZE) - 1u];
+}
+
+fn subgroup_exclusive_scan(v: Elem, sg: Subgroup) -> Elem {
+    subgroup_inclusive_scan_scratch(v, sg);
+    // Shift the inclusive scan by one invocation to make it exclusive
+    if (sg.lane == 0u) {
+        return identity();
+    }
+    return subgroup_scratch[sg.id * sg.size + sg.lane - 1u];
+}
@@ -1,31 +1,514 @@
+use crate::prefix_scan::{
+    BufferRange, MAX_ELEMS_PER_THREAD, MAX_RECORDED_SCANS, ScanConfig, SubgroupSizes,
+};
+use crate::readback::{Readback, ReadbackBuffer, WorkDone};
+use crate::scan_error::ScanError;
+use std::collections::HashMap;
+use std::num::NonZeroU64;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 pub fn align_up(v: usize, a: usize) -> usize {
-    (v + a - 1) / a * a
-}
-pub async fn init_wgpu() -> (wgpu::Device, wgpu::Queue) {
-    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
-    let adapter = instance
-        .request_adapter(&wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::HighPerformance,
-            compatible_surface: None,
-            force_fallback_adapter: false,
-        })
-        .await
-        .expect("No adapter found");
+    v.div_ceil(a) * a
+}
+/// Create a device with `required_features`, and the `optional_features` the adapter has, on the
+/// adapter picked according to `config.gpu`. Returns `config` with the subgroup sizes of the
+/// adapter filled in if it left them open, or checked against the adapter otherwise.
+pub async fn init_wgpu(
+    mut config: ScanConfig,
+    required_features: wgpu::Features,
+    optional_features: wgpu::Features,
+) -> Result<(wgpu::Device, wgpu::Queue, ScanConfig), ScanError> {
+    let adapter = config.gpu.request_adapter().await?;
+
+    let mut missing = required_features;
+    missing.remove(adapter.features());
+    if !missing.is_empty() {
+        return Err(ScanError::MissingFeatures(missing));
+    }
 
     let mut limits = wgpu::Limits::default();
     limits.max_buffer_size = adapter.limits().max_buffer_size;
     limits.max_storage_buffer_binding_size = adapter.limits().max_storage_buffer_binding_size;
+    // Allow the largest workgroups the adapter supports for `ScanConfig::workgroup_size`
+    limits.max_compute_invocations_per_workgroup =
+        adapter.limits().max_compute_invocations_per_workgroup;
+    limits.max_compute_workgroup_size_x = adapter.limits().max_compute_workgroup_size_x;
+    limits.max_compute_workgroup_storage_size = adapter.limits().max_compute_workgroup_storage_size;
 
     let (device, queue) = adapter
         .request_device(&wgpu::DeviceDescriptor {
             label: Some("device"),
-            required_features: wgpu::Features::SUBGROUP,
+            required_features: required_features | (optional_features & adapter.features()),
             required_limits: limits,
             experimental_features: Default::default(),
             memory_hints: wgpu::MemoryHints::default(),
             trace: Default::default(),
         })
         .await
-        .expect("Failed to create device");
-    (device, queue)
+        .map_err(ScanError::RequestDevice)?;
+
+    // The sizes of adapters without subgroup operations say nothing about the emulated ones
+    if device.features().contains(wgpu::Features::SUBGROUP) {
+        let adapter_sizes = SubgroupSizes::of_adapter(&adapter.get_info());
+        match config.subgroup_sizes {
+            Some(sizes) if !sizes.covers(adapter_sizes) => {
+                return Err(ScanError::UncoveredSubgroupSizes {
+                    sizes,
+                    adapter: adapter_sizes,
+                });
+            }
+            Some(_) => {}
+            None => config.subgroup_sizes = Some(adapter_sizes),
+        }
+    }
+    Ok((device, queue, config))
+}
+
+/// Check that `device` supports the features and buffer sizes a scan over `byte_len` bytes needs,
+/// and that dispatching `workgroups` workgroups fits into its limits.
+pub fn check_device(
+    device: &wgpu::Device,
+    required_features: wgpu::Features,
+    byte_len: u64,
+    workgroups: u32,
+) -> Result<(), ScanError> {
+    let mut missing = required_features;
+    missing.remove(device.features());
+    if !missing.is_empty() {
+        return Err(ScanError::MissingFeatures(missing));
+    }
+
+    let limits = device.limits();
+    if byte_len > limits.max_storage_buffer_binding_size as u64 {
+        return Err(ScanError::SizeTooLarge {
+            limit_name: "max_storage_buffer_binding_size",
+            size: byte_len,
+            limit: limits.max_storage_buffer_binding_size as u64,
+        });
+    }
+    if byte_len > limits.max_buffer_size {
+        return Err(ScanError::SizeTooLarge {
+            limit_name: "max_buffer_size",
+            size: byte_len,
+            limit: limits.max_buffer_size,
+        });
+    }
+    // The dispatches are split over two dimensions at least
+    let max_dim = limits.max_compute_workgroups_per_dimension as u64;
+    if workgroups as u64 > max_dim * max_dim {
+        return Err(ScanError::SizeTooLarge {
+            limit_name: "max_compute_workgroups_per_dimension squared",
+            size: workgroups as u64,
+            limit: max_dim * max_dim,
+        });
+    }
+    Ok(())
+}
+
+/// Check that a scan over `len` elements fits into a context created with `capacity`.
+pub fn check_len(capacity: usize, len: usize) -> Result<(), ScanError> {
+    if len > capacity {
+        return Err(ScanError::LenExceedsCapacity { len, capacity });
+    }
+    Ok(())
+}
+
+/// Check that the `input` range of a buffer scan can be copied into the context and the result
+/// copied to `output`, and return the range the result is written to.
+pub fn check_buffer_ranges<'a, T>(
+    input: BufferRange<'a>,
+    output: Option<BufferRange<'a>>,
+) -> Result<BufferRange<'a>, ScanError> {
+    let output = output.unwrap_or(input);
+    if output.len != input.len {
+        return Err(ScanError::InvalidBufferRange(
+            "the output length differs from the input length",
+        ));
+    }
+    for (range, usage) in [
+        (input, wgpu::BufferUsages::COPY_SRC),
+        (output, wgpu::BufferUsages::COPY_DST),
+    ] {
+        if !range.offset.is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT) {
+            return Err(ScanError::InvalidBufferRange(
+                "the offset is not a multiple of 4",
+            ));
+        }
+        if range.offset + (range.len * size_of::<T>()) as u64 > range.buffer.size() {
+            return Err(ScanError::InvalidBufferRange(
+                "the range exceeds the buffer size",
+            ));
+        }
+        if !range.buffer.usage().contains(usage) {
+            return Err(ScanError::InvalidBufferRange(
+                "the input needs COPY_SRC and the output COPY_DST usage",
+            ));
+        }
+    }
+    Ok(output)
+}
+
+/// Range of a buffer bound as storage, which identifies the bind groups created for it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StorageBinding {
+    buffer: wgpu::Buffer,
+    offset: u64,
+    size: Option<NonZeroU64>,
+}
+
+impl StorageBinding {
+    /// The whole of `buffer`.
+    pub fn entire(buffer: &wgpu::Buffer) -> Self {
+        Self {
+            buffer: buffer.clone(),
+            offset: 0,
+            size: None,
+        }
+    }
+
+    /// The elements of `range`.
+    pub fn of_range<T>(range: BufferRange<'_>) -> Self {
+        Self {
+            buffer: range.buffer.clone(),
+            offset: range.offset,
+            size: NonZeroU64::new((range.len * size_of::<T>()) as u64),
+        }
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn resource(&self) -> wgpu::BindingResource<'_> {
+        wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: &self.buffer,
+            offset: self.offset,
+            size: self.size,
+        })
+    }
+}
+
+/// Whether `range` can be bound as storage instead of being copied through the context's
+/// buffers. Storage bindings need the `STORAGE` usage and an offset aligned to
+/// `min_storage_buffer_offset_alignment`, and the block scans store whole chunks of
+/// `chunk_len` elements, so the range must not end within one.
+pub fn can_bind_range(device: &wgpu::Device, range: BufferRange<'_>, chunk_len: usize) -> bool {
+    let align = device.limits().min_storage_buffer_offset_alignment as u64;
+    range.buffer.usage().contains(wgpu::BufferUsages::STORAGE)
+        && range.offset.is_multiple_of(align)
+        && range.len.is_multiple_of(chunk_len)
+}
+
+/// Bindings of a buffer scan which scans `data` in place, after reading it from `input` in
+/// the first pass if it is set.
+pub struct RangeBindings {
+    pub data: StorageBinding,
+    pub input: Option<StorageBinding>,
+}
+
+/// Bind the ranges of a buffer scan by a scan which reads the input in its first pass, after
+/// [`check_buffer_ranges`] returned `output`.
+///
+/// The input is read directly if both ranges can be bound and they are in different buffers,
+/// a storage binding cannot be read and written by the same pass. Otherwise it is copied into
+/// the output range and scanned there. Returns `None` if the output range cannot be bound, or
+/// the input cannot be copied to it, so both have to be copied through the context's buffers.
+pub fn bind_ranges<T>(
+    device: &wgpu::Device,
+    encoder: &mut wgpu::CommandEncoder,
+    input: BufferRange<'_>,
+    output: BufferRange<'_>,
+    chunk_len: usize,
+) -> Option<RangeBindings> {
+    if !can_bind_range(device, output, chunk_len) {
+        return None;
+    }
+    let data = StorageBinding::of_range::<T>(output);
+    if input.buffer == output.buffer && input.offset == output.offset {
+        return Some(RangeBindings { data, input: None });
+    }
+    if input.buffer == output.buffer {
+        // Copies within one buffer are not allowed either
+        return None;
+    }
+    if can_bind_range(device, input, chunk_len) {
+        let input = Some(StorageBinding::of_range::<T>(input));
+        return Some(RangeBindings { data, input });
+    }
+    let byte_len = (input.len * size_of::<T>()) as u64;
+    encoder.copy_buffer_to_buffer(
+        input.buffer,
+        input.offset,
+        output.buffer,
+        output.offset,
+        byte_len,
+    );
+    Some(RangeBindings { data, input: None })
+}
+
+/// Most entries a [`BindGroupCache`] keeps. The bind groups keep the caller's buffers alive, so
+/// the cache starts over once it is full instead of growing with every range scanned.
+const MAX_CACHED_BIND_GROUPS: usize = 64;
+
+/// Bind groups created for the data and input bindings of a scan, kept for the following
+/// scans of the same ranges.
+pub struct BindGroupCache<V> {
+    entries: Mutex<HashMap<(StorageBinding, Option<StorageBinding>), V>>,
+}
+
+impl<V: Clone> BindGroupCache<V> {
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The bind groups of `data` and `input`, created by `create` unless they are cached.
+    pub fn get_or_create(
+        &self,
+        data: &StorageBinding,
+        input: Option<&StorageBinding>,
+        create: impl FnOnce() -> V,
+    ) -> V {
+        let key = (data.clone(), input.cloned());
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(value) = entries.get(&key) {
+            return value.clone();
+        }
+        let value = create();
+        if entries.len() >= MAX_CACHED_BIND_GROUPS {
+            entries.clear();
+        }
+        entries.insert(key, value.clone());
+        value
+    }
+}
+
+/// Number of elements on each level of a blocked scan over `len` elements: the data itself, then
+/// one block sum per `block_size` elements of the level below, until a level fits into one block.
+pub fn level_lens(len: usize, block_size: usize) -> Vec<usize> {
+    let mut lens = vec![len];
+    let mut level_len = len;
+    while level_len > block_size {
+        level_len = level_len.div_ceil(block_size);
+        lens.push(level_len);
+    }
+    lens
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LevelUniform {
+    len: u32,
+    _pad: [u32; 3],
+}
+
+/// Live length of each level of a blocked scan, which the shaders read instead of the
+/// `arrayLength` of the buffers sized for the capacity.
+///
+/// Every level has its own uniform at an aligned offset of one buffer, so the bind groups of a
+/// level bind it statically and only the contents change with the length.
+pub struct LevelUniforms {
+    uniforms: UniformBuffer,
+    stride: u64,
+}
+
+impl LevelUniforms {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, levels: usize) -> Self {
+        let align = device.limits().min_uniform_buffer_offset_alignment as usize;
+        let stride = align_up(size_of::<LevelUniform>(), align) as u64;
+        let uniforms = UniformBuffer::new(device, queue, "level uniforms", stride * levels as u64);
+        Self { uniforms, stride }
+    }
+
+    pub fn binding(&self, level: usize) -> wgpu::BindingResource<'_> {
+        wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: self.uniforms.buffer(),
+            offset: level as u64 * self.stride,
+            size: NonZeroU64::new(size_of::<LevelUniform>() as u64),
+        })
+    }
+
+    /// Record a write of the lengths of the levels returned by [`level_lens`] into `encoder`,
+    /// ahead of the passes which read them.
+    pub fn encode_write(&self, encoder: &mut wgpu::CommandEncoder, level_lens: &[usize]) {
+        let mut blob = vec![0u8; self.stride as usize * level_lens.len()];
+        for (level, &len) in level_lens.iter().enumerate() {
+            let u = LevelUniform {
+                len: len as u32,
+                _pad: [0; 3],
+            };
+            let offset = level * self.stride as usize;
+            blob[offset..offset + size_of::<LevelUniform>()]
+                .copy_from_slice(bytemuck::bytes_of(&u));
+        }
+        self.uniforms.encode_write(encoder, &blob);
+    }
+}
+
+/// Uniform buffer whose contents every recorded scan writes from the command encoder.
+///
+/// `Queue::write_buffer` lands before everything of the next submit, so every scan recorded into
+/// one encoder would see the last contents written. Instead the contents are written to a slot of
+/// a staging buffer kept with the uniforms, and a copy to the uniforms is recorded in the encoder,
+/// which orders it with the passes around it. The slots are used in turn, so a slot is only
+/// overwritten after [`MAX_RECORDED_SCANS`] more scans have been recorded.
+pub struct UniformBuffer {
+    buffer: wgpu::Buffer,
+    staging: wgpu::Buffer,
+    queue: wgpu::Queue,
+    next_slot: AtomicUsize,
+}
+
+impl UniformBuffer {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, label: &str, size: u64) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let staging = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("uniform staging"),
+            size: size * MAX_RECORDED_SCANS as u64,
+            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            buffer,
+            staging,
+            queue: queue.clone(),
+            next_slot: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// Record a write of `bytes` to the start of the uniforms into `encoder`.
+    pub fn encode_write(&self, encoder: &mut wgpu::CommandEncoder, bytes: &[u8]) {
+        let slot = self.next_slot.fetch_add(1, Ordering::Relaxed) % MAX_RECORDED_SCANS;
+        let offset = slot as u64 * self.buffer.size();
+        self.queue.write_buffer(&self.staging, offset, bytes);
+        encoder.copy_buffer_to_buffer(&self.staging, offset, &self.buffer, 0, bytes.len() as u64);
+    }
+}
+
+/// Check the number of elements each invocation of a block scan handles.
+pub fn check_elems_per_thread(elems_per_thread: u32) -> Result<(), ScanError> {
+    if !(1..=MAX_ELEMS_PER_THREAD).contains(&elems_per_thread) {
+        return Err(ScanError::InvalidElemsPerThread(elems_per_thread));
+    }
+    Ok(())
+}
+
+/// Check that the subgroup sizes are powers of two in the range wgpu allows, the smallest first.
+pub fn check_subgroup_sizes(sizes: SubgroupSizes) -> Result<(), ScanError> {
+    let SubgroupSizes { min, max } = sizes;
+    let in_range = |size: u32| {
+        size.is_power_of_two()
+            && (wgpu::MINIMUM_SUBGROUP_MIN_SIZE..=wgpu::MAXIMUM_SUBGROUP_MAX_SIZE).contains(&size)
+    };
+    if !in_range(min) || !in_range(max) || min > max {
+        return Err(ScanError::InvalidSubgroupSizes { min, max });
+    }
+    Ok(())
+}
+
+/// Check that `device` can run workgroups of `wg_size` invocations, which share
+/// `workgroup_storage` bytes of workgroup memory.
+pub fn check_workgroup_size(
+    device: &wgpu::Device,
+    wg_size: u32,
+    workgroup_storage: u64,
+) -> Result<(), ScanError> {
+    if wg_size < 2 || !wg_size.is_power_of_two() {
+        return Err(ScanError::InvalidWorkgroupSize(wg_size));
+    }
+    let limits = device.limits();
+    for (limit_name, size, limit) in [
+        (
+            "max_compute_invocations_per_workgroup",
+            wg_size as u64,
+            limits.max_compute_invocations_per_workgroup,
+        ),
+        (
+            "max_compute_workgroup_size_x",
+            wg_size as u64,
+            limits.max_compute_workgroup_size_x,
+        ),
+        (
+            "max_compute_workgroup_storage_size",
+            workgroup_storage,
+            limits.max_compute_workgroup_storage_size,
+        ),
+    ] {
+        if size > limit as u64 {
+            return Err(ScanError::SizeTooLarge {
+                limit_name,
+                size,
+                limit: limit as u64,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Number of elements the block scans load and store at a time. Scalar elements are vectorized
+/// when every invocation handles a multiple of four, user-defined monoids cannot be.
+pub fn chunk_len(elems_per_thread: u32, vectorize: bool) -> usize {
+    if vectorize && elems_per_thread.is_multiple_of(4) {
+        4
+    } else {
+        1
+    }
+}
+
+/// WGSL declarations of the `Chunk` of [`chunk_len`] elements, appended to the prelude of the
+/// block scans.
+pub fn wgsl_chunk_prelude(chunk_len: usize) -> &'static str {
+    if chunk_len == 4 {
+        include_str!("chunk_vec4.wgsl")
+    } else {
+        include_str!("chunk_scalar.wgsl")
+    }
+}
+
+/// Prepend the prelude declaring the element type and the scan operator to a shader.
+pub fn shader_source(prelude: &str, source: &str) -> wgpu::ShaderSource<'static> {
+    wgpu::ShaderSource::Wgsl(format!("{prelude}\n{source}").into())
+}
+
+pub fn split_dispatch_3d(workgroups_needed: u32, max_dim: u32) -> [u32; 3] {
+    let x = workgroups_needed.min(max_dim);
+    let remaining_after_x = workgroups_needed.div_ceil(x);
+    let y = remaining_after_x.min(max_dim);
+
+    // `check_device` makes sure that x and y are enough
+    let xy = (x as u64) * (y as u64);
+    let z = (workgroups_needed as u64).div_ceil(xy);
+
+    [x, y, z as u32]
+}
+
+/// Copy the first `len` elements of `src` into the mappable `readback` buffer and read them back
+/// to the CPU, blocking until they arrive.
+pub fn read_buffer<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    src: &wgpu::Buffer,
+    readback: &ReadbackBuffer,
+    len: usize,
+) -> anyhow::Result<Vec<T>> {
+    Readback::new(device, queue, src, readback, len).wait()
+}
+
+pub fn wait_for_previous_submit(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+    let mut work_done = WorkDone::new(device, queue);
+    device.poll(wgpu::PollType::wait_indefinitely())?;
+    work_done
+        .try_poll()
+        .unwrap_or_else(|| Err(ScanError::DeviceLost.into()))
 }
@@ -0,0 +1,21 @@
+mod common;
+
+use common::{Gpu, check_context};
+use wgpu_prefix_sum_demo::block_blelloch_scan::BlockBlellochGpuContext;
+use wgpu_prefix_sum_demo::prefix_scan::ScanConfig;
+
+/// The padded layout with the workgroup sizes around the 32 banks, and combined with the other
+/// options of the blocked Blelloch scan.
+#[test]
+fn bank_conflict_free_block_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    let config = ScanConfig::default().with_bank_conflict_free(true);
+    for wg_size in [16, 32, 256] {
+        let config = config.clone().with_workgroup_size(wg_size);
+        check_context::<BlockBlellochGpuContext>(&gpu, config, wg_size as usize);
+    }
+    let config = config.with_elems_per_thread(4).with_fused_carry(true);
+    check_context::<BlockBlellochGpuContext>(&gpu, config, 4 * 64);
+}
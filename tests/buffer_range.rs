@@ -0,0 +1,119 @@
+mod common;
+
+use common::{CAPACITY, Gpu, expected, input};
+use wgpu::util::DeviceExt;
+use wgpu_prefix_sum_demo::block_blelloch_scan::BlockBlellochGpuContext;
+use wgpu_prefix_sum_demo::decoupled_lookback_scan::DecoupledLookbackGpuContext;
+use wgpu_prefix_sum_demo::global_blelloch_scan::GlobalBlellochGpuContext;
+use wgpu_prefix_sum_demo::hillis_steele_scan::HillisSteeleGpuContext;
+use wgpu_prefix_sum_demo::prefix_scan::{BufferRange, PrefixScan, ScanConfig, ScanKind};
+use wgpu_prefix_sum_demo::reduce_then_scan::ReduceThenScanGpuContext;
+use wgpu_prefix_sum_demo::subgroup_scan::SubgroupScanGpuContext;
+
+/// Record scans of several ranges of one buffer into a single encoder, at offsets the ranges
+/// can be bound at and at one which is only 4-byte aligned, and check that every range is
+/// scanned on its own and the elements between them are left alone.
+fn check_ranges<C: PrefixScan<u32>>(gpu: &Gpu) {
+    let alignment = gpu.device.limits().min_storage_buffer_offset_alignment as usize / 4;
+    // (offset, length) in elements, with gaps between the ranges and the third one misaligned
+    let mut ranges = Vec::new();
+    let mut end = 0usize;
+    for (misalignment, len) in [(0, 100), (0, 5000), (1, 3000), (0, CAPACITY)] {
+        let offset = end.next_multiple_of(alignment) + misalignment;
+        ranges.push((offset, len));
+        end = offset + len + 10;
+    }
+    let data = input(end);
+
+    for kind in [ScanKind::Inclusive, ScanKind::Exclusive] {
+        let config = ScanConfig::default().with_kind(kind);
+        let context = C::from_device(&gpu.device, &gpu.queue, CAPACITY, &config).unwrap();
+        let buffer = gpu
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("test buffer"),
+                contents: bytemuck::cast_slice(&data),
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
+            });
+
+        let mut encoder = gpu.device.create_command_encoder(&Default::default());
+        for &(offset, len) in &ranges {
+            context
+                .encode_scan_buffer(
+                    &mut encoder,
+                    BufferRange::new(&buffer, (offset * 4) as u64, len),
+                    None,
+                )
+                .unwrap();
+        }
+        // The recorded scans keep their lengths
+        context.set_len(1).unwrap();
+        gpu.queue.submit([encoder.finish()]);
+
+        let mut expected_data = data.clone();
+        for &(offset, len) in &ranges {
+            let range = offset..offset + len;
+            expected_data[range.clone()].copy_from_slice(&expected(
+                &data[range],
+                None,
+                kind,
+                config.op,
+            ));
+        }
+        let result = gpu.read::<u32>(&buffer, 0, data.len());
+        assert!(
+            result == expected_data,
+            "Scans of several ranges of a buffer differ"
+        );
+    }
+}
+
+#[test]
+fn hillis_steele_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_ranges::<HillisSteeleGpuContext>(&gpu);
+}
+
+#[test]
+fn global_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_ranges::<GlobalBlellochGpuContext>(&gpu);
+}
+
+#[test]
+fn block_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_ranges::<BlockBlellochGpuContext>(&gpu);
+}
+
+#[test]
+fn subgroup_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::SUBGROUP) else {
+        return;
+    };
+    check_ranges::<SubgroupScanGpuContext>(&gpu);
+}
+
+#[test]
+fn decoupled_lookback_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_ranges::<DecoupledLookbackGpuContext>(&gpu);
+}
+
+#[test]
+fn reduce_then_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_ranges::<ReduceThenScanGpuContext>(&gpu);
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use common::{CAPACITY, Gpu, check_scan, expected, input};
+use wgpu_prefix_sum_demo::block_blelloch_scan::BlockBlellochGpuContext;
+use wgpu_prefix_sum_demo::cpu_prefix_scan::CpuPrefixScan;
+use wgpu_prefix_sum_demo::decoupled_lookback_scan::DecoupledLookbackGpuContext;
+use wgpu_prefix_sum_demo::global_blelloch_scan::GlobalBlellochGpuContext;
+use wgpu_prefix_sum_demo::hillis_steele_scan::HillisSteeleGpuContext;
+use wgpu_prefix_sum_demo::prefix_scan::{PrefixScan, ScanConfig, ScanKind};
+use wgpu_prefix_sum_demo::reduce_then_scan::ReduceThenScanGpuContext;
+use wgpu_prefix_sum_demo::segmented_scan::{
+    SegmentedBlockBlellochGpuContext, SegmentedSubgroupScanGpuContext,
+};
+use wgpu_prefix_sum_demo::subgroup_scan::SubgroupScanGpuContext;
+
+/// Growing and shrinking lengths, so a stale length, level count or uniform of the previous
+/// scan would show.
+const LENGTHS: [usize; 8] = [CAPACITY, 1, 5000, 4999, 0, 70, CAPACITY - 1, 3];
+
+/// Scan a series of lengths with one context, and rescan the data with `set_len` alone.
+fn check_capacity<C: PrefixScan<u32>>(gpu: &Gpu) {
+    for kind in [ScanKind::Inclusive, ScanKind::Exclusive] {
+        let config = ScanConfig::default().with_kind(kind);
+        let context = C::from_device(&gpu.device, &gpu.queue, CAPACITY, &config).unwrap();
+        assert_eq!(context.capacity(), CAPACITY);
+        let data = input(CAPACITY);
+        for len in LENGTHS {
+            let data = &data[..len];
+            check_scan(&context, data, &expected(data, None, kind, config.op));
+            assert_eq!(context.len(), len);
+        }
+
+        // Shorten the scan of the uploaded data without uploading it again
+        context.upload_data(&data).unwrap();
+        context.set_len(100).unwrap();
+        context.run_prefix_scan();
+        let result = context.read_computed_data().unwrap();
+        assert_eq!(result, expected(&data[..100], None, kind, config.op));
+    }
+}
+
+#[test]
+fn cpu_prefix_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_capacity::<CpuPrefixScan>(&gpu);
+}
+
+#[test]
+fn hillis_steele_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_capacity::<HillisSteeleGpuContext>(&gpu);
+}
+
+#[test]
+fn global_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_capacity::<GlobalBlellochGpuContext>(&gpu);
+}
+
+#[test]
+fn block_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_capacity::<BlockBlellochGpuContext>(&gpu);
+}
+
+#[test]
+fn subgroup_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::SUBGROUP) else {
+        return;
+    };
+    check_capacity::<SubgroupScanGpuContext>(&gpu);
+}
+
+#[test]
+fn decoupled_lookback_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_capacity::<DecoupledLookbackGpuContext>(&gpu);
+}
+
+#[test]
+fn reduce_then_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_capacity::<ReduceThenScanGpuContext>(&gpu);
+}
+
+/// The contexts can be shared between threads, e.g. behind an `Arc`.
+#[test]
+fn contexts_are_send_and_sync() {
+    fn assert_send_sync<C: Send + Sync>() {}
+    assert_send_sync::<CpuPrefixScan>();
+    assert_send_sync::<HillisSteeleGpuContext>();
+    assert_send_sync::<GlobalBlellochGpuContext>();
+    assert_send_sync::<BlockBlellochGpuContext>();
+    assert_send_sync::<SubgroupScanGpuContext>();
+    assert_send_sync::<SegmentedBlockBlellochGpuContext>();
+    assert_send_sync::<SegmentedSubgroupScanGpuContext>();
+    assert_send_sync::<DecoupledLookbackGpuContext>();
+    assert_send_sync::<ReduceThenScanGpuContext>();
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use common::{Gpu, input};
+use wgpu_prefix_sum_demo::block_blelloch_scan::BlockBlellochGpuContext;
+use wgpu_prefix_sum_demo::chunked_scan::ChunkedScan;
+use wgpu_prefix_sum_demo::cpu_prefix_scan::CpuPrefixScan;
+use wgpu_prefix_sum_demo::global_blelloch_scan::GlobalBlellochGpuContext;
+use wgpu_prefix_sum_demo::hillis_steele_scan::HillisSteeleGpuContext;
+use wgpu_prefix_sum_demo::prefix_scan::{PrefixScan, ScanBuffers, ScanConfig, ScanKind};
+use wgpu_prefix_sum_demo::scan_op::ScanOp;
+
+// Small chunks, so a few thousand elements already cross many chunk boundaries
+const CHUNK_LEN: usize = 100;
+const LENGTHS: [usize; 8] = [0, 1, 99, 100, 101, 250, 1000, 12_345];
+
+/// Scan of `data` by the CPU context with `config`.
+fn reference(data: &[i32], config: &ScanConfig) -> Vec<i32> {
+    let cpu = pollster::block_on(CpuPrefixScan::with_config(data.len(), config.clone())).unwrap();
+    cpu.upload_data(data).unwrap();
+    cpu.run_prefix_scan();
+    cpu.read_computed_data().unwrap()
+}
+
+/// Scan inputs across the chunk boundaries with every scan method of a chunked scan on the `S`
+/// context and compare them to the CPU scan.
+fn check_chunked<S: PrefixScan<i32> + ScanBuffers>(gpu: &Gpu) {
+    let data: Vec<i32> = input(LENGTHS[LENGTHS.len() - 1])
+        .iter()
+        .map(|&v| v as i32 - 7)
+        .collect();
+    for (kind, op) in [
+        (ScanKind::Inclusive, ScanOp::Add),
+        (ScanKind::Exclusive, ScanOp::Add),
+        (ScanKind::Exclusive, ScanOp::Max),
+    ] {
+        let config = ScanConfig::default().with_kind(kind).with_op(op);
+        let chunked =
+            ChunkedScan::<i32, S>::from_device(&gpu.device, &gpu.queue, CHUNK_LEN, &config)
+                .unwrap();
+        assert_eq!(chunked.chunk_len(), CHUNK_LEN);
+        for len in LENGTHS {
+            let data = &data[..len];
+            let expected = reference(data, &config);
+
+            assert!(
+                chunked.scan_slice(data).unwrap() == expected,
+                "Chunked scan of {len} elements differs"
+            );
+
+            let mut in_place = data.to_vec();
+            chunked.scan_in_place(&mut in_place).unwrap();
+            assert!(
+                in_place == expected,
+                "Chunked scan of {len} elements in place differs"
+            );
+
+            let mut chunks = Vec::new();
+            chunked
+                .scan_iter(data.iter().copied(), |chunk| {
+                    assert!(chunk.len() <= CHUNK_LEN);
+                    chunks.extend_from_slice(chunk);
+                    Ok(())
+                })
+                .unwrap();
+            assert!(
+                chunks == expected,
+                "Chunked scan of {len} elements from an iterator differs"
+            );
+        }
+    }
+}
+
+#[test]
+fn chunked_block_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_chunked::<BlockBlellochGpuContext<i32>>(&gpu);
+}
+
+#[test]
+fn chunked_hillis_steele_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_chunked::<HillisSteeleGpuContext<i32>>(&gpu);
+}
+
+#[test]
+fn chunked_global_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_chunked::<GlobalBlellochGpuContext<i32>>(&gpu);
+}
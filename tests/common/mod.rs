@@ -0,0 +1,304 @@
+// Shared by the test binaries, which each use a part of it
+#![allow(dead_code)]
+
+use wgpu::util::DeviceExt;
+use wgpu_prefix_sum_demo::cpu_prefix_scan::{cpu_prefix_scan, cpu_segmented_prefix_scan};
+use wgpu_prefix_sum_demo::gpu_options::GpuOptions;
+use wgpu_prefix_sum_demo::prefix_scan::{BufferRange, PrefixScan, ScanConfig, ScanKind};
+use wgpu_prefix_sum_demo::scan_element::ScanElement;
+use wgpu_prefix_sum_demo::scan_op::ScanOp;
+
+/// Device on the software adapter, or the adapter the `WGPU_*` variables select.
+pub struct Gpu {
+    pub adapter: wgpu::Adapter,
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+}
+
+/// Adapter selection of the tests, also for the contexts creating their own device.
+pub fn gpu_options() -> GpuOptions {
+    GpuOptions::default()
+        .with_force_fallback_adapter(true)
+        .with_env_overrides()
+}
+
+impl Gpu {
+    /// Create a device with the `features` the adapter has and the limits of the adapter, or
+    /// `None` to skip the test if there is no adapter.
+    pub fn new(features: wgpu::Features) -> Option<Self> {
+        pollster::block_on(async {
+            let adapter = match gpu_options().request_adapter().await {
+                Ok(adapter) => adapter,
+                Err(error) => {
+                    eprintln!("Skipping the test: {error}");
+                    return None;
+                }
+            };
+            let (device, queue) = adapter
+                .request_device(&wgpu::DeviceDescriptor {
+                    label: Some("test device"),
+                    required_features: features & adapter.features(),
+                    required_limits: adapter.limits(),
+                    ..Default::default()
+                })
+                .await
+                .expect("Failed to create device");
+            Some(Self {
+                adapter,
+                device,
+                queue,
+            })
+        })
+    }
+
+    pub fn has_subgroups(&self) -> bool {
+        self.device.features().contains(wgpu::Features::SUBGROUP)
+    }
+
+    /// Read the first `len` elements of `buffer` back to the CPU.
+    pub fn read<T: ScanElement>(&self, buffer: &wgpu::Buffer, offset: u64, len: usize) -> Vec<T> {
+        let size = (len * size_of::<T>()) as u64;
+        if size == 0 {
+            return Vec::new();
+        }
+        let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("test readback buffer"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        encoder.copy_buffer_to_buffer(buffer, offset, &readback, 0, size);
+        self.queue.submit([encoder.finish()]);
+        readback.slice(..).map_async(wgpu::MapMode::Read, |_| {});
+        self.device
+            .poll(wgpu::PollType::wait_indefinitely())
+            .expect("Failed to poll the device");
+        bytemuck::cast_slice(&readback.slice(..).get_mapped_range()).to_vec()
+    }
+}
+
+/// Capacity of the contexts the tests create.
+pub const CAPACITY: usize = 40_000;
+
+/// Empty and single-element scans, the lengths around one block of `block_len` elements and two
+/// lengths which need more levels, up to the capacity.
+pub fn lengths(block_len: usize) -> Vec<usize> {
+    [
+        0,
+        1,
+        block_len - 1,
+        block_len,
+        block_len + 1,
+        block_len * block_len + 1,
+        CAPACITY,
+    ]
+    .into_iter()
+    .filter(|&len| len <= CAPACITY)
+    .collect()
+}
+
+/// Small pseudo-random values, so sums of many of them neither overflow nor repeat a pattern
+/// which hides a wrong offset.
+pub fn input(len: usize) -> Vec<u32> {
+    (0..len as u32)
+        .map(|i| i.wrapping_mul(7919) % 13 + 1)
+        .collect()
+}
+
+/// Head flags starting segments of varying lengths.
+pub fn flags(len: usize) -> Vec<u32> {
+    (0..len as u32)
+        .map(|i| (i.wrapping_mul(2654435761) % 97 < 3) as u32)
+        .collect()
+}
+
+/// Element types of the tests, with the identities the exclusive references start from.
+pub trait Element: ScanElement {
+    fn identity(op: ScanOp) -> Self;
+}
+
+impl Element for u32 {
+    fn identity(op: ScanOp) -> Self {
+        match op {
+            ScanOp::Add | ScanOp::Or | ScanOp::Xor | ScanOp::Max => 0,
+            ScanOp::Mul => 1,
+            ScanOp::Min | ScanOp::And => u32::MAX,
+        }
+    }
+}
+
+impl Element for i32 {
+    fn identity(op: ScanOp) -> Self {
+        match op {
+            ScanOp::Add | ScanOp::Or | ScanOp::Xor => 0,
+            ScanOp::Mul => 1,
+            ScanOp::Min => i32::MAX,
+            ScanOp::Max => i32::MIN,
+            ScanOp::And => -1,
+        }
+    }
+}
+
+impl Element for f32 {
+    fn identity(op: ScanOp) -> Self {
+        match op {
+            ScanOp::Add => 0.0,
+            ScanOp::Mul => 1.0,
+            ScanOp::Min => f32::INFINITY,
+            ScanOp::Max => f32::NEG_INFINITY,
+            ScanOp::And | ScanOp::Or | ScanOp::Xor => panic!("{op:?} is not defined for f32"),
+        }
+    }
+}
+
+/// Scan of `data` by the CPU references, restarting at the nonzero `flags` if there are any.
+pub fn expected<T: Element>(
+    data: &[T],
+    flags: Option<&[u32]>,
+    kind: ScanKind,
+    op: ScanOp,
+) -> Vec<T> {
+    let inclusive = match flags {
+        Some(flags) => cpu_segmented_prefix_scan(data, flags, op).unwrap(),
+        None => cpu_prefix_scan(data, op).unwrap(),
+    };
+    match kind {
+        ScanKind::Inclusive => inclusive,
+        ScanKind::Exclusive => (0..data.len())
+            .map(|i| {
+                let head = i == 0 || flags.is_some_and(|flags| flags[i] != 0);
+                if head {
+                    <T as Element>::identity(op)
+                } else {
+                    inclusive[i - 1]
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Scan `data` with `context` and compare the result to `expected`.
+pub fn check_scan<T: ScanElement>(context: &impl PrefixScan<T>, data: &[T], expected: &[T]) {
+    context.upload_data(data).unwrap();
+    context.run_prefix_scan();
+    let result = context.read_computed_data().unwrap();
+    assert_eq!(
+        first_mismatch(&result, expected),
+        None,
+        "Scan of {} elements differs",
+        data.len()
+    );
+}
+
+/// Check inclusive and exclusive, in-place and out-of-place scans of the context with `config`
+/// against the CPU reference, uploaded and in caller-owned buffers.
+pub fn check_context<C: PrefixScan<u32>>(gpu: &Gpu, config: ScanConfig, block_len: usize) {
+    let data = input(CAPACITY);
+    for kind in [ScanKind::Inclusive, ScanKind::Exclusive] {
+        for out_of_place in [false, true] {
+            let config = config
+                .clone()
+                .with_kind(kind)
+                .with_out_of_place(out_of_place);
+            let context = C::from_device(&gpu.device, &gpu.queue, CAPACITY, &config).unwrap();
+            for len in lengths(block_len) {
+                let data = &data[..len];
+                let expected = expected(data, None, kind, config.op);
+                check_scan(&context, data, &expected);
+                check_scan_buffer(gpu, &context, data, &expected, out_of_place);
+            }
+        }
+    }
+}
+
+/// Scan `data` at an aligned offset of a caller-owned buffer with `encode_scan_buffer`, in place
+/// or into a second buffer, and compare the result to `expected`.
+pub fn check_scan_buffer<T: ScanElement>(
+    gpu: &Gpu,
+    context: &impl PrefixScan<T>,
+    data: &[T],
+    expected: &[T],
+    out_of_place: bool,
+) {
+    let offset = gpu.device.limits().min_storage_buffer_offset_alignment as u64;
+    let padding = vec![0u8; offset as usize];
+    let contents = [padding.as_slice(), bytemuck::cast_slice(data)].concat();
+    let usage =
+        wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST;
+    let input = gpu
+        .device
+        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("test input buffer"),
+            contents: &contents,
+            usage,
+        });
+    let output = out_of_place.then(|| {
+        gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("test output buffer"),
+            size: contents.len() as u64,
+            usage,
+            mapped_at_creation: false,
+        })
+    });
+
+    let mut encoder = gpu.device.create_command_encoder(&Default::default());
+    context
+        .encode_scan_buffer(
+            &mut encoder,
+            BufferRange::new(&input, offset, data.len()),
+            output
+                .as_ref()
+                .map(|output| BufferRange::new(output, offset, data.len())),
+        )
+        .unwrap();
+    gpu.queue.submit([encoder.finish()]);
+
+    let result = gpu.read::<T>(output.as_ref().unwrap_or(&input), offset, data.len());
+    assert_eq!(
+        first_mismatch(&result, expected),
+        None,
+        "Scan of {} elements of a caller buffer differs",
+        data.len()
+    );
+    if out_of_place {
+        assert_eq!(gpu.read::<T>(&input, offset, data.len()), data);
+    }
+}
+
+/// Compare float `result`s to `expected` up to a relative `tolerance`, as the GPU combines the
+/// elements in a different order than the CPU reference.
+pub fn assert_close(result: &[f32], expected: &[f32], tolerance: f32) {
+    assert_eq!(result.len(), expected.len(), "Lengths differ");
+    for (i, (a, b)) in result.iter().zip(expected).enumerate() {
+        assert!(
+            (a - b).abs() <= tolerance * b.abs().max(1.0),
+            "{a} instead of {b} at {i}"
+        );
+    }
+}
+
+/// The error `result` fails with.
+pub fn error_of<T>(result: anyhow::Result<T>) -> anyhow::Error {
+    match result {
+        Ok(_) => panic!("The call should fail"),
+        Err(error) => error,
+    }
+}
+
+/// Index and values of the first element where `result` differs from `expected`, or the
+/// lengths if they differ, which keeps the assertion messages of long scans readable.
+fn first_mismatch<T: ScanElement>(result: &[T], expected: &[T]) -> Option<String> {
+    if result.len() != expected.len() {
+        return Some(format!(
+            "{} elements instead of {}",
+            result.len(),
+            expected.len()
+        ));
+    }
+    let i = result.iter().zip(expected).position(|(a, b)| a != b)?;
+    Some(format!(
+        "{:?} instead of {:?} at {i}",
+        result[i], expected[i]
+    ))
+}
@@ -0,0 +1,15 @@
+mod common;
+
+use common::{Gpu, check_context};
+use wgpu_prefix_sum_demo::decoupled_lookback_scan::DecoupledLookbackGpuContext;
+use wgpu_prefix_sum_demo::prefix_scan::ScanConfig;
+
+/// Lengths within one tile of 256 elements and up to the 157 tiles of the capacity, each of
+/// which looks back over its predecessors.
+#[test]
+fn decoupled_lookback_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_context::<DecoupledLookbackGpuContext>(&gpu, ScanConfig::default(), 256);
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use common::{CAPACITY, Gpu, assert_close, check_scan, expected, input};
+use wgpu_prefix_sum_demo::block_blelloch_scan::BlockBlellochGpuContext;
+use wgpu_prefix_sum_demo::cpu_prefix_scan::CpuPrefixScan;
+use wgpu_prefix_sum_demo::decoupled_lookback_scan::DecoupledLookbackGpuContext;
+use wgpu_prefix_sum_demo::global_blelloch_scan::GlobalBlellochGpuContext;
+use wgpu_prefix_sum_demo::hillis_steele_scan::HillisSteeleGpuContext;
+use wgpu_prefix_sum_demo::prefix_scan::{PrefixScan, ScanConfig, ScanKind};
+use wgpu_prefix_sum_demo::reduce_then_scan::ReduceThenScanGpuContext;
+use wgpu_prefix_sum_demo::scan_op::ScanOp;
+use wgpu_prefix_sum_demo::subgroup_scan::SubgroupScanGpuContext;
+
+/// Signed elements with negative values, and floats which are not exactly representable, over
+/// several blocks of every context.
+fn check_element_types<I: PrefixScan<i32>, F: PrefixScan<f32>>(gpu: &Gpu) {
+    let signed: Vec<i32> = input(CAPACITY).iter().map(|&v| v as i32 - 7).collect();
+    for kind in [ScanKind::Inclusive, ScanKind::Exclusive] {
+        let config = ScanConfig::default().with_kind(kind);
+        let context = I::from_device(&gpu.device, &gpu.queue, CAPACITY, &config).unwrap();
+        check_scan(&context, &signed, &expected(&signed, None, kind, config.op));
+    }
+
+    let floats: Vec<f32> = signed.iter().map(|&v| v as f32 * 0.1).collect();
+    for kind in [ScanKind::Inclusive, ScanKind::Exclusive] {
+        let config = ScanConfig::default().with_kind(kind);
+        let context = F::from_device(&gpu.device, &gpu.queue, CAPACITY, &config).unwrap();
+        context.upload_data(&floats).unwrap();
+        context.run_prefix_scan();
+        let result = context.read_computed_data().unwrap();
+        // The sums stay small, so the rounding errors of both orders stay far below this
+        assert_close(&result, &expected(&floats, None, kind, config.op), 1e-3);
+    }
+
+    // Minima and maxima of floats are exact, unlike their sums in a different order
+    for op in [ScanOp::Min, ScanOp::Max] {
+        let config = ScanConfig::default().with_op(op);
+        let context = F::from_device(&gpu.device, &gpu.queue, CAPACITY, &config).unwrap();
+        check_scan(
+            &context,
+            &floats,
+            &expected(&floats, None, ScanKind::Inclusive, op),
+        );
+    }
+}
+
+#[test]
+fn cpu_prefix_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_element_types::<CpuPrefixScan<i32>, CpuPrefixScan<f32>>(&gpu);
+}
+
+#[test]
+fn hillis_steele_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_element_types::<HillisSteeleGpuContext<i32>, HillisSteeleGpuContext<f32>>(&gpu);
+}
+
+#[test]
+fn global_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_element_types::<GlobalBlellochGpuContext<i32>, GlobalBlellochGpuContext<f32>>(&gpu);
+}
+
+#[test]
+fn block_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_element_types::<BlockBlellochGpuContext<i32>, BlockBlellochGpuContext<f32>>(&gpu);
+}
+
+#[test]
+fn subgroup_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::SUBGROUP) else {
+        return;
+    };
+    check_element_types::<SubgroupScanGpuContext<i32>, SubgroupScanGpuContext<f32>>(&gpu);
+}
+
+#[test]
+fn decoupled_lookback_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_element_types::<DecoupledLookbackGpuContext<i32>, DecoupledLookbackGpuContext<f32>>(&gpu);
+}
+
+#[test]
+fn reduce_then_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_element_types::<ReduceThenScanGpuContext<i32>, ReduceThenScanGpuContext<f32>>(&gpu);
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use common::{Gpu, check_context};
+use wgpu_prefix_sum_demo::block_blelloch_scan::BlockBlellochGpuContext;
+use wgpu_prefix_sum_demo::prefix_scan::{MAX_ELEMS_PER_THREAD, PrefixScan, ScanConfig};
+use wgpu_prefix_sum_demo::reduce_then_scan::ReduceThenScanGpuContext;
+use wgpu_prefix_sum_demo::subgroup_scan::SubgroupScanGpuContext;
+
+/// Counts which are loaded element by element, as `vec4`s, and the largest one.
+const ELEMS_PER_THREAD: [u32; 3] = [3, 4, MAX_ELEMS_PER_THREAD];
+
+/// Check the context with several elements per invocation, whose blocks are `wg_size` times as
+/// long.
+fn check_elems_per_thread<C: PrefixScan<u32>>(gpu: &Gpu, wg_size: u32) {
+    for elems_per_thread in ELEMS_PER_THREAD {
+        let config = ScanConfig::default().with_elems_per_thread(elems_per_thread);
+        check_context::<C>(gpu, config, (wg_size * elems_per_thread) as usize);
+    }
+}
+
+#[test]
+fn block_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_elems_per_thread::<BlockBlellochGpuContext>(&gpu, 64);
+}
+
+#[test]
+fn subgroup_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::SUBGROUP) else {
+        return;
+    };
+    check_elems_per_thread::<SubgroupScanGpuContext>(&gpu, 128);
+}
+
+#[test]
+fn reduce_then_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_elems_per_thread::<ReduceThenScanGpuContext>(&gpu, 64);
+}
@@ -0,0 +1,30 @@
+mod common;
+
+use common::{Gpu, check_context};
+use wgpu_prefix_sum_demo::block_blelloch_scan::BlockBlellochGpuContext;
+use wgpu_prefix_sum_demo::prefix_scan::{PrefixScan, ScanConfig};
+use wgpu_prefix_sum_demo::subgroup_scan::SubgroupScanGpuContext;
+
+/// Check the context with fused carries, alone and with several elements per invocation.
+fn check_fused_carry<C: PrefixScan<u32>>(gpu: &Gpu, wg_size: u32) {
+    let config = ScanConfig::default().with_fused_carry(true);
+    check_context::<C>(gpu, config.clone(), wg_size as usize);
+    let config = config.with_elems_per_thread(4);
+    check_context::<C>(gpu, config, 4 * wg_size as usize);
+}
+
+#[test]
+fn block_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_fused_carry::<BlockBlellochGpuContext>(&gpu, 64);
+}
+
+#[test]
+fn subgroup_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::SUBGROUP) else {
+        return;
+    };
+    check_fused_carry::<SubgroupScanGpuContext>(&gpu, 128);
+}
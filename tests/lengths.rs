@@ -0,0 +1,71 @@
+mod common;
+
+use common::{CAPACITY, Gpu, check_scan, expected, input};
+use wgpu_prefix_sum_demo::block_blelloch_scan::BlockBlellochGpuContext;
+use wgpu_prefix_sum_demo::cpu_prefix_scan::CpuPrefixScan;
+use wgpu_prefix_sum_demo::global_blelloch_scan::GlobalBlellochGpuContext;
+use wgpu_prefix_sum_demo::hillis_steele_scan::HillisSteeleGpuContext;
+use wgpu_prefix_sum_demo::prefix_scan::{PrefixScan, ScanConfig};
+use wgpu_prefix_sum_demo::subgroup_scan::SubgroupScanGpuContext;
+
+/// Lengths which are neither powers of two nor multiples of the workgroup sizes, next to the
+/// ones which are.
+const LENGTHS: [usize; 14] = [
+    0, 1, 2, 3, 5, 63, 64, 65, 255, 257, 1000, 4097, 12_345, CAPACITY,
+];
+
+/// Scan the prefixes of one input with the default config and compare them to the CPU
+/// reference.
+fn check_lengths<C: PrefixScan>(gpu: &Gpu) {
+    let config = ScanConfig::default();
+    let context = C::from_device(&gpu.device, &gpu.queue, CAPACITY, &config).unwrap();
+    let data = input(CAPACITY);
+    for len in LENGTHS {
+        let data = &data[..len];
+        check_scan(
+            &context,
+            data,
+            &expected(data, None, config.kind, config.op),
+        );
+    }
+}
+
+#[test]
+fn cpu_prefix_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_lengths::<CpuPrefixScan>(&gpu);
+}
+
+#[test]
+fn hillis_steele_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_lengths::<HillisSteeleGpuContext>(&gpu);
+}
+
+#[test]
+fn global_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_lengths::<GlobalBlellochGpuContext>(&gpu);
+}
+
+#[test]
+fn block_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_lengths::<BlockBlellochGpuContext>(&gpu);
+}
+
+#[test]
+fn subgroup_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::SUBGROUP) else {
+        return;
+    };
+    check_lengths::<SubgroupScanGpuContext>(&gpu);
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use common::{CAPACITY, Gpu, input, lengths};
+use wgpu_prefix_sum_demo::block_blelloch_scan::BlockBlellochGpuContext;
+use wgpu_prefix_sum_demo::prefix_scan::{ScanConfig, ScanKind};
+use wgpu_prefix_sum_demo::scan_op::WgslMonoid;
+
+/// The affine map `x -> a * x + b` on wrapping `u32`s. Composing them is associative but not
+/// commutative, so a scan combining the elements in the wrong order gives a different result.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Affine {
+    a: u32,
+    b: u32,
+}
+
+// Two `u32`s without padding
+unsafe impl bytemuck::Zeroable for Affine {}
+unsafe impl bytemuck::Pod for Affine {}
+
+impl Affine {
+    const IDENTITY: Self = Self { a: 1, b: 0 };
+
+    /// The map applying `self` first and `next` to its result.
+    fn then(self, next: Self) -> Self {
+        Self {
+            a: next.a.wrapping_mul(self.a),
+            b: next.a.wrapping_mul(self.b).wrapping_add(next.b),
+        }
+    }
+}
+
+fn affine_monoid() -> WgslMonoid {
+    WgslMonoid {
+        declarations: "struct Affine { a: u32, b: u32 }".to_string(),
+        ty: "Affine".to_string(),
+        identity: "Affine(1u, 0u)".to_string(),
+        combine: "return Affine(b.a * a.a, b.a * a.b + b.b);".to_string(),
+    }
+}
+
+/// Scan of `data` by a CPU fold in input order.
+fn expected(data: &[Affine], kind: ScanKind) -> Vec<Affine> {
+    let mut acc = Affine::IDENTITY;
+    data.iter()
+        .map(|&map| {
+            let previous = acc;
+            acc = acc.then(map);
+            match kind {
+                ScanKind::Inclusive => acc,
+                ScanKind::Exclusive => previous,
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn affine_composition() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    // Odd factors, which never multiply to zero
+    let data: Vec<Affine> = input(2 * CAPACITY)
+        .chunks(2)
+        .map(|pair| Affine {
+            a: 2 * pair[0] + 1,
+            b: pair[1],
+        })
+        .collect();
+    // Several elements per invocation are loaded one at a time for a monoid
+    for (kind, elems_per_thread) in [
+        (ScanKind::Inclusive, 1),
+        (ScanKind::Exclusive, 1),
+        (ScanKind::Exclusive, 3),
+    ] {
+        let config = ScanConfig::default()
+            .with_kind(kind)
+            .with_elems_per_thread(elems_per_thread);
+        let context = BlockBlellochGpuContext::<Affine>::from_device_with_monoid(
+            &gpu.device,
+            &gpu.queue,
+            CAPACITY,
+            &config,
+            &affine_monoid(),
+        )
+        .unwrap();
+        for len in lengths(64 * elems_per_thread as usize) {
+            let data = &data[..len];
+            context.upload_data(data).unwrap();
+            context.run_prefix_scan();
+            let result = context.read_computed_data().unwrap();
+            assert!(
+                result == expected(data, kind),
+                "Scan of {len} affine maps differs"
+            );
+        }
+    }
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use common::{CAPACITY, Gpu, check_scan, expected, input};
+use wgpu_prefix_sum_demo::block_blelloch_scan::BlockBlellochGpuContext;
+use wgpu_prefix_sum_demo::cpu_prefix_scan::CpuPrefixScan;
+use wgpu_prefix_sum_demo::decoupled_lookback_scan::DecoupledLookbackGpuContext;
+use wgpu_prefix_sum_demo::global_blelloch_scan::GlobalBlellochGpuContext;
+use wgpu_prefix_sum_demo::hillis_steele_scan::HillisSteeleGpuContext;
+use wgpu_prefix_sum_demo::prefix_scan::{PrefixScan, ScanConfig, ScanKind};
+use wgpu_prefix_sum_demo::reduce_then_scan::ReduceThenScanGpuContext;
+use wgpu_prefix_sum_demo::scan_op::ScanOp;
+use wgpu_prefix_sum_demo::subgroup_scan::SubgroupScanGpuContext;
+
+/// The operators other than addition, on signed elements which also cover negative values, with
+/// the exclusive scans starting from the identities.
+fn check_operators<C: PrefixScan<i32>>(gpu: &Gpu) {
+    let data: Vec<i32> = input(CAPACITY).iter().map(|&v| v as i32 - 7).collect();
+    for op in [
+        ScanOp::Mul,
+        ScanOp::Min,
+        ScanOp::Max,
+        ScanOp::And,
+        ScanOp::Or,
+        ScanOp::Xor,
+    ] {
+        for kind in [ScanKind::Inclusive, ScanKind::Exclusive] {
+            let config = ScanConfig::default().with_op(op).with_kind(kind);
+            let context = C::from_device(&gpu.device, &gpu.queue, CAPACITY, &config).unwrap();
+            check_scan(&context, &data, &expected(&data, None, kind, op));
+        }
+    }
+}
+
+#[test]
+fn cpu_prefix_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_operators::<CpuPrefixScan<i32>>(&gpu);
+}
+
+#[test]
+fn hillis_steele_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_operators::<HillisSteeleGpuContext<i32>>(&gpu);
+}
+
+#[test]
+fn global_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_operators::<GlobalBlellochGpuContext<i32>>(&gpu);
+}
+
+#[test]
+fn block_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_operators::<BlockBlellochGpuContext<i32>>(&gpu);
+}
+
+#[test]
+fn subgroup_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::SUBGROUP) else {
+        return;
+    };
+    check_operators::<SubgroupScanGpuContext<i32>>(&gpu);
+}
+
+#[test]
+fn decoupled_lookback_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_operators::<DecoupledLookbackGpuContext<i32>>(&gpu);
+}
+
+#[test]
+fn reduce_then_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_operators::<ReduceThenScanGpuContext<i32>>(&gpu);
+}
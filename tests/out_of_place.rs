@@ -0,0 +1,83 @@
+mod common;
+
+use common::{CAPACITY, Gpu, check_scan, check_scan_buffer, expected, input};
+use wgpu_prefix_sum_demo::block_blelloch_scan::BlockBlellochGpuContext;
+use wgpu_prefix_sum_demo::decoupled_lookback_scan::DecoupledLookbackGpuContext;
+use wgpu_prefix_sum_demo::global_blelloch_scan::GlobalBlellochGpuContext;
+use wgpu_prefix_sum_demo::hillis_steele_scan::HillisSteeleGpuContext;
+use wgpu_prefix_sum_demo::prefix_scan::{PrefixScan, ScanConfig, ScanKind};
+use wgpu_prefix_sum_demo::reduce_then_scan::ReduceThenScanGpuContext;
+use wgpu_prefix_sum_demo::subgroup_scan::SubgroupScanGpuContext;
+
+/// The out-of-place scan keeps the uploaded input, so scanning it again without another upload
+/// gives the same result, and scans of caller buffers leave the input range unchanged.
+fn check_out_of_place<C: PrefixScan<u32>>(gpu: &Gpu) {
+    let data = input(CAPACITY);
+    for kind in [ScanKind::Inclusive, ScanKind::Exclusive] {
+        let config = ScanConfig::default()
+            .with_kind(kind)
+            .with_out_of_place(true);
+        let context = C::from_device(&gpu.device, &gpu.queue, CAPACITY, &config).unwrap();
+        for len in [1, 1000, CAPACITY] {
+            let data = &data[..len];
+            let expected = expected(data, None, kind, config.op);
+            check_scan(&context, data, &expected);
+            for _ in 0..2 {
+                context.run_prefix_scan();
+                assert!(
+                    context.read_computed_data().unwrap() == expected,
+                    "Rescan of {len} elements differs"
+                );
+            }
+            check_scan_buffer(gpu, &context, data, &expected, true);
+        }
+    }
+}
+
+#[test]
+fn hillis_steele_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_out_of_place::<HillisSteeleGpuContext>(&gpu);
+}
+
+#[test]
+fn global_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_out_of_place::<GlobalBlellochGpuContext>(&gpu);
+}
+
+#[test]
+fn block_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_out_of_place::<BlockBlellochGpuContext>(&gpu);
+}
+
+#[test]
+fn subgroup_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::SUBGROUP) else {
+        return;
+    };
+    check_out_of_place::<SubgroupScanGpuContext>(&gpu);
+}
+
+#[test]
+fn decoupled_lookback_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_out_of_place::<DecoupledLookbackGpuContext>(&gpu);
+}
+
+#[test]
+fn reduce_then_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_out_of_place::<ReduceThenScanGpuContext>(&gpu);
+}
@@ -0,0 +1,140 @@
+mod common;
+
+use common::{CAPACITY, Gpu, expected, input};
+use wgpu_prefix_sum_demo::block_blelloch_scan::BlockBlellochGpuContext;
+use wgpu_prefix_sum_demo::cpu_prefix_scan::CpuPrefixScan;
+use wgpu_prefix_sum_demo::hillis_steele_scan::HillisSteeleGpuContext;
+use wgpu_prefix_sum_demo::prefix_scan::{PrefixScan, ScanConfig, ScanKind};
+use wgpu_prefix_sum_demo::readback::{Readback, WorkDone};
+use wgpu_prefix_sum_demo::scan_error::ScanError;
+use wgpu_prefix_sum_demo::scan_op::ScanOp;
+
+/// Upload and scan `data` with a new context, without waiting for the scan.
+fn start_scan<C: PrefixScan<u32>>(gpu: &Gpu, data: &[u32]) -> C {
+    let context =
+        C::from_device(&gpu.device, &gpu.queue, CAPACITY, &ScanConfig::default()).unwrap();
+    context.upload_data(data).unwrap();
+    context.run_prefix_scan();
+    context
+}
+
+/// Call `try_poll` until it returns the result, as a frame loop would.
+fn poll_readback(mut readback: Readback<u32>) -> anyhow::Result<Vec<u32>> {
+    loop {
+        if let Some(result) = readback.try_poll() {
+            return result;
+        }
+        std::thread::yield_now();
+    }
+}
+
+fn poll_work_done(mut work_done: WorkDone) -> anyhow::Result<()> {
+    loop {
+        if let Some(result) = work_done.try_poll() {
+            return result;
+        }
+        std::thread::yield_now();
+    }
+}
+
+fn reference(data: &[u32]) -> Vec<u32> {
+    expected(data, None, ScanKind::Inclusive, ScanOp::Add)
+}
+
+#[test]
+fn readback_try_poll() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    let data = input(CAPACITY);
+    let context = start_scan::<BlockBlellochGpuContext>(&gpu, &data);
+    let result = poll_readback(context.read_computed_data_async()).unwrap();
+    assert!(result == reference(&data));
+
+    // The buffer is released for the next readback
+    context.upload_data(&data[..1000]).unwrap();
+    context.run_prefix_scan();
+    let result = poll_readback(context.read_computed_data_async()).unwrap();
+    assert!(result == reference(&data[..1000]));
+}
+
+/// Readbacks awaited on an executor which does not poll the device, each woken by the shared
+/// background thread of the device.
+#[test]
+fn readback_poll_in_background() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    let data = input(CAPACITY);
+    let block = start_scan::<BlockBlellochGpuContext>(&gpu, &data);
+    let hillis = start_scan::<HillisSteeleGpuContext>(&gpu, &data[..5000]);
+    let block_readback = block.read_computed_data_async().poll_in_background();
+    let hillis_readback = hillis.read_computed_data_async().poll_in_background();
+    let (block_result, hillis_result) =
+        pollster::block_on(async { (block_readback.await, hillis_readback.await) });
+    assert!(block_result.unwrap() == reference(&data));
+    assert!(hillis_result.unwrap() == reference(&data[..5000]));
+}
+
+/// A context has one readback buffer, which a dropped readback releases.
+#[test]
+fn readback_pending() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    let data = input(CAPACITY);
+    let context = start_scan::<BlockBlellochGpuContext>(&gpu, &data);
+
+    let first = context.read_computed_data_async();
+    let error = poll_readback(context.read_computed_data_async()).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<ScanError>(),
+        Some(ScanError::ReadbackPending)
+    ));
+    assert!(poll_readback(first).unwrap() == reference(&data));
+
+    // Dropped before and after its mapping completes
+    drop(context.read_computed_data_async());
+    assert!(context.read_computed_data().unwrap() == reference(&data));
+    let dropped = context.read_computed_data_async();
+    gpu.device
+        .poll(wgpu::PollType::wait_indefinitely())
+        .unwrap();
+    drop(dropped);
+    assert!(context.read_computed_data().unwrap() == reference(&data));
+}
+
+#[test]
+fn work_done() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    let data = input(CAPACITY);
+    let context = start_scan::<BlockBlellochGpuContext>(&gpu, &data);
+    poll_work_done(context.wait_for_previous_submit_async()).unwrap();
+    assert!(context.read_computed_data().unwrap() == reference(&data));
+
+    context.run_prefix_scan();
+    pollster::block_on(
+        context
+            .wait_for_previous_submit_async()
+            .poll_in_background(),
+    )
+    .unwrap();
+    // The scan ran twice in place
+    assert!(context.read_computed_data().unwrap() == reference(&reference(&data)));
+}
+
+/// The CPU scan runs when the readback is started, which is resolved right away.
+#[test]
+fn cpu_readback() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    let data = input(CAPACITY);
+    let context = start_scan::<CpuPrefixScan>(&gpu, &data);
+    let mut readback = context.read_computed_data_async();
+    assert!(readback.try_poll().unwrap().unwrap() == reference(&data));
+    let mut work_done = context.wait_for_previous_submit_async();
+    work_done.try_poll().unwrap().unwrap();
+}
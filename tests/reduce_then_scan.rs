@@ -0,0 +1,13 @@
+mod common;
+
+use common::{Gpu, check_context};
+use wgpu_prefix_sum_demo::prefix_scan::ScanConfig;
+use wgpu_prefix_sum_demo::reduce_then_scan::ReduceThenScanGpuContext;
+
+#[test]
+fn reduce_then_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_context::<ReduceThenScanGpuContext>(&gpu, ScanConfig::default(), 64);
+}
@@ -0,0 +1,264 @@
+mod common;
+
+use common::{Gpu, error_of, input};
+use wgpu::util::DeviceExt;
+use wgpu_prefix_sum_demo::block_blelloch_scan::BlockBlellochGpuContext;
+use wgpu_prefix_sum_demo::cpu_prefix_scan::{CpuPrefixScan, cpu_prefix_scan};
+use wgpu_prefix_sum_demo::decoupled_lookback_scan::DecoupledLookbackGpuContext;
+use wgpu_prefix_sum_demo::global_blelloch_scan::GlobalBlellochGpuContext;
+use wgpu_prefix_sum_demo::hillis_steele_scan::HillisSteeleGpuContext;
+use wgpu_prefix_sum_demo::prefix_scan::{BufferRange, PrefixScan, ScanConfig};
+use wgpu_prefix_sum_demo::reduce_then_scan::ReduceThenScanGpuContext;
+use wgpu_prefix_sum_demo::scan_element::ScanElement;
+use wgpu_prefix_sum_demo::scan_error::ScanError;
+use wgpu_prefix_sum_demo::scan_op::ScanOp;
+use wgpu_prefix_sum_demo::subgroup_scan::SubgroupScanGpuContext;
+
+const CAPACITY: usize = 1000;
+
+/// The error creating a `C` with `config` fails with.
+fn creation_error<T: ScanElement, C: PrefixScan<T>>(
+    gpu: &Gpu,
+    config: &ScanConfig,
+) -> anyhow::Error {
+    error_of(C::from_device(&gpu.device, &gpu.queue, CAPACITY, config))
+}
+
+/// Bitwise operators are rejected for floats.
+fn check_unsupported_op<C: PrefixScan<f32>>(gpu: &Gpu) {
+    for op in [ScanOp::And, ScanOp::Or, ScanOp::Xor] {
+        let config = ScanConfig::default().with_op(op);
+        let error = creation_error::<f32, C>(gpu, &config);
+        assert!(
+            matches!(
+                error.downcast_ref::<ScanError>(),
+                Some(ScanError::UnsupportedOp { op: error_op, element_type: "f32" })
+                    if *error_op == op
+            ),
+            "Unexpected error {error}"
+        );
+    }
+}
+
+/// Lengths over the capacity are rejected and leave the length of the context unchanged.
+fn check_len_exceeds_capacity<C: PrefixScan<u32>>(gpu: &Gpu) {
+    let context =
+        C::from_device(&gpu.device, &gpu.queue, CAPACITY, &ScanConfig::default()).unwrap();
+    let is_len_error = |error: anyhow::Error| {
+        matches!(
+            error.downcast_ref::<ScanError>(),
+            Some(ScanError::LenExceedsCapacity {
+                len: 1001,
+                capacity: CAPACITY
+            })
+        )
+    };
+    assert!(is_len_error(error_of(context.set_len(CAPACITY + 1))));
+    assert!(is_len_error(error_of(
+        context.upload_data(&input(CAPACITY + 1))
+    )));
+    // The length is unchanged
+    assert_eq!(context.len(), CAPACITY);
+}
+
+/// Every context rejects the operators its element type does not have, and lengths over its
+/// capacity.
+fn check_context<I: PrefixScan<u32>, F: PrefixScan<f32>>(gpu: &Gpu) {
+    check_unsupported_op::<F>(gpu);
+    check_len_exceeds_capacity::<I>(gpu);
+}
+
+#[test]
+fn cpu_prefix_scan_errors() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_context::<CpuPrefixScan, CpuPrefixScan<f32>>(&gpu);
+    assert!(matches!(
+        cpu_prefix_scan(&[1.0f32], ScanOp::Xor),
+        Err(ScanError::UnsupportedOp {
+            op: ScanOp::Xor,
+            element_type: "f32"
+        })
+    ));
+
+    let buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("test buffer"),
+        size: 16,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    });
+    let context = pollster::block_on(CpuPrefixScan::<u32>::new(16)).unwrap();
+    let mut encoder = gpu.device.create_command_encoder(&Default::default());
+    let error =
+        error_of(context.encode_scan_buffer(&mut encoder, BufferRange::new(&buffer, 0, 4), None));
+    assert!(matches!(
+        error.downcast_ref::<ScanError>(),
+        Some(ScanError::Unsupported(_))
+    ));
+}
+
+#[test]
+fn hillis_steele_scan_errors() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_context::<HillisSteeleGpuContext, HillisSteeleGpuContext<f32>>(&gpu);
+}
+
+#[test]
+fn global_blelloch_scan_errors() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_context::<GlobalBlellochGpuContext, GlobalBlellochGpuContext<f32>>(&gpu);
+}
+
+#[test]
+fn block_blelloch_scan_errors() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_context::<BlockBlellochGpuContext, BlockBlellochGpuContext<f32>>(&gpu);
+}
+
+#[test]
+fn subgroup_scan_errors() {
+    let Some(gpu) = Gpu::new(wgpu::Features::SUBGROUP) else {
+        return;
+    };
+    check_context::<SubgroupScanGpuContext, SubgroupScanGpuContext<f32>>(&gpu);
+}
+
+#[test]
+fn decoupled_lookback_scan_errors() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_context::<DecoupledLookbackGpuContext, DecoupledLookbackGpuContext<f32>>(&gpu);
+}
+
+#[test]
+fn reduce_then_scan_errors() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_context::<ReduceThenScanGpuContext, ReduceThenScanGpuContext<f32>>(&gpu);
+}
+
+/// Ranges which cannot be copied or bound are rejected before anything is recorded.
+#[test]
+fn invalid_buffer_ranges() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    let context = BlockBlellochGpuContext::<u32>::from_device(
+        &gpu.device,
+        &gpu.queue,
+        64,
+        &ScanConfig::default(),
+    )
+    .unwrap();
+    let usage =
+        wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST;
+    let buffer = gpu
+        .device
+        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("test buffer"),
+            contents: bytemuck::cast_slice(&input(64)),
+            usage,
+        });
+    let storage_only = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("test storage buffer"),
+        size: 256,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    });
+
+    for (input, output) in [
+        // Misaligned
+        (BufferRange::new(&buffer, 2, 8), None),
+        // Out of bounds
+        (BufferRange::new(&buffer, 0, 65), None),
+        (BufferRange::new(&buffer, 128, 40), None),
+        // Different lengths
+        (
+            BufferRange::new(&buffer, 0, 8),
+            Some(BufferRange::new(&buffer, 128, 9)),
+        ),
+        // Not copyable
+        (BufferRange::new(&storage_only, 0, 8), None),
+    ] {
+        let mut encoder = gpu.device.create_command_encoder(&Default::default());
+        let error = error_of(context.encode_scan_buffer(&mut encoder, input, output));
+        assert!(
+            matches!(
+                error.downcast_ref::<ScanError>(),
+                Some(ScanError::InvalidBufferRange(_))
+            ),
+            "Unexpected error {error}"
+        );
+    }
+}
+
+/// The elements per invocation are checked against their supported range.
+#[test]
+fn invalid_elems_per_thread() {
+    let Some(gpu) = Gpu::new(wgpu::Features::SUBGROUP) else {
+        return;
+    };
+    for elems_per_thread in [0, 17] {
+        let config = ScanConfig::default().with_elems_per_thread(elems_per_thread);
+        for error in [
+            creation_error::<u32, BlockBlellochGpuContext>(&gpu, &config),
+            creation_error::<u32, SubgroupScanGpuContext>(&gpu, &config),
+            creation_error::<u32, ReduceThenScanGpuContext>(&gpu, &config),
+        ] {
+            assert!(matches!(
+                error.downcast_ref::<ScanError>(),
+                Some(ScanError::InvalidElemsPerThread(value)) if *value == elems_per_thread
+            ));
+        }
+    }
+}
+
+/// Workgroup sizes which are not powers of two, or exceed the limits of the device, are
+/// rejected by every GPU scan.
+#[test]
+fn invalid_workgroup_size() {
+    let Some(gpu) = Gpu::new(wgpu::Features::SUBGROUP) else {
+        return;
+    };
+    let errors = |config: &ScanConfig| {
+        [
+            creation_error::<u32, HillisSteeleGpuContext>(&gpu, config),
+            creation_error::<u32, GlobalBlellochGpuContext>(&gpu, config),
+            creation_error::<u32, BlockBlellochGpuContext>(&gpu, config),
+            creation_error::<u32, SubgroupScanGpuContext>(&gpu, config),
+            creation_error::<u32, ReduceThenScanGpuContext>(&gpu, config),
+            creation_error::<u32, DecoupledLookbackGpuContext>(&gpu, config),
+        ]
+    };
+    for workgroup_size in [0, 1, 48] {
+        let config = ScanConfig::default().with_workgroup_size(workgroup_size);
+        for error in errors(&config) {
+            assert!(matches!(
+                error.downcast_ref::<ScanError>(),
+                Some(ScanError::InvalidWorkgroupSize(value)) if *value == workgroup_size
+            ));
+        }
+    }
+
+    let too_large =
+        (gpu.device.limits().max_compute_invocations_per_workgroup + 1).next_power_of_two();
+    let config = ScanConfig::default().with_workgroup_size(too_large);
+    for error in errors(&config) {
+        assert!(
+            matches!(
+                error.downcast_ref::<ScanError>(),
+                Some(ScanError::SizeTooLarge { .. })
+            ),
+            "Unexpected error {error}"
+        );
+    }
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use common::{CAPACITY, Gpu, check_context, check_scan, expected, input, lengths};
+use wgpu_prefix_sum_demo::block_blelloch_scan::BlockBlellochGpuContext;
+use wgpu_prefix_sum_demo::cpu_prefix_scan::CpuPrefixScan;
+use wgpu_prefix_sum_demo::global_blelloch_scan::GlobalBlellochGpuContext;
+use wgpu_prefix_sum_demo::hillis_steele_scan::HillisSteeleGpuContext;
+use wgpu_prefix_sum_demo::prefix_scan::{PrefixScan, ScanConfig, ScanKind};
+use wgpu_prefix_sum_demo::subgroup_scan::SubgroupScanGpuContext;
+
+#[test]
+fn cpu_prefix_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    let data = input(CAPACITY);
+    for kind in [ScanKind::Inclusive, ScanKind::Exclusive] {
+        let config = ScanConfig::default().with_kind(kind);
+        let context =
+            CpuPrefixScan::from_device(&gpu.device, &gpu.queue, CAPACITY, &config).unwrap();
+        for len in lengths(64) {
+            let data = &data[..len];
+            check_scan(&context, data, &expected(data, None, kind, config.op));
+        }
+    }
+}
+
+#[test]
+fn hillis_steele_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_context::<HillisSteeleGpuContext>(&gpu, ScanConfig::default(), 64);
+}
+
+#[test]
+fn global_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_context::<GlobalBlellochGpuContext>(&gpu, ScanConfig::default(), 64);
+}
+
+#[test]
+fn block_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_context::<BlockBlellochGpuContext>(&gpu, ScanConfig::default(), 64);
+}
+
+#[test]
+fn subgroup_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::SUBGROUP) else {
+        return;
+    };
+    check_context::<SubgroupScanGpuContext>(&gpu, ScanConfig::default(), 128);
+}
@@ -0,0 +1,125 @@
+mod common;
+
+use common::{
+    CAPACITY, Gpu, check_scan, check_scan_buffer, error_of, expected, flags, input, lengths,
+};
+use wgpu_prefix_sum_demo::prefix_scan::{PrefixScan, ScanConfig, ScanKind, SubgroupSizes};
+use wgpu_prefix_sum_demo::scan_error::ScanError;
+use wgpu_prefix_sum_demo::scan_op::ScanOp;
+use wgpu_prefix_sum_demo::segmented_scan::{
+    SegmentedBlockBlellochGpuContext, SegmentedSubgroupScanGpuContext,
+};
+
+/// Check the segmented context with `config` against the CPU reference, which restarts at the
+/// same head flags.
+fn check_segmented<C: PrefixScan<u32>>(
+    gpu: &Gpu,
+    config: ScanConfig,
+    block_len: usize,
+    upload_flags: impl Fn(&C, &[u32]) -> anyhow::Result<()>,
+) {
+    let data = input(CAPACITY);
+    let flags = flags(CAPACITY);
+    for kind in [ScanKind::Inclusive, ScanKind::Exclusive] {
+        for out_of_place in [false, true] {
+            let config = config
+                .clone()
+                .with_kind(kind)
+                .with_out_of_place(out_of_place);
+            let context = C::from_device(&gpu.device, &gpu.queue, CAPACITY, &config).unwrap();
+            upload_flags(&context, &flags).unwrap();
+            for len in lengths(block_len) {
+                let data = &data[..len];
+                let expected = expected(data, Some(&flags), kind, config.op);
+                check_scan(&context, data, &expected);
+                check_scan_buffer(gpu, &context, data, &expected, out_of_place);
+            }
+        }
+    }
+}
+
+/// Flags shorter than the data are rejected, whether they are uploaded before or after it.
+fn check_flags_too_short<C: PrefixScan<u32>>(
+    gpu: &Gpu,
+    upload_flags: impl Fn(&C, &[u32]) -> anyhow::Result<()>,
+) {
+    let context =
+        C::from_device(&gpu.device, &gpu.queue, CAPACITY, &ScanConfig::default()).unwrap();
+    let data = input(100);
+    let flags = flags(100);
+
+    context.upload_data(&data).unwrap();
+    let error = error_of(upload_flags(&context, &flags[..99]));
+    assert!(matches!(
+        error.downcast_ref::<ScanError>(),
+        Some(ScanError::FlagsTooShort {
+            len: 100,
+            flags_len: 99
+        })
+    ));
+
+    context.upload_data(&data[..50]).unwrap();
+    upload_flags(&context, &flags[..50]).unwrap();
+    let error = error_of(context.upload_data(&data));
+    assert!(matches!(
+        error.downcast_ref::<ScanError>(),
+        Some(ScanError::FlagsTooShort {
+            len: 100,
+            flags_len: 50
+        })
+    ));
+    let expected = expected(
+        &data[..50],
+        Some(&flags[..50]),
+        ScanKind::Inclusive,
+        ScanOp::Add,
+    );
+    check_scan(&context, &data[..50], &expected);
+}
+
+/// The options the segmented scans do not implement are rejected instead of ignored.
+fn check_rejected_options<C: PrefixScan<u32>>(gpu: &Gpu) {
+    for config in [
+        ScanConfig::default().with_elems_per_thread(4),
+        ScanConfig::default().with_fused_carry(true),
+        ScanConfig::default().with_subgroup_sizes(SubgroupSizes::exact(32)),
+    ] {
+        let error = error_of(C::from_device(&gpu.device, &gpu.queue, 16, &config));
+        assert!(matches!(
+            error.downcast_ref::<ScanError>(),
+            Some(ScanError::Unsupported(_))
+        ));
+    }
+}
+
+#[test]
+fn segmented_block_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    let upload_flags =
+        |context: &SegmentedBlockBlellochGpuContext, flags: &[u32]| context.upload_flags(flags);
+    check_segmented(&gpu, ScanConfig::default(), 64, upload_flags);
+    let config = ScanConfig::default().with_workgroup_size(32);
+    check_segmented(&gpu, config, 32, upload_flags);
+    check_flags_too_short(&gpu, upload_flags);
+    check_rejected_options::<SegmentedBlockBlellochGpuContext>(&gpu);
+}
+
+#[test]
+fn segmented_subgroup_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::SUBGROUP) else {
+        return;
+    };
+    if !gpu.has_subgroups() {
+        eprintln!("Skipping the test: the adapter has no subgroup operations");
+        return;
+    }
+    let upload_flags =
+        |context: &SegmentedSubgroupScanGpuContext, flags: &[u32]| context.upload_flags(flags);
+    check_segmented(&gpu, ScanConfig::default(), 128, upload_flags);
+    let config = ScanConfig::default().with_workgroup_size(32);
+    check_segmented(&gpu, config, 32, upload_flags);
+    check_flags_too_short(&gpu, upload_flags);
+    check_rejected_options::<SegmentedSubgroupScanGpuContext>(&gpu);
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use common::{Gpu, check_scan, check_scan_buffer, expected, input};
+use wgpu_prefix_sum_demo::prefix_scan::{PrefixScan, ScanConfig, ScanKind};
+use wgpu_prefix_sum_demo::scan_op::ScanOp;
+use wgpu_prefix_sum_demo::subgroup_scan::SubgroupScanGpuContext;
+
+const CAPACITY: usize = 70_000;
+const LENGTHS: [usize; 8] = [0, 1, 31, 32, 33, 1025, 40_000, CAPACITY];
+
+/// Configs covering the options the emulation has to handle: the workgroup sizes around the
+/// emulated subgroup size of 32, several elements per invocation and the fused carries.
+fn configs() -> Vec<ScanConfig> {
+    let mut configs = Vec::new();
+    for (workgroup_size, elems_per_thread, fused_carry) in [
+        (128, 1, false),
+        (16, 1, true),
+        (32, 3, false),
+        (256, 4, true),
+    ] {
+        for kind in [ScanKind::Inclusive, ScanKind::Exclusive] {
+            configs.push(
+                ScanConfig::default()
+                    .with_kind(kind)
+                    .with_workgroup_size(workgroup_size)
+                    .with_elems_per_thread(elems_per_thread)
+                    .with_fused_carry(fused_carry),
+            );
+        }
+    }
+    configs
+}
+
+/// Without the `SUBGROUP` feature the subgroup operations are emulated in workgroup memory.
+#[test]
+fn emulated_subgroups() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    let data = input(CAPACITY);
+    for config in configs() {
+        for out_of_place in [false, true] {
+            let config = config.clone().with_out_of_place(out_of_place);
+            let context =
+                SubgroupScanGpuContext::from_device(&gpu.device, &gpu.queue, CAPACITY, &config)
+                    .unwrap();
+            for len in LENGTHS {
+                let data = &data[..len];
+                let expected = expected(data, None, config.kind, config.op);
+                check_scan(&context, data, &expected);
+                check_scan_buffer(&gpu, &context, data, &expected, out_of_place);
+            }
+        }
+    }
+}
+
+/// The emulated subgroups with the other operators, on signed and floating-point elements.
+#[test]
+fn emulated_subgroup_operators() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    let data: Vec<i32> = input(CAPACITY).iter().map(|&v| v as i32 - 7).collect();
+    for op in [ScanOp::Min, ScanOp::Max, ScanOp::Xor] {
+        let config = ScanConfig::default().with_op(op).with_workgroup_size(64);
+        let context =
+            SubgroupScanGpuContext::<i32>::from_device(&gpu.device, &gpu.queue, CAPACITY, &config)
+                .unwrap();
+        check_scan(
+            &context,
+            &data,
+            &expected(&data, None, ScanKind::Inclusive, op),
+        );
+    }
+    // Minima and maxima of floats are exact, unlike their sums in a different order
+    let data: Vec<f32> = data.iter().map(|&v| v as f32 * 0.25).collect();
+    for op in [ScanOp::Min, ScanOp::Max] {
+        let config = ScanConfig::default().with_op(op);
+        let context =
+            SubgroupScanGpuContext::<f32>::from_device(&gpu.device, &gpu.queue, CAPACITY, &config)
+                .unwrap();
+        check_scan(
+            &context,
+            &data,
+            &expected(&data, None, ScanKind::Inclusive, op),
+        );
+    }
+}
+
+/// The emulation gives the same results as the subgroup operations of the adapter, if it has
+/// them.
+#[test]
+fn emulated_matches_native_subgroups() {
+    let Some(native) = Gpu::new(wgpu::Features::SUBGROUP) else {
+        return;
+    };
+    if !native.has_subgroups() {
+        eprintln!("Skipping the test: the adapter has no subgroup operations");
+        return;
+    }
+    let Some(emulated) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    let data = input(CAPACITY);
+    for config in configs() {
+        let native_context =
+            SubgroupScanGpuContext::from_device(&native.device, &native.queue, CAPACITY, &config)
+                .unwrap();
+        let emulated_context = SubgroupScanGpuContext::from_device(
+            &emulated.device,
+            &emulated.queue,
+            CAPACITY,
+            &config,
+        )
+        .unwrap();
+        for len in LENGTHS {
+            let data = &data[..len];
+            let expected = expected(data, None, config.kind, config.op);
+            check_scan(&native_context, data, &expected);
+            check_scan(&emulated_context, data, &expected);
+        }
+    }
+}
+
+/// The largest workgroups of the device, whose subgroup sums the first subgroup scans in several
+/// rounds if they outnumber its invocations.
+#[test]
+fn largest_workgroups() {
+    let Some(gpu) = Gpu::new(wgpu::Features::SUBGROUP) else {
+        return;
+    };
+    let limits = gpu.device.limits();
+    let wg_size = limits
+        .max_compute_invocations_per_workgroup
+        .min(limits.max_compute_workgroup_size_x);
+    // A power of two
+    let wg_size = 1 << wg_size.ilog2();
+    let data = input(CAPACITY);
+    for kind in [ScanKind::Inclusive, ScanKind::Exclusive] {
+        let config = ScanConfig::default()
+            .with_kind(kind)
+            .with_workgroup_size(wg_size);
+        let context =
+            SubgroupScanGpuContext::from_device(&gpu.device, &gpu.queue, CAPACITY, &config)
+                .unwrap();
+        for len in [1, wg_size as usize + 1, CAPACITY] {
+            let data = &data[..len];
+            check_scan(&context, data, &expected(data, None, kind, config.op));
+        }
+    }
+}
@@ -0,0 +1,147 @@
+mod common;
+
+use common::{Gpu, check_scan, check_scan_buffer, expected, gpu_options, input};
+use wgpu_prefix_sum_demo::prefix_scan::{PrefixScan, ScanConfig, ScanKind, SubgroupSizes};
+use wgpu_prefix_sum_demo::scan_error::ScanError;
+use wgpu_prefix_sum_demo::subgroup_scan::SubgroupScanGpuContext;
+
+const CAPACITY: usize = 40_000;
+
+/// Every subgroup size wgpu allows.
+fn sizes() -> impl Iterator<Item = u32> {
+    (wgpu::MINIMUM_SUBGROUP_MIN_SIZE.ilog2()..=wgpu::MAXIMUM_SUBGROUP_MAX_SIZE.ilog2())
+        .map(|exponent| 1 << exponent)
+}
+
+/// Check the subgroup scan with `config` for the lengths around one and two levels of blocks.
+fn check_context(gpu: &Gpu, config: &ScanConfig) {
+    let context =
+        SubgroupScanGpuContext::from_device(&gpu.device, &gpu.queue, CAPACITY, config).unwrap();
+    let block_len = (config.workgroup_size.unwrap() * config.elems_per_thread) as usize;
+    let data = input(CAPACITY);
+    for len in [
+        0,
+        1,
+        block_len - 1,
+        block_len,
+        block_len + 1,
+        block_len * block_len + 1,
+    ] {
+        let data = &data[..len.min(CAPACITY)];
+        let expected = expected(data, None, config.kind, config.op);
+        check_scan(&context, data, &expected);
+        check_scan_buffer(gpu, &context, data, &expected, config.out_of_place);
+    }
+}
+
+/// Returns the `ScanError` the context creation with `config` fails with.
+fn creation_error(gpu: &Gpu, config: &ScanConfig) -> ScanError {
+    let error = SubgroupScanGpuContext::<u32>::from_device(&gpu.device, &gpu.queue, 16, config)
+        .err()
+        .expect("The context creation should fail");
+    error
+        .downcast::<ScanError>()
+        .expect("The error should be a ScanError")
+}
+
+/// The emulated subgroups take every size, so the scan is checked for all of them, with
+/// subgroups larger than the workgroup, which do not divide its size, and subgroups covering
+/// several whole workgroups of elements.
+#[test]
+fn emulated_subgroup_sizes() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    for size in sizes() {
+        for (workgroup_size, elems_per_thread, kind) in [
+            (16, 1, ScanKind::Inclusive),
+            (16, 3, ScanKind::Exclusive),
+            (64, 1, ScanKind::Exclusive),
+            (256, 4, ScanKind::Inclusive),
+        ] {
+            let config = ScanConfig::default()
+                .with_kind(kind)
+                .with_workgroup_size(workgroup_size)
+                .with_elems_per_thread(elems_per_thread)
+                .with_fused_carry(elems_per_thread > 1)
+                .with_out_of_place(kind == ScanKind::Exclusive)
+                .with_subgroup_sizes(SubgroupSizes::exact(size));
+            check_context(&gpu, &config);
+        }
+    }
+}
+
+/// The subgroup sizes of the adapter, compiled in if there is a single one, and the sizes
+/// the workgroup memory is sized for if the device may run any of them.
+#[test]
+fn native_subgroup_sizes() {
+    let Some(gpu) = Gpu::new(wgpu::Features::SUBGROUP) else {
+        return;
+    };
+    if !gpu.has_subgroups() {
+        eprintln!("Skipping the test: the adapter has no subgroup operations");
+        return;
+    }
+    let adapter_sizes = SubgroupSizes::of_adapter(&gpu.adapter.get_info());
+    for sizes in [adapter_sizes, SubgroupSizes::ALL] {
+        for workgroup_size in [16, 128] {
+            let config = ScanConfig::default()
+                .with_workgroup_size(workgroup_size)
+                .with_subgroup_sizes(sizes);
+            check_context(&gpu, &config);
+        }
+    }
+}
+
+#[test]
+fn invalid_subgroup_sizes_are_rejected() {
+    let Some(gpu) = Gpu::new(wgpu::Features::SUBGROUP) else {
+        return;
+    };
+    for sizes in [
+        SubgroupSizes::exact(2),
+        SubgroupSizes::exact(24),
+        SubgroupSizes::exact(256),
+        SubgroupSizes { min: 64, max: 32 },
+    ] {
+        let config = ScanConfig::default().with_subgroup_sizes(sizes);
+        assert!(matches!(
+            creation_error(&gpu, &config),
+            ScanError::InvalidSubgroupSizes { .. }
+        ));
+    }
+}
+
+/// A context creating its own device checks the sizes against its adapter.
+#[test]
+fn uncovered_subgroup_sizes_are_rejected() {
+    let Some(gpu) = Gpu::new(wgpu::Features::SUBGROUP) else {
+        return;
+    };
+    if !gpu.has_subgroups() {
+        eprintln!("Skipping the test: the adapter has no subgroup operations");
+        return;
+    }
+    let adapter_sizes = SubgroupSizes::of_adapter(&gpu.adapter.get_info());
+    // A single size other than the only one of the adapter
+    let size = if adapter_sizes == SubgroupSizes::exact(128) {
+        4
+    } else {
+        128
+    };
+    let config = ScanConfig::default()
+        .with_subgroup_sizes(SubgroupSizes::exact(size))
+        .with_gpu(gpu_options());
+    let error = pollster::block_on(SubgroupScanGpuContext::<u32>::with_config(16, config))
+        .err()
+        .expect("The context creation should fail");
+    assert!(matches!(
+        error.downcast_ref::<ScanError>(),
+        Some(ScanError::UncoveredSubgroupSizes { .. })
+    ));
+
+    let config = ScanConfig::default()
+        .with_subgroup_sizes(SubgroupSizes::ALL)
+        .with_gpu(gpu_options());
+    pollster::block_on(SubgroupScanGpuContext::<u32>::with_config(16, config)).unwrap();
+}
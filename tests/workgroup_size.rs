@@ -0,0 +1,89 @@
+mod common;
+
+use common::{Gpu, check_context, input};
+use wgpu_prefix_sum_demo::block_blelloch_scan::BlockBlellochGpuContext;
+use wgpu_prefix_sum_demo::chunked_scan::ChunkedScan;
+use wgpu_prefix_sum_demo::cpu_prefix_scan::cpu_prefix_scan;
+use wgpu_prefix_sum_demo::decoupled_lookback_scan::DecoupledLookbackGpuContext;
+use wgpu_prefix_sum_demo::global_blelloch_scan::GlobalBlellochGpuContext;
+use wgpu_prefix_sum_demo::hillis_steele_scan::HillisSteeleGpuContext;
+use wgpu_prefix_sum_demo::prefix_scan::{PrefixScan, ScanConfig};
+use wgpu_prefix_sum_demo::reduce_then_scan::ReduceThenScanGpuContext;
+use wgpu_prefix_sum_demo::scan_op::ScanOp;
+use wgpu_prefix_sum_demo::subgroup_scan::SubgroupScanGpuContext;
+
+/// Small workgroups, so a few thousand elements already need three levels, and the largest ones
+/// every device supports.
+const WG_SIZES: [u32; 2] = [32, 256];
+
+/// Check the context with every workgroup size, whose blocks are as long as the workgroups.
+fn check_workgroup_sizes<C: PrefixScan<u32>>(gpu: &Gpu) {
+    for wg_size in WG_SIZES {
+        let config = ScanConfig::default().with_workgroup_size(wg_size);
+        check_context::<C>(gpu, config, wg_size as usize);
+    }
+}
+
+#[test]
+fn hillis_steele_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_workgroup_sizes::<HillisSteeleGpuContext>(&gpu);
+}
+
+#[test]
+fn global_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_workgroup_sizes::<GlobalBlellochGpuContext>(&gpu);
+}
+
+#[test]
+fn block_blelloch_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_workgroup_sizes::<BlockBlellochGpuContext>(&gpu);
+}
+
+#[test]
+fn subgroup_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::SUBGROUP) else {
+        return;
+    };
+    check_workgroup_sizes::<SubgroupScanGpuContext>(&gpu);
+}
+
+#[test]
+fn reduce_then_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_workgroup_sizes::<ReduceThenScanGpuContext>(&gpu);
+}
+
+#[test]
+fn decoupled_lookback_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    check_workgroup_sizes::<DecoupledLookbackGpuContext>(&gpu);
+}
+
+/// The workgroup size applies to the inner scan and to the carries between the chunks.
+#[test]
+fn chunked_scan() {
+    let Some(gpu) = Gpu::new(wgpu::Features::empty()) else {
+        return;
+    };
+    let data = input(10_000);
+    let expected = cpu_prefix_scan(&data, ScanOp::Add).unwrap();
+    for wg_size in WG_SIZES {
+        let config = ScanConfig::default().with_workgroup_size(wg_size);
+        let chunked =
+            ChunkedScan::<u32>::from_device(&gpu.device, &gpu.queue, 1000, &config).unwrap();
+        assert!(chunked.scan_slice(&data).unwrap() == expected);
+    }
+}